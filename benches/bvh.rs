@@ -0,0 +1,79 @@
+// BVHと線形探索の速さ比べ. cargo bench --bench bvh で動かす
+// 部屋の中からランダムな向きに飛ばしたレイで, 何回か測った最短の時間を比べる
+// 物体が少なく葉1つで済むシーン (smallpt の球, モデル0) は線形探索と同じ手間になり, 比は1前後
+use rust_smallpt::*;
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::hint::black_box;
+use std::time::Instant;
+
+const RAYS: usize = 200_000;
+const RUNS: usize = 15;
+
+// 元の smallpt の, 壁も1e5の球でできた9個の球のコーネルボックス
+fn smallpt_spheres() -> Scene {
+    let mut scene = Scene::init();
+    let z = Vec3::zero();
+    let g = Vec3::new(0.75, 0.75, 0.75);
+    let w = Vec3::new(1.0, 1.0, 1.0) * 0.999;
+    scene.add(Sphere::new(1e5, Vec3::new(1e5 + 1.0, 40.8, 81.6), z, Vec3::new(0.75, 0.25, 0.25), Refl::Diff));
+    scene.add(Sphere::new(1e5, Vec3::new(-1e5 + 99.0, 40.8, 81.6), z, Vec3::new(0.25, 0.25, 0.75), Refl::Diff));
+    scene.add(Sphere::new(1e5, Vec3::new(50.0, 40.8, 1e5), z, g, Refl::Diff));
+    scene.add(Sphere::new(1e5, Vec3::new(50.0, 40.8, -1e5 + 170.0), z, z, Refl::Diff));
+    scene.add(Sphere::new(1e5, Vec3::new(50.0, 1e5, 81.6), z, g, Refl::Diff));
+    scene.add(Sphere::new(1e5, Vec3::new(50.0, -1e5 + 81.6, 81.6), z, g, Refl::Diff));
+    scene.add(Sphere::new(16.5, Vec3::new(27.0, 16.5, 47.0), z, w, Refl::Spec));
+    scene.add(Sphere::new(16.5, Vec3::new(73.0, 16.5, 78.0), z, w, Refl::Refr));
+    scene.add(Sphere::new(600.0, Vec3::new(50.0, 681.6 - 0.27, 81.6), Vec3::new(12.0, 12.0, 12.0), z, Refl::Diff));
+    scene
+}
+
+fn time(scene: &Scene, rays: &[Ray]) -> f64 {
+    let start = Instant::now();
+    let mut sum = 0.0;
+    for r in rays {
+        if let Some(h) = scene.intersect(r) {
+            sum += h.t;
+        }
+    }
+    black_box(sum);
+    start.elapsed().as_secs_f64()
+}
+
+fn bench(name: &str, mut scene: Scene) {
+    let mut rng = StdRng::seed_from_u64(1);
+    let rays: Vec<Ray> = (0..RAYS)
+        .map(|_| {
+            let o = Vec3::new(rng.random_range(5.0..95.0), rng.random_range(5.0..80.0), rng.random_range(5.0..165.0));
+            let d = Vec3::new(rng.random::<f64>() - 0.5, rng.random::<f64>() - 0.5, rng.random::<f64>() - 0.5);
+            Ray::new(o, d.norm())
+        })
+        .collect();
+    // 交互に測って, 途中でクロックが変わっても両方に同じように効くようにする
+    let (mut linear, mut bvh) = (f64::INFINITY, f64::INFINITY);
+    for _ in 0..RUNS {
+        scene.bvh = None;
+        linear = linear.min(time(&scene, &rays));
+        scene.build_bvh(DEFAULT_LEAF_SIZE);
+        bvh = bvh.min(time(&scene, &rays));
+    }
+    println!(
+        "{:<16} linear {:8.2}ms  bvh {:8.2}ms  bvh/linear {:.3}",
+        name,
+        linear * 1e3,
+        bvh * 1e3,
+        bvh / linear
+    );
+}
+
+fn main() {
+    bench("smallpt spheres", smallpt_spheres());
+    for n in [0, 3, 9] {
+        let scene = load_builtin_scene(n).unwrap().unwrap().0;
+        bench(&format!("model {}", n), scene);
+    }
+    let mut scene = Scene::init();
+    scene.model_init13();
+    bench("model 13 (CSG)", scene);
+}
@@ -0,0 +1,4 @@
+// パストレーサ本体. コマンドライン (main.rs) もこれを使う
+// SceneBuilder でシーンを組み立て, Renderer で描く
+pub mod raymod;
+pub use raymod::*;
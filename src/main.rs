@@ -1,156 +1,150 @@
-mod raymod;
-use raymod::*;
+mod optarg;
+use optarg::*;
+use rust_smallpt::*;
 
-use rayon::prelude::*;
-use std::f64::consts::*;
-use std::io::Write;
 use std::time::*;
 
-fn radiance(r: &Ray, depth: u8,scene:&Scene) -> Vec3 {
-    let ir=scene.intersect(r);
-    if ir.b==false {
-        return Vec3::zero();
-    }
-    let obj = &scene.objects[ir.id];
-    let x = r.o + r.d * ir.t;
-    let n = (x - obj.p).norm();
-    let nl = if n.dot(&r.d) < 0.0 { n } else { n * -1.0 };
-    let mut f = obj.c;
-    let p = f64::max(f.x,f64::max(f.y,f.z));
-    let depth = depth + 1;
-    if depth > 5 {
-        if random() < p {
-            f = f * (1.0 / p);
-        } else {
-            return obj.e ;
-        }
-    }
-
-    return match obj.refl {
-        Refl::Diff => {
-            let r1 = 2.0 * PI * random();
-            let r2 = random();
-            let r2s = r2.sqrt();
-            let w = nl;
-            let u = ((
-                if w.x.abs() > 0.1 {Vec3::new(0.0, 1.0, 0.0)
-                } else if w.z.abs()>0.1  { Vec3::new(1.0, 0.0, 0.0) }
-                else {Vec3::new(0.0,0.0,1.0)}) % w).norm();
-            let v = w % u;
-            let d = (u * f64::cos(r1) * r2s + v * f64::sin(r1) * r2s + w * (1.0 - r2).sqrt()).norm();
-            obj.e  + f.mult(&radiance(&Ray::new(x, d), depth,scene))
-        }
-        Refl::Spec => {
-            obj.e + f.mult(&radiance( &Ray::new(x, r.d - n * 2.0 * n.dot(&r.d)), depth,scene))
-        }
-        _ => {
-            // Refl.Refr
-            let refl_ray = Ray::new(x, r.d - n * 2.0 * n.dot(&r.d));
-            let into = n.dot(&nl) > 0.0;
-            let nc = 1.0;
-            let nt = 1.5;
-            let nnt = if into { nc / nt } else { nt / nc };
-            let ddn = r.d.dot(&nl);
-            let cos2t = 1.0 - nnt * nnt * (1.0 - ddn * ddn);
-            if cos2t < 0.0 {
-                obj.e + f.mult(&radiance(&refl_ray, depth,scene))
-            } else {
-                let tdir =
-                    r.d * nnt - n * ((if into { 1.0 } else { -1.0 }) * (ddn * nnt + cos2t.sqrt()));
-                tdir.norm();
-                let a = nt - nc;
-                let b = nt + nc;
-                let r0 = a * a / (b * b);
-                let c = 1.0 - (if into { -ddn } else { tdir.dot(&n) });
-                let re = r0 + (1.0 - r0) * c * c * c * c * c;
-                let tr = 1.0 - re;
-                let p = 0.25 + 0.5 * re;
-                let rp = re / p;
-                let tp = tr / (1.0 - p);
-                obj.e
-                    + f.mult(
-                        &(if depth > 2 {
-                            if random() < p {
-                                radiance(&refl_ray, depth, scene) * rp
-                            } else {
-                                radiance(&Ray::new(x, tdir), depth,scene) * tp
-                            }
-                        } else {
-                            radiance(&refl_ray, depth, scene) * re
-                                + radiance(&Ray::new(x, tdir), depth,scene) * tr
-                        }),
-                    )
-            }
-        }
-    };
-}
-
 fn main() {
 
     let args = parameters();
     println!("{:?}", args);
     
 	let mut scene=Scene::init();
-    match args.m{
-        0=> scene.model_init0(),
-        1=> scene.model_init1(),
-        2=> scene.model_init2(),
-        3=> scene.model_init3(),
-        4=> scene.model_init4(),
-        5=> scene.model_init5(),
-        6=> scene.model_init6(),
-        7=> scene.model_init7(),
-        8=> scene.model_init8(),
-        9=> scene.model_init9(),
-        _=> scene.model_init0(),
+    let mut settings = RenderSettings::default();
+    let loaded = match &args.scene {
+        Some(f) => {
+            let path = std::path::Path::new(f);
+            // .pbrt は pbrt-v3, .gltf と .glb は glTF 2.0 の形式. 読めなかったものは警告を出して続ける
+            let ext = path.extension().map(|e| e.to_string_lossy().to_lowercase()).unwrap_or_default();
+            let imported = match ext.as_str() {
+                "pbrt" => Some(load_pbrt_file(path)),
+                "gltf" | "glb" => Some(load_gltf_scene(path)),
+                _ => None,
+            };
+            Some(match imported {
+                Some(r) => r.map(|(s, st, warnings)| {
+                    for w in warnings {
+                        eprintln!("warning: {}", w);
+                    }
+                    (s, st)
+                }),
+                None => load_scene_file(path),
+            })
+        }
+        // -m 0〜9 は scenes/ のシーンファイル. 知らない番号はモデル0にする
+        None if args.m > 16 => load_builtin_scene(0),
+        None => load_builtin_scene(args.m),
     };
+    match loaded {
+        Some(Ok((s, st))) => {
+            scene = s;
+            settings = st;
+        }
+        Some(Err(e)) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+        None => {
+            match args.m{
+                10=> scene.model_init10(),
+                11=> scene.model_init11(),
+                12=> scene.model_init12(),
+                13=> scene.model_init13(),
+                14=> scene.model_init14(),
+                15=> {
+                    let map = match &args.heightmap {
+                        Some(f) => HeightMap::load(std::path::Path::new(f)).unwrap_or_else(|e| {
+                            eprintln!("{}", e);
+                            std::process::exit(1);
+                        }),
+                        None => HeightMap::noise(256, 256, 6, 1),
+                    };
+                    scene.model_init15(&map)
+                }
+                _=> scene.model_init16(),
+            };
+        }
+    }
+    for f in args.meshes.iter() {
+        match load_mesh_file(std::path::Path::new(f)) {
+            Ok(objects) => {
+                for o in objects {
+                    println!("-> {}: {} ({} triangles)", f, o.name, o.mesh.indices.len());
+                    scene.add_mesh(o.mesh);
+                }
+            }
+            Err(e) => {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+        }
+    }
 
-	
-    let w: usize = args.w;
-    let h: usize = (480.0*w as f64/640.0) as usize;
-    let samps = args.s;
-
-    let cam = Ray::new(
-        Vec3::new(50.0, 52.0, 295.6),
-        Vec3::new(0.0, -0.042612, -1.0).norm(),
-    );
+    let start = Instant::now();
+    if !args.linear {
+        scene.build_bvh(args.leaf_size.or(settings.leaf_size).unwrap_or(DEFAULT_LEAF_SIZE));
+    }
+    if args.bvh_stats {
+        if let Some(bvh) = &scene.bvh {
+            let st = bvh.stats();
+            println!("-> BVH: {} objects, {:.4}s", scene.objects.len(), start.elapsed().as_secs_f64());
+            if bvh.is_flat() {
+                println!("   no tree: all {} objects are tested one by one", st.primitives + st.unbounded);
+            } else {
+                println!("   nodes: {}  leaves: {}  depth: {}  max leaf: {}  outside tree: {}", st.nodes, st.leaves, st.max_depth, st.max_leaf_size, st.unbounded);
+                println!("   SAH cost: {:.4}", st.sah_cost);
+            }
+        }
+    }
 
-    let cx = Vec3::new((w as f64) * 0.5135 / (h as f64), 0.0, 0.0);
-    let cy = (cx % cam.d).norm() * 0.5135;
-    let mut image = vec![Color::zero(); (w * h) as usize];
 
-    println!("-> 処理を開始します...");
-    let start = Instant::now();
-    
-    let bands: Vec<(usize, &mut [Color])> = image.chunks_mut(w as usize).enumerate().collect();
-    bands.into_par_iter().for_each(|(y, band)| {
-        let y2 = h - (y as usize) - 1;
-        if (y % 10) == 0 {
-            writeln!( std::io::stderr(), "Rendering ({} spp) {:5.2}%", samps * 4,100.0 * (y as f64) / ((h as f64) - 1.0) ).unwrap();
+    args.apply_camera(&mut scene.camera);
+    if let Some(f) = &args.bokeh {
+        match BokehMask::load(std::path::Path::new(f)) {
+            Ok(mask) => scene.camera.lens = Aperture::Mask(std::sync::Arc::new(mask)),
+            Err(e) => {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
         }
-        for x in 0..w {
-            let mut r = Vec3::zero();
-            for sy in 0..2 {
-                for sx in 0..2 {
-                    for _s in 0..samps {
-                        let r1 = 2.0 * random();
-                        let dx = if r1 < 1.0 { r1.sqrt() - 1.0 } else { 1.0 - (2.0 - r1).sqrt() };
-                        let r2 = 2.0 * random();
-                        let dy = if r2 < 1.0 { r2.sqrt() - 1.0 } else { 1.0 - (2.0 - r2).sqrt() };
-                        let d = cx * ((((sx as f64) + 0.5 + dx) / 2.0 + (x as f64)) / (w as f64) - 0.5)
-                              + cy * ((((sy as f64) + 0.5 + dy) / 2.0 + (y2 as f64)) / (h as f64)- 0.5)
-                              + cam.d;
-                        r = r + radiance(&(Ray::new(cam.o + d * 140.0, d.norm())), 0, &scene) * (1.0 / (samps as f64));
-                    }
-                    band[x as usize] = band[x as usize] + r * (1.0 / 4.0 as f64);
-                    r = Vec3::zero();
-                }
+    }
+    if let Some((px, py)) = args.autofocus {
+        match scene.camera.autofocus(&scene, px, py) {
+            Some(d) => {
+                println!("-> autofocus ({}, {}): {:.3}", px, py, d);
+                scene.camera.focus_dist = Some(d);
             }
+            None => eprintln!("-> autofocus ({}, {}): nothing there, focus is unchanged", px, py),
+        }
+    }
+    // コマンドラインの指定をシーンファイルの設定より優先する
+    let settings = RenderSettings {
+        samples: args.s.or(settings.samples),
+        output: args.output.clone().or(settings.output),
+        leaf_size: args.leaf_size.or(settings.leaf_size),
+    };
+    if let Some(f) = &args.dump_scene {
+        // コマンドラインで上書きしたカメラと描画の設定もファイルに残す
+        if let Aperture::Mask(_) = scene.camera.lens {
+            eprintln!("-> the bokeh image can't be written to a scene file, the aperture becomes a disk");
         }
-    });
+        if let Err(e) = std::fs::write(f, dump_scene(&scene, &settings)) {
+            eprintln!("{}: {}", f, e);
+            std::process::exit(1);
+        }
+        println!("-> wrote {}", f);
+        return;
+    }
+
+    println!("-> 処理を開始します...");
+    let start = Instant::now();
+    let image = Renderer::new().with_progress(true).render(&scene, &settings);
     println!("-> 処理を終了しました...");
     let duration = start.elapsed();
     println!("   秒: {:.4}s", duration.as_secs_f64());
-    //    save_ppm_file("image.ppm", image, w, h);
-    save_png_file(&args.output, image, w, h);
+    let output = settings.output.as_deref().unwrap_or("image.png");
+    if let Err(e) = image.save(std::path::Path::new(output)) {
+        eprintln!("{}: {}", output, e);
+        std::process::exit(1);
+    }
 }
@@ -0,0 +1,273 @@
+use rust_smallpt::*;
+use getopts::Options;
+use std::process;
+
+// 引数を格納する構造体
+#[derive(Debug)]
+pub struct Args {
+    //   repeat: usize,
+    pub s:Option<usize>,
+    pub w:Option<usize>,
+    pub height:Option<usize>,
+    pub m:usize,
+    pub output: Option<String>,
+    pub leaf_size:Option<usize>,
+    pub bvh_stats:bool,
+    pub linear:bool,
+    pub meshes: Vec<String>,
+    pub heightmap: Option<String>,
+    pub scene: Option<String>,
+    pub dump_scene: Option<String>,
+    pub camera_pos: Option<Vec3>,
+    pub look_at: Option<Vec3>,
+    pub up: Option<Vec3>,
+    pub fov: Option<f64>,
+    pub near: Option<f64>,
+    pub aperture: Option<f64>,
+    pub focus: Option<f64>,
+    pub autofocus: Option<(usize, usize)>,
+    pub blades: Option<usize>,
+    pub bokeh: Option<String>,
+    pub projection: Option<Projection>,
+    pub stereo: Option<StereoLayout>,
+    pub ipd: Option<f64>,
+    pub convergence: Option<f64>,
+    pub shutter: Option<(f64, f64)>,
+}
+
+impl Args {
+    // シーンのカメラをコマンドラインの指定で上書きする
+    // 幅だけ指定されたときはシーンの縦横比を保つ
+    pub fn apply_camera(&self, cam: &mut Camera) {
+        if let Some(p) = self.camera_pos {
+            cam.pos = p;
+        }
+        if let Some(p) = self.look_at {
+            cam.look_at = p;
+        }
+        if let Some(u) = self.up {
+            cam.up = u;
+        }
+        if let Some(f) = self.fov {
+            cam.vfov = f;
+        }
+        if let Some(n) = self.near {
+            cam.near = n;
+        }
+        if let Some(a) = self.aperture {
+            cam.aperture = a;
+        }
+        if let Some(f) = self.focus {
+            cam.focus_dist = Some(f);
+        }
+        if let Some(p) = self.projection {
+            cam.projection = p;
+        }
+        if let Some(layout) = self.stereo {
+            let st = cam.stereo.get_or_insert(Stereo {
+                ipd: DEFAULT_IPD,
+                convergence: None,
+                layout,
+            });
+            st.layout = layout;
+        }
+        if let Some(st) = cam.stereo.as_mut() {
+            if let Some(d) = self.ipd {
+                st.ipd = d;
+            }
+            if let Some(c) = self.convergence {
+                st.convergence = Some(c);
+            }
+        }
+        if let Some(sh) = self.shutter {
+            cam.shutter = sh;
+        }
+        if let Some(n) = self.blades {
+            cam.lens = Aperture::polygon(n);
+        }
+        match (self.w, self.height) {
+            (Some(w), Some(h)) => {
+                cam.width = w;
+                cam.height = h;
+            }
+            (Some(w), None) => {
+                cam.height = (cam.height as f64 * w as f64 / cam.width as f64) as usize;
+                cam.width = w;
+            }
+            (None, Some(h)) => {
+                cam.width = (cam.width as f64 * h as f64 / cam.height as f64) as usize;
+                cam.height = h;
+            }
+            (None, None) => {}
+        }
+    }
+}
+
+// 読めなかった値は --blades と同じようにメッセージを出して終了する
+fn or_exit<T>(opt: &str, r: Result<T, String>) -> T {
+    r.unwrap_or_else(|e| {
+        eprintln!("--{}: {}", opt, e);
+        process::exit(1);
+    })
+}
+
+// "x,y,z" を Vec3 に
+fn parse_vec3(s: &str) -> Result<Vec3, String> {
+    let v = s
+        .split(',')
+        .map(|x| x.trim().parse().map_err(|_| format!("bad number '{}' in '{}'", x, s)))
+        .collect::<Result<Vec<f64>, String>>()?;
+    if v.len() != 3 {
+        return Err(format!("expected x,y,z but got '{}'", s));
+    }
+    Ok(Vec3::new(v[0], v[1], v[2]))
+}
+
+// "open,close" の時刻
+fn parse_pair(s: &str) -> Result<(f64, f64), String> {
+    let v = s
+        .split(',')
+        .map(|x| x.trim().parse().map_err(|_| format!("bad number '{}' in '{}'", x, s)))
+        .collect::<Result<Vec<f64>, String>>()?;
+    if v.len() != 2 {
+        return Err(format!("expected open,close but got '{}'", s));
+    }
+    Ok((v[0], v[1]))
+}
+
+// "x,y" の画素位置
+fn parse_pixel(s: &str) -> Result<(usize, usize), String> {
+    let v = s
+        .split(',')
+        .map(|x| x.trim().parse().map_err(|_| format!("bad pixel '{}' in '{}'", x, s)))
+        .collect::<Result<Vec<usize>, String>>()?;
+    if v.len() != 2 {
+        return Err(format!("expected x,y but got '{}'", s));
+    }
+    Ok((v[0], v[1]))
+}
+
+fn print_usage(exe_name: &str, opts: &Options) {
+    let brief = format!("Usage: {}  [Options]", exe_name);
+    print!("{}", opts.usage(&brief));
+    process::exit(0);
+}
+
+pub fn parameters() -> Args {
+    // コマンドラインオプションを取得
+    let args: Vec<String> = std::env::args().collect();
+
+    // キーワード引数を指定
+    let mut opts = Options::new();
+    opts.optopt("s", "samples", "sampling number", "1..etc");
+    opts.optopt("w","width","screen width","ex)640");
+    opts.optopt("", "height", "screen height (default keeps the scene's aspect)", "ex)480");
+    opts.optopt("m","model","model number","0..16");
+    opts.optopt("o", "output", "set output file name (default image.png)", "[FILE]");
+    opts.optopt("l", "leaf-size", "max primitives per BVH leaf", "4");
+    opts.optflag("b", "bvh-stats", "print BVH build statistics");
+    opts.optflag("", "linear", "intersect objects one by one without the BVH");
+    opts.optmulti("i", "mesh", "add a mesh file (.obj .ply .stl .gltf .glb) to the scene", "[FILE]");
+    opts.optopt("", "scene", "load the scene from a TOML, pbrt-v3 (.pbrt) or glTF (.gltf .glb) file instead of a model", "[FILE]");
+    opts.optopt("", "dump-scene", "write the scene as a TOML scene file and exit without rendering", "[FILE]");
+    opts.optopt("", "heightmap", "grayscale image for the terrain of model 15", "[FILE]");
+    opts.optopt("", "camera", "camera position", "x,y,z");
+    opts.optopt("", "look-at", "point the camera looks at", "x,y,z");
+    opts.optopt("", "up", "camera up vector", "x,y,z");
+    opts.optopt("", "fov", "vertical field of view in degrees", "28.8");
+    opts.optopt("", "near", "skip everything closer than this to the camera", "140");
+    opts.optopt("", "aperture", "lens radius (0 is a pinhole)", "0");
+    opts.optopt("", "focus", "focus distance along the view direction", "220");
+    opts.optopt("", "autofocus", "focus on the object seen at this pixel", "x,y");
+    opts.optopt("", "blades", "polygonal aperture with this many blades", "6");
+    opts.optopt("", "bokeh", "grayscale image giving the aperture shape", "[FILE]");
+    opts.optopt("", "projection", "perspective, ortho:HEIGHT, fisheye:FOV or equirect", "perspective");
+    opts.optopt("", "stereo", "render both eyes side by side or top and bottom", "sbs|tb");
+    opts.optopt("", "ipd", "distance between the eyes", "6.5");
+    opts.optopt("", "convergence", "distance where both eyes meet (inf for parallel)", "[DIST]");
+    opts.optopt("", "shutter", "times the shutter opens and closes", "open,close");
+    opts.optflag("h", "help", "print this help");
+
+    // パース
+    let matches = opts.parse(&args[1..])
+        .unwrap_or_else(|f| panic!("{}",f.to_string()));
+
+    // "h" が存在したらヘルプを表示して終了
+    if matches.opt_present("h") {
+        print_usage(&args[0], &opts);
+    }
+
+    // 必要な位置引数がなかったらヘルプを表示して終了
+//    if matches.free.is_empty() {print_usage(&args[0], &opts); }
+
+    // キーワード引数の取得
+    let s = matches.opt_str("s").map(|s| s.parse().unwrap());
+    let w  = matches.opt_str("w").map(|w| w.parse().unwrap());
+    let height = matches.opt_str("height").map(|h| h.parse().unwrap());
+    let m  = matches.opt_str("m").unwrap_or("0".to_string()).parse().unwrap();
+    let output = matches.opt_str("o");
+    let leaf_size = matches.opt_str("l").map(|l| l.parse().unwrap());
+    let bvh_stats = matches.opt_present("b");
+    let linear = matches.opt_present("linear");
+    let meshes = matches.opt_strs("i");
+    let heightmap = matches.opt_str("heightmap");
+    let scene = matches.opt_str("scene");
+    let dump_scene = matches.opt_str("dump-scene");
+    let camera_pos = matches.opt_str("camera").map(|s| or_exit("camera", parse_vec3(&s)));
+    let look_at = matches.opt_str("look-at").map(|s| or_exit("look-at", parse_vec3(&s)));
+    let up = matches.opt_str("up").map(|s| or_exit("up", parse_vec3(&s)));
+    let fov = matches.opt_str("fov").map(|f| f.parse().unwrap());
+    let near = matches.opt_str("near").map(|n| n.parse().unwrap());
+    let aperture = matches.opt_str("aperture").map(|a| a.parse().unwrap());
+    let focus = matches.opt_str("focus").map(|f| f.parse().unwrap());
+    let autofocus = matches.opt_str("autofocus").map(|s| or_exit("autofocus", parse_pixel(&s)));
+    let blades = matches.opt_str("blades").map(|b| b.parse().unwrap());
+    if blades.is_some_and(|n: usize| n < 3) {
+        eprintln!("--blades: an aperture needs at least 3 blades");
+        process::exit(1);
+    }
+    let bokeh = matches.opt_str("bokeh");
+    let stereo = matches.opt_str("stereo").map(|l| or_exit("stereo", l.parse()));
+    let ipd = matches.opt_str("ipd").map(|d| d.parse().unwrap());
+    let convergence = matches.opt_str("convergence").map(|c| c.parse().unwrap());
+    let shutter = matches.opt_str("shutter").map(|s| or_exit("shutter", parse_pair(&s)));
+    let projection = matches.opt_str("projection").map(|p| or_exit("projection", p.parse()));
+    // 位置引数の取得
+//    let repeat = matches.free[0].clone().parse::<usize>().unwrap_or_else(|f| panic!("{}",f.to_string()));
+
+    // 構造体の生成
+    Args {
+        s,
+        w,
+        height,
+        m,
+        output,
+        leaf_size,
+        bvh_stats,
+        linear,
+        meshes,
+        heightmap,
+        scene,
+        dump_scene,
+        camera_pos,
+        look_at,
+        up,
+        fov,
+        near,
+        aperture,
+        focus,
+        autofocus,
+        blades,
+        bokeh,
+        projection,
+        stereo,
+        ipd,
+        convergence,
+        shutter,
+    }
+}
+#[allow(dead_code)]
+fn test() {
+    let args = parameters();
+    println!("{:?}", args);
+}
@@ -0,0 +1,104 @@
+use crate::raymod::*;
+
+use std::f64::consts::PI;
+use std::path::Path;
+use std::sync::Arc;
+
+// レンズの開口の形. sample は半径1に収まる点を返す (ボケの形になる)
+#[derive(Clone, Debug)]
+pub enum Aperture {
+    // 丸い絞り
+    Disk,
+    // 羽根 blades 枚の正多角形. rotation は最初の頂点の角度(ラジアン)
+    Polygon { blades: usize, rotation: f64 },
+    // 画像で与えた形. 明るいところほど多く通す
+    Mask(Arc<BokehMask>),
+}
+
+impl Aperture {
+    // 羽根が3枚より少ないと形にならないので3枚にする. 指定の誤りは読むところで知らせる
+    pub fn polygon(blades: usize) -> Aperture {
+        Aperture::Polygon {
+            blades: blades.max(3),
+            rotation: PI * 0.5,
+        }
+    }
+
+    pub fn sample(&self) -> (f64, f64) {
+        match self {
+            Aperture::Disk => {
+                // 同心円写像
+                let (a, b) = (2.0 * random() - 1.0, 2.0 * random() - 1.0);
+                if a == 0.0 && b == 0.0 {
+                    return (0.0, 0.0);
+                }
+                let (r, phi) = if a.abs() > b.abs() {
+                    (a, PI / 4.0 * (b / a))
+                } else {
+                    (b, PI / 2.0 - PI / 4.0 * (a / b))
+                };
+                (r * phi.cos(), r * phi.sin())
+            }
+            Aperture::Polygon { blades, rotation } => {
+                // 中心と隣り合う頂点2つの三角形はどれも同じ面積なので, 1つ選んで一様に取る
+                let k = ((random() * *blades as f64) as usize).min(blades - 1);
+                let step = 2.0 * PI / *blades as f64;
+                let a0 = rotation + step * k as f64;
+                let (p1, p2) = ((a0.cos(), a0.sin()), ((a0 + step).cos(), (a0 + step).sin()));
+                let (mut s, mut t) = (random(), random());
+                if s + t > 1.0 {
+                    s = 1.0 - s;
+                    t = 1.0 - t;
+                }
+                (p1.0 * s + p2.0 * t, p1.1 * s + p2.1 * t)
+            }
+            Aperture::Mask(mask) => mask.sample(),
+        }
+    }
+}
+
+// ボケの形を描いたグレースケール画像. 明るさに比例した確率で画素を選ぶ
+#[derive(Debug)]
+pub struct BokehMask {
+    w: usize,
+    h: usize,
+    // 画素の明るさの累積. 最後が1になるように正規化してある
+    cdf: Vec<f64>,
+}
+
+impl BokehMask {
+    pub fn load(path: &Path) -> Result<BokehMask, LoadError> {
+        let img = image::open(path).map_err(|e| LoadError::new(path, 0, e.to_string()))?;
+        let img = img.to_luma16();
+        let (w, h) = img.dimensions();
+        let mut cdf = Vec::with_capacity((w * h) as usize);
+        let mut sum = 0.0;
+        for p in img.pixels() {
+            sum += p.0[0] as f64 / 65535.0;
+            cdf.push(sum);
+        }
+        if sum <= 0.0 {
+            return Err(LoadError::new(path, 0, "bokeh mask is completely black".to_string()));
+        }
+        for c in cdf.iter_mut() {
+            *c /= sum;
+        }
+        Ok(BokehMask {
+            w: w as usize,
+            h: h as usize,
+            cdf,
+        })
+    }
+
+    // 画像の長い辺が直径2になるように, 中心を原点に置く
+    fn sample(&self) -> (f64, f64) {
+        let u = random();
+        let i = self.cdf.partition_point(|&c| c < u).min(self.cdf.len() - 1);
+        let (px, py) = (i % self.w, i / self.w);
+        let scale = 2.0 / self.w.max(self.h) as f64;
+        let x = (px as f64 + random() - self.w as f64 * 0.5) * scale;
+        // 画像は上から下へ並んでいるので y を反転する
+        let y = (self.h as f64 * 0.5 - py as f64 - random()) * scale;
+        (x, y)
+    }
+}
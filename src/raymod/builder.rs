@@ -0,0 +1,144 @@
+use crate::raymod::*;
+
+use std::collections::HashMap;
+use std::fmt;
+use std::path::Path;
+
+// コードからシーンを組み立てる. マテリアルは名前をつけて登録し, 物体からは名前で指す
+// 名前は使う前に登録する. 間違いは最初の1つを覚えておき, build で返す
+//
+//     let scene = SceneBuilder::new()
+//         .material("white", Material::new(Vec3::zero(), Vec3::new(0.75, 0.75, 0.75), Refl::Diff))
+//         .sphere(Vec3::new(0.0, 1.0, 0.0), 1.0, "white")
+//         .light(Vec3::new(0.0, 10.0, 0.0), 2.0, Vec3::new(20.0, 20.0, 20.0))
+//         .camera(camera)
+//         .build()?;
+//     let image = Renderer::new().render(&scene, &RenderSettings::default());
+pub struct SceneBuilder {
+    scene: Scene,
+    materials: HashMap<String, Material>,
+    leaf_size: usize,
+    error: Option<BuildError>,
+}
+
+#[derive(Debug)]
+pub enum BuildError {
+    UnknownMaterial(String),
+    Load(LoadError),
+}
+
+impl fmt::Display for BuildError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BuildError::UnknownMaterial(name) => write!(f, "unknown material '{}'", name),
+            BuildError::Load(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for BuildError {}
+
+impl Default for SceneBuilder {
+    fn default() -> SceneBuilder {
+        SceneBuilder::new()
+    }
+}
+
+impl SceneBuilder {
+    pub fn new() -> SceneBuilder {
+        SceneBuilder {
+            scene: Scene::init(),
+            materials: HashMap::new(),
+            leaf_size: DEFAULT_LEAF_SIZE,
+            error: None,
+        }
+    }
+
+    // 同じ名前で登録し直すと, それより後に足す物体から新しいものを使う
+    pub fn material(mut self, name: &str, m: Material) -> SceneBuilder {
+        self.materials.insert(name.to_string(), m);
+        self
+    }
+
+    pub fn camera(mut self, camera: Camera) -> SceneBuilder {
+        self.scene.camera = camera;
+        self
+    }
+
+    pub fn leaf_size(mut self, leaf_size: usize) -> SceneBuilder {
+        self.leaf_size = leaf_size;
+        self
+    }
+
+    fn lookup(&mut self, name: &str) -> Option<Material> {
+        let m = self.materials.get(name).copied();
+        if m.is_none() && self.error.is_none() {
+            self.error = Some(BuildError::UnknownMaterial(name.to_string()));
+        }
+        m
+    }
+
+    pub fn sphere(mut self, center: Vec3, radius: f64, material: &str) -> SceneBuilder {
+        if let Some(m) = self.lookup(material) {
+            self.scene.add(Sphere { rad: radius, p: center, m });
+        }
+        self
+    }
+
+    // 光る球. 色は黒で, 光を跳ね返さない
+    pub fn light(mut self, center: Vec3, radius: f64, emission: Vec3) -> SceneBuilder {
+        self.scene.add(Sphere::new(radius, center, emission, Vec3::zero(), Refl::Diff));
+        self
+    }
+
+    // メッシュのマテリアルを名前で指したものに置き換える
+    pub fn mesh(mut self, mut mesh: Mesh, material: &str) -> SceneBuilder {
+        if let Some(m) = self.lookup(material) {
+            mesh.m = m;
+            self.scene.add_mesh(mesh);
+        }
+        self
+    }
+
+    // メッシュファイルを読む. material が None ならファイルのマテリアルのまま
+    pub fn mesh_file(mut self, path: &Path, material: Option<&str>) -> SceneBuilder {
+        let m = match material {
+            Some(name) => match self.lookup(name) {
+                Some(m) => Some(m),
+                None => return self,
+            },
+            None => None,
+        };
+        match load_mesh_file(path) {
+            Ok(meshes) => {
+                for mut o in meshes {
+                    if let Some(m) = m {
+                        o.mesh.m = m;
+                    }
+                    self.scene.add_mesh(o.mesh);
+                }
+            }
+            Err(e) => {
+                if self.error.is_none() {
+                    self.error = Some(BuildError::Load(e));
+                }
+            }
+        }
+        self
+    }
+
+    // ほかの形はマテリアルごと作って足す
+    pub fn object<P: Into<Primitive>>(mut self, obj: P) -> SceneBuilder {
+        self.scene.add(obj);
+        self
+    }
+
+    // BVH を作って返す
+    pub fn build(mut self) -> Result<Scene, BuildError> {
+        if let Some(e) = self.error {
+            return Err(e);
+        }
+        self.scene.build_bvh(self.leaf_size);
+        Ok(self.scene)
+    }
+}
@@ -1,6 +1,5 @@
 use crate::raymod::*;
 
-use std::cmp::Ordering;
 use std::f64;
 
 
@@ -18,7 +17,8 @@ pub fn surrounding_box(box0: &AABB, box1: &AABB) -> AABB {
     AABB { min, max }
 }
 
-#[derive(Clone, Copy)]
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Clone, Copy, Debug)]
 pub struct AABB {
     pub min: Vec3,
     pub max: Vec3,
@@ -30,6 +30,63 @@ impl AABB {
         AABB { min, max }
     }
 
+    // 空の箱. surrounding_boxで何かと合わせると相手の箱になる
+    pub fn empty() -> Self {
+        AABB {
+            min: Vec3::new(INF, INF, INF),
+            max: Vec3::new(-INF, -INF, -INF),
+        }
+    }
+
+    pub fn centroid(&self) -> Vec3 {
+        (self.min + self.max) * 0.5
+    }
+
+    pub fn extent(&self) -> Vec3 {
+        self.max - self.min
+    }
+
+    pub fn surface_area(&self) -> f64 {
+        let d = self.extent();
+        if d.x < 0.0 || d.y < 0.0 || d.z < 0.0 {
+            return 0.0;
+        }
+        2.0 * (d.x * d.y + d.y * d.z + d.z * d.x)
+    }
+
+    pub fn longest_axis(&self) -> usize {
+        let d = self.extent();
+        if d.x > d.y && d.x > d.z {
+            0
+        } else if d.y > d.z {
+            1
+        } else {
+            2
+        }
+    }
+
+    // inv_dは事前に計算したレイ方向の逆数. 分岐なしで3軸まとめて調べる
+    #[inline]
+    pub fn hit_inv(&self, o: &Vec3, inv_d: &Vec3, t_min: f64, t_max: f64) -> bool {
+        #[inline(always)]
+        fn mn(a: f64, b: f64) -> f64 {
+            if a < b { a } else { b }
+        }
+        #[inline(always)]
+        fn mx(a: f64, b: f64) -> f64 {
+            if a > b { a } else { b }
+        }
+        let tx0 = (self.min.x - o.x) * inv_d.x;
+        let tx1 = (self.max.x - o.x) * inv_d.x;
+        let ty0 = (self.min.y - o.y) * inv_d.y;
+        let ty1 = (self.max.y - o.y) * inv_d.y;
+        let tz0 = (self.min.z - o.z) * inv_d.z;
+        let tz1 = (self.max.z - o.z) * inv_d.z;
+        let t0 = mx(mx(mx(mn(tx0, tx1), mn(ty0, ty1)), mn(tz0, tz1)), t_min);
+        let t1 = mn(mn(mn(mx(tx0, tx1), mx(ty0, ty1)), mx(tz0, tz1)), t_max);
+        t0 <= t1
+    }
+
     pub fn hit(&self, ray: &Ray, mut t_min: f64, mut t_max: f64) -> bool {
         for a in 0..3 {
             let inv_d = 1.0 / ray.d[a];
@@ -46,107 +103,515 @@ impl AABB {
     }
 }
 
-enum BVHNode {
-    Branch { left: Box<BVH>, right: Box<BVH> },
-    Leaf(Box<dyn Shape>),
+// SAHのコスト. 小さな球の交差判定1回を1としたときのノード巡回のコスト
+// 中間ノードでは子の箱を2つ調べ, これが球2回分ほどの重さになる
+// 形状ごとの重さは Shape::cost で, 球ばかりなら少数の物体は葉1つにまとまる
+const SAH_TRAVERSAL_COST: f64 = 2.0;
+const SAH_BINS: usize = 12;
+const STACK_SIZE: usize = 64;
+// これより深くなったら中央値で割って深さを抑える
+const MAX_SAH_DEPTH: usize = 32;
+// 1e5の壁のように全体の箱に対して大きすぎる物体は箱で間引けないので
+// 木に入れず毎回直接調べる. 取り除くと残りの箱が HUGE_SHRINK 分の1以下に
+// 縮む場合だけ取り除き, これを繰り返す. 残りの小さな物体は木でたどる
+const HUGE_AREA_RATIO: f64 = 0.1;
+const HUGE_SHRINK: f64 = 4.0;
+const MAX_HUGE: usize = 16;
+
+pub const DEFAULT_LEAF_SIZE: usize = 4;
+
+// 深さ優先で並べたノード. 左の子は常に直後(i+1)に置く
+// count==0 なら中間ノードで offset は右の子の位置
+// count>0 なら葉で offset から count 個が indices の範囲
+#[derive(Clone, Copy, Debug)]
+struct BVHNode {
+    bbox: AABB,
+    offset: u32,
+    count: u16,
+    axis: u8,
 }
 
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Debug)]
 pub struct BVH {
-    tree: BVHNode,
+    nodes: Vec<BVHNode>,
+    indices: Vec<usize>,
+    // 木の外に置いた, 箱を持たないか大きすぎる物体
+    // nodes が空のときは全部がここに元の順で入る
+    unbounded: Vec<usize>,
+}
+
+// 構築時のみ使う. プリミティブの番号とそのバウンディングボックス, 重心
+struct BuildItem {
+    index: usize,
     bbox: AABB,
+    centroid: Vec3,
+    cost: f64,
+}
+
+fn union_of(items: &[BuildItem]) -> AABB {
+    items
+        .iter()
+        .fold(AABB::empty(), |b, i| surrounding_box(&b, &i.bbox))
+}
+
+#[derive(Clone, Copy)]
+struct Bin {
+    count: usize,
+    cost: f64,
+    bbox: AABB,
+}
+
+#[derive(Debug, Default)]
+pub struct BVHStats {
+    pub nodes: usize,
+    pub leaves: usize,
+    pub primitives: usize,
+    pub unbounded: usize,
+    pub max_depth: usize,
+    pub max_leaf_size: usize,
+    pub sah_cost: f64,
 }
 
 impl BVH {
-    pub fn new(mut shape: Vec<Box<dyn Shape>>) -> Self {
-        fn box_compare(axis: usize) -> impl FnMut(&Box<dyn Shape>, &Box<dyn Shape>) -> Ordering {
-            move |a, b| {
-                let a_bbox = a.bounding_box();
-                let b_bbox = b.bounding_box();
-                if let (Some(a), Some(b)) = (a_bbox, b_bbox) {
-                    let ac = a.min[axis] + a.max[axis];
-                    let bc = b.min[axis] + b.max[axis];
-                    ac.partial_cmp(&bc).unwrap()
-                } else {
-                    panic!["no bounding box in bvh node"]
-                }
+    // prims の並びは変えない. 交差判定の時にも同じスライスを渡すこと
+    pub fn new<T: Shape>(prims: &[T], leaf_size: usize) -> Self {
+        let mut bvh = BVH {
+            nodes: Vec::with_capacity(2 * prims.len()),
+            indices: Vec::with_capacity(prims.len()),
+            unbounded: vec![],
+        };
+        let mut items: Vec<BuildItem> = vec![];
+        for (index, s) in prims.iter().enumerate() {
+            match s.bounding_box() {
+                Some(bbox) => items.push(BuildItem {
+                    index,
+                    bbox,
+                    centroid: bbox.centroid(),
+                    cost: s.cost(),
+                }),
+                None => bvh.unbounded.push(index),
+            }
+        }
+
+        loop {
+            let area = union_of(&items).surface_area();
+            let limit = area * HUGE_AREA_RATIO;
+            let huge = items.iter().filter(|i| i.bbox.surface_area() > limit).count();
+            // 1つしか残らないと縮み方で比べる意味がない (小さな光源1枚だけ残ると
+            // 部屋の中の球まで外に出てしまう). 木には2つ以上残す
+            if huge == 0 || huge + 2 > items.len() || bvh.unbounded.len() + huge > MAX_HUGE {
+                break;
+            }
+            let rest = items
+                .iter()
+                .filter(|i| i.bbox.surface_area() <= limit)
+                .fold(AABB::empty(), |b, i| surrounding_box(&b, &i.bbox));
+            if rest.surface_area() * HUGE_SHRINK > area {
+                break;
+            }
+            let (huge, rest): (Vec<BuildItem>, Vec<BuildItem>) = items
+                .into_iter()
+                .partition(|i| i.bbox.surface_area() > limit);
+            bvh.unbounded.extend(huge.iter().map(|i| i.index));
+            items = rest;
+        }
+
+        if !items.is_empty() {
+            bvh.build(items, leaf_size.clamp(1, u16::MAX as usize), 0);
+        }
+
+        bvh
+    }
+
+    fn push_leaf(&mut self, items: Vec<BuildItem>, bbox: AABB) {
+        self.nodes.push(BVHNode {
+            bbox,
+            offset: self.indices.len() as u32,
+            count: items.len() as u16,
+            axis: 0,
+        });
+        self.indices.extend(items.iter().map(|i| i.index));
+    }
+
+    fn build(&mut self, mut items: Vec<BuildItem>, leaf_size: usize, depth: usize) {
+        let bbox = union_of(&items);
+        let n = items.len();
+        if n == 1 {
+            return self.push_leaf(items, bbox);
+        }
+
+        // 重心の広がりが最も大きい軸で分割する
+        let cbox = items.iter().fold(AABB::empty(), |b, i| {
+            surrounding_box(&b, &AABB::new(i.centroid, i.centroid))
+        });
+        let axis = cbox.longest_axis();
+        let cmin = cbox.min[axis];
+        let cext = cbox.max[axis] - cmin;
+        if cext <= 0.0 {
+            // 全部同じ位置. 分けようがないので葉にするか半分に割る
+            if n <= leaf_size {
+                return self.push_leaf(items, bbox);
             }
+            let right = items.split_off(n / 2);
+            return self.push_branch(items, right, bbox, axis, leaf_size, depth);
+        }
+        if depth >= MAX_SAH_DEPTH {
+            items.sort_unstable_by(|a, b| a.centroid[axis].total_cmp(&b.centroid[axis]));
+            let right = items.split_off(n / 2);
+            return self.push_branch(items, right, bbox, axis, leaf_size, depth);
         }
 
-        let axis_random = random();
-        let axis: usize;
-        if axis_random < 0.33 {
-            axis = 0
-        } else if axis_random < 0.66 {
-            axis = 1
+        let bin_of = |c: &Vec3| -> usize {
+            let b = ((c[axis] - cmin) / cext * SAH_BINS as f64) as usize;
+            b.min(SAH_BINS - 1)
+        };
+
+        let empty = Bin { count: 0, cost: 0.0, bbox: AABB::empty() };
+        let mut bins = [empty; SAH_BINS];
+        for i in items.iter() {
+            let b = &mut bins[bin_of(&i.centroid)];
+            b.count += 1;
+            b.cost += i.cost;
+            b.bbox = surrounding_box(&b.bbox, &i.bbox);
+        }
+
+        // 左から累積したものと右から累積したもの
+        let mut left_area = [0.0; SAH_BINS];
+        let mut left_count = [0usize; SAH_BINS];
+        let mut left_cost = [0.0; SAH_BINS];
+        let mut acc = empty;
+        for i in 0..SAH_BINS - 1 {
+            acc.count += bins[i].count;
+            acc.cost += bins[i].cost;
+            acc.bbox = surrounding_box(&acc.bbox, &bins[i].bbox);
+            left_area[i] = acc.bbox.surface_area();
+            left_count[i] = acc.count;
+            left_cost[i] = acc.cost;
+        }
+        let mut acc = empty;
+        let mut best_cost = INF;
+        let mut best_split = 0;
+        for i in (0..SAH_BINS - 1).rev() {
+            acc.count += bins[i + 1].count;
+            acc.cost += bins[i + 1].cost;
+            acc.bbox = surrounding_box(&acc.bbox, &bins[i + 1].bbox);
+            if left_count[i] == 0 || acc.count == 0 {
+                continue;
+            }
+            let cost = left_area[i] * left_cost[i] + acc.bbox.surface_area() * acc.cost;
+            if cost < best_cost {
+                best_cost = cost;
+                best_split = i;
+            }
+        }
+
+        let area = bbox.surface_area();
+        let split_cost = if area > 0.0 {
+            SAH_TRAVERSAL_COST + best_cost / area
+        } else {
+            INF
+        };
+        let leaf_cost: f64 = items.iter().map(|i| i.cost).sum();
+        if n <= leaf_size && split_cost >= leaf_cost {
+            return self.push_leaf(items, bbox);
+        }
+
+        let (left, right): (Vec<BuildItem>, Vec<BuildItem>) = if best_cost < INF {
+            items
+                .into_iter()
+                .partition(|i| bin_of(&i.centroid) <= best_split)
         } else {
-            axis = 2
+            // どのビンにも分けられなかった場合は中央値で割る
+            items.sort_unstable_by(|a, b| a.centroid[axis].total_cmp(&b.centroid[axis]));
+            let right = items.split_off(n / 2);
+            (items, right)
         };
+        self.push_branch(left, right, bbox, axis, leaf_size, depth)
+    }
+
+    fn push_branch(
+        &mut self,
+        left: Vec<BuildItem>,
+        right: Vec<BuildItem>,
+        bbox: AABB,
+        axis: usize,
+        leaf_size: usize,
+        depth: usize,
+    ) {
+        let me = self.nodes.len();
+        self.nodes.push(BVHNode {
+            bbox,
+            offset: 0,
+            count: 0,
+            axis: axis as u8,
+        });
+        self.build(left, leaf_size, depth + 1);
+        self.nodes[me].offset = self.nodes.len() as u32;
+        self.build(right, leaf_size, depth + 1);
+    }
+
+    // t_max より近い中で最も近い交差を返す. prims は構築時と同じものを渡す
+    #[inline]
+    pub fn intersect<'a, T: Shape>(&self, prims: &'a [T], ray: &Ray, t_max: f64) -> Option<HitInfo<'a>> {
+        let mut hit: Option<HitInfo> = None;
+        let mut closest = t_max;
+        match self.nodes.first() {
+            // 根が葉なら木で飛ばせるものはない. 箱は調べず,
+            // 木の外のものとまとめて線形探索と同じ順に全部調べる
+            Some(root) if root.count > 0 => {
+                for p in prims.iter().rev() {
+                    if let Some(h) = p.intersect(ray, closest) {
+                        closest = h.t;
+                        hit = Some(h);
+                    }
+                }
+                return hit;
+            }
+            Some(_) => self.traverse(prims, ray, &mut closest, &mut hit),
+            None => {}
+        }
+        // 木の中で近い交点が見つかっていれば, 木の外の壁はそれより遠いものとして捨てられる
+        for &pi in self.unbounded.iter().rev() {
+            if let Some(h) = prims[pi].intersect(ray, closest) {
+                closest = h.t;
+                hit = Some(h);
+            }
+        }
+        hit
+    }
 
-        shape.sort_unstable_by(box_compare(axis));
-        let len = shape.len();
-        match len {
-            0 => panic!["no elements in scene"],
-            1 => {
-                let leaf = shape.pop().unwrap();
-                if let Some(bbox) = leaf.bounding_box() {
-                    BVH {
-                        tree: BVHNode::Leaf(leaf),
-                        bbox,
+    #[inline]
+    fn traverse<'a, T: Shape>(&self, prims: &'a [T], ray: &Ray, closest: &mut f64, hit: &mut Option<HitInfo<'a>>) {
+        let inv_d = Vec3::new(1.0 / ray.d.x, 1.0 / ray.d.y, 1.0 / ray.d.z);
+        let neg = [inv_d.x < 0.0, inv_d.y < 0.0, inv_d.z < 0.0];
+        let mut stack = [0u32; STACK_SIZE];
+        let mut sp = 0;
+        // 箱は親で調べておき, 当たった子だけをたどる
+        let mut i = 0;
+        loop {
+            let node = &self.nodes[i];
+            if node.count > 0 {
+                let first = node.offset as usize;
+                for &pi in &self.indices[first..first + node.count as usize] {
+                    if let Some(h) = prims[pi].intersect(ray, *closest) {
+                        *closest = h.t;
+                        *hit = Some(h);
                     }
+                }
+            } else {
+                // 近い方の子を先に調べ, 遠い方はスタックに積む
+                let (near, far) = if neg[node.axis as usize] {
+                    (node.offset as usize, i + 1)
                 } else {
-                    panic!["no bounding box in bvh node"]
+                    (i + 1, node.offset as usize)
+                };
+                let hit_near = self.nodes[near].bbox.hit_inv(&ray.o, &inv_d, EPS, *closest);
+                let hit_far = self.nodes[far].bbox.hit_inv(&ray.o, &inv_d, EPS, *closest);
+                if hit_near {
+                    if hit_far {
+                        stack[sp] = far as u32;
+                        sp += 1;
+                    }
+                    i = near;
+                    continue;
+                }
+                if hit_far {
+                    i = far;
+                    continue;
                 }
             }
-            _ => {
-                let right = BVH::new(shape.drain(len / 2..).collect());
-                let left = BVH::new(shape);
-                let bbox = surrounding_box(&left.bbox, &right.bbox);
-                BVH {
-                    tree: BVHNode::Branch {
-                        left: Box::new(left),
-                        right: Box::new(right),
-                    },
-                    bbox,
+            if sp == 0 {
+                break;
+            }
+            sp -= 1;
+            i = stack[sp] as usize;
+        }
+    }
+
+    // 中間ノードがない. 交差判定では全部を1つずつ調べるのと同じになる
+    pub fn is_flat(&self) -> bool {
+        self.nodes.len() <= 1
+    }
+
+    // 根の箱に当たるレイ1本あたりの交差判定の重さの見積もり. Shape::cost と同じ単位
+    pub fn cost<T: Shape>(&self, prims: &[T]) -> f64 {
+        let root_area = self.nodes.first().map_or(0.0, |n| n.bbox.surface_area());
+        let tree: f64 = self
+            .nodes
+            .iter()
+            .map(|node| {
+                let rel = if root_area > 0.0 {
+                    node.bbox.surface_area() / root_area
+                } else {
+                    1.0
+                };
+                if node.count > 0 {
+                    let first = node.offset as usize;
+                    let leaf = &self.indices[first..first + node.count as usize];
+                    rel * leaf.iter().map(|&pi| prims[pi].cost()).sum::<f64>()
+                } else {
+                    rel * SAH_TRAVERSAL_COST
                 }
+            })
+            .sum();
+        tree + self.unbounded.iter().map(|&pi| prims[pi].cost()).sum::<f64>()
+    }
+
+    pub fn stats(&self) -> BVHStats {
+        fn walk(bvh: &BVH, i: usize, depth: usize, root_area: f64, st: &mut BVHStats) {
+            let node = &bvh.nodes[i];
+            st.max_depth = st.max_depth.max(depth);
+            let rel = if root_area > 0.0 {
+                node.bbox.surface_area() / root_area
+            } else {
+                1.0
+            };
+            if node.count > 0 {
+                let n = node.count as usize;
+                st.leaves += 1;
+                st.primitives += n;
+                st.max_leaf_size = st.max_leaf_size.max(n);
+                st.sah_cost += rel * n as f64;
+            } else {
+                st.sah_cost += rel * SAH_TRAVERSAL_COST;
+                walk(bvh, i + 1, depth + 1, root_area, st);
+                walk(bvh, node.offset as usize, depth + 1, root_area, st);
             }
         }
+        let mut st = BVHStats {
+            nodes: self.nodes.len(),
+            unbounded: self.unbounded.len(),
+            ..Default::default()
+        };
+        if !self.nodes.is_empty() {
+            walk(self, 0, 1, self.nodes[0].bbox.surface_area(), &mut st);
+        }
+        st
     }
 }
 
-impl Shape for BVH {
-    fn intersect(&self, ray: &Ray) -> Option<HitInfo> {
-        if !self.bbox.hit(&ray, EPS, INF) {
-            return None;
-        }
-        match &self.tree {
-            BVHNode::Leaf(leaf) => leaf.intersect(&ray),
-            BVHNode::Branch { left, right } => {
-                let left_val = left.intersect(&ray);
-                let right_val = right.intersect(&ray);
-                match (left_val, right_val) {
-                    // どちらも交差なし
-                    (None, None) => None,
-                    // 左のみ交差あり
-                    (Some(l), None) => Some(l),
-                    // 右のみ交差あり
-                    (None, Some(r)) => Some(r),
-                    // どちらも交差あり
-                    (Some(l), Some(r)) => {
-                        // t値が小さい方（より近い方）を返す
-                        if l.t < r.t {
-                            Some(l)
-                        } else {
-                            Some(r)
-                        }
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    fn rand_vec(rng: &mut StdRng, r: f64) -> Vec3 {
+        Vec3::new(rng.random_range(-r..r), rng.random_range(-r..r), rng.random_range(-r..r))
+    }
+
+    // 小さな球と四角形をばらまき, 平面2枚と1e5の壁3枚を足したもの
+    fn random_scene(rng: &mut StdRng, n: usize) -> Vec<Primitive> {
+        let z = Vec3::zero();
+        let c = Vec3::new(0.5, 0.5, 0.5);
+        let mut prims: Vec<Primitive> = vec![];
+        for i in 0..n {
+            let p = rand_vec(rng, 20.0);
+            if i % 3 == 0 {
+                prims.push(Rect::new(p, rand_vec(rng, 2.0), rand_vec(rng, 2.0), z, c, Refl::Diff).into());
+            } else {
+                prims.push(Sphere::new(rng.random_range(0.2..2.0), p, z, c, Refl::Diff).into());
+            }
+        }
+        prims.push(Plane::new(Vec3::new(0.0, -30.0, 0.0), Vec3::new(0.0, 1.0, 0.0), z, c, Refl::Diff).into());
+        prims.push(Plane::new(Vec3::new(0.0, 0.0, 40.0), Vec3::new(0.0, 0.0, -1.0), z, c, Refl::Diff).into());
+        let far = -1e5 - 35.0;
+        for center in [Vec3::new(far, 0.0, 0.0), Vec3::new(0.0, far, 0.0), Vec3::new(0.0, 0.0, far)] {
+            prims.push(Sphere::new(1e5, center, z, c, Refl::Diff).into());
+        }
+        prims
+    }
+
+    fn linear<'a>(prims: &'a [Primitive], ray: &Ray, t_max: f64) -> Option<HitInfo<'a>> {
+        let mut hit: Option<HitInfo> = None;
+        let mut closest = t_max;
+        for p in prims.iter().rev() {
+            if let Some(h) = p.intersect(ray, closest) {
+                closest = h.t;
+                hit = Some(h);
+            }
+        }
+        hit
+    }
+
+    #[test]
+    fn closest_hit_matches_linear_search() {
+        let mut rng = StdRng::seed_from_u64(2);
+        for &(n, leaf_size) in &[(5, 4), (60, 1), (300, 1), (300, 4), (300, 16)] {
+            let prims = random_scene(&mut rng, n);
+            let bvh = BVH::new(&prims, leaf_size);
+            let st = bvh.stats();
+            // 平面2枚と1e5の壁3枚は木の外
+            assert_eq!(st.unbounded, 5, "n={} leaf={}", n, leaf_size);
+            assert_eq!(st.primitives, n);
+            assert!(st.max_leaf_size <= leaf_size.max(1));
+            for k in 0..2000 {
+                let ray = Ray::new(rand_vec(&mut rng, 25.0), rand_vec(&mut rng, 1.0).norm());
+                let t_max = if k % 4 == 0 { rng.random_range(1.0..30.0) } else { INF };
+                let a = bvh.intersect(&prims, &ray, t_max);
+                let b = linear(&prims, &ray, t_max);
+                match (a, b) {
+                    (Some(a), Some(b)) => {
+                        assert_eq!(a.t, b.t, "n={} leaf={} ray {}", n, leaf_size, k);
+                        assert!((a.p - b.p).length() < 1e-12);
+                        assert!(std::ptr::eq(a.m, b.m));
                     }
+                    (None, None) => {}
+                    (a, b) => panic!("n={} leaf={} ray {}: {:?} vs {:?}", n, leaf_size, k, a.map(|h| h.t), b.map(|h| h.t)),
                 }
             }
         }
     }
 
-    fn bounding_box(&self) -> Option<AABB> {
-        Some(self.bbox.clone())
+    #[test]
+    fn stats_count_nodes_and_leaves() {
+        let z = Vec3::zero();
+        let c = Vec3::new(0.5, 0.5, 0.5);
+        // 離れて1列に並んだ8個の球. 葉1個ずつなら完全な2分木で 8 葉, 15 ノード
+        let mut prims: Vec<Primitive> = (0..8)
+            .map(|i| Sphere::new(1.0, Vec3::new(10.0 * i as f64, 0.0, 0.0), z, c, Refl::Diff).into())
+            .collect();
+        prims.push(Plane::new(Vec3::new(0.0, -5.0, 0.0), Vec3::new(0.0, 1.0, 0.0), z, c, Refl::Diff).into());
+        let st = BVH::new(&prims, 1).stats();
+        assert_eq!((st.nodes, st.leaves, st.primitives, st.unbounded), (15, 8, 8, 1));
+        assert_eq!((st.max_depth, st.max_leaf_size), (4, 1));
+
+        // 木がなければノードも葉もない
+        let st = BVH::new(&prims[8..], 4).stats();
+        assert_eq!((st.nodes, st.leaves, st.primitives, st.unbounded), (0, 0, 0, 1));
+
+        // どんな木でも 2分木なのでノード数は 葉の数 * 2 - 1
+        let mut rng = StdRng::seed_from_u64(3);
+        for leaf_size in [1, 2, 4, 8] {
+            let prims = random_scene(&mut rng, 200);
+            let st = BVH::new(&prims, leaf_size).stats();
+            assert_eq!(st.nodes, 2 * st.leaves - 1);
+            assert_eq!(st.primitives + st.unbounded, prims.len());
+        }
     }
-}
 
+    fn linear_cost(scene: &Scene) -> f64 {
+        scene.objects.iter().map(|o| o.cost()).sum()
+    }
 
+    #[test]
+    fn small_scenes_cost_no_more_than_linear() {
+        // 球が数個だけなら木を作らず, 線形探索と同じ手間で済む
+        for n in [0, 1, 2] {
+            let mut scene = load_builtin_scene(n).unwrap().unwrap().0;
+            scene.build_bvh(DEFAULT_LEAF_SIZE);
+            let bvh = scene.bvh.as_ref().unwrap();
+            assert!(bvh.is_flat(), "model {}", n);
+            assert!(bvh.cost(&scene.objects) <= linear_cost(&scene) + 1e-9, "model {}", n);
+        }
+        // 重い形状が並べば木をたどる方が安い
+        let mut scene = Scene::init();
+        scene.model_init13();
+        scene.build_bvh(DEFAULT_LEAF_SIZE);
+        let bvh = scene.bvh.as_ref().unwrap();
+        assert!(!bvh.is_flat());
+        assert!(bvh.cost(&scene.objects) < 0.5 * linear_cost(&scene));
+    }
+}
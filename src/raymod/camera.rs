@@ -0,0 +1,553 @@
+use crate::raymod::*;
+
+use std::f64::consts::PI;
+use std::fmt;
+use std::str::FromStr;
+
+// 画面の位置からレイの向きへの写し方
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Projection {
+    // 透視投影. 画角は Camera の vfov
+    Perspective,
+    // 平行投影. height は画面の縦に写るワールドの長さ
+    Orthographic { height: f64 },
+    // 等距離射影の魚眼. 画面の短い辺いっぱいの円に fov 度が入る. 円の外は黒
+    Fisheye { fov: f64 },
+    // 正距円筒図法. 横が経度 360度, 縦が緯度 180度 (幅:高さ = 2:1 で使う)
+    Equirectangular,
+}
+
+// 左右の目の画像の並べ方
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum StereoLayout {
+    // 左目が左, 右目が右
+    SideBySide,
+    // 左目が上, 右目が下
+    TopBottom,
+}
+
+// 両眼の画像を1枚に並べて出す. ipd は両目の間隔
+// convergence は左右の像が重なる距離で, None ならピントの距離. INF なら視線は平行
+// 正距円筒では向きごとに目を置き直す omni-directional stereo になる
+#[derive(Clone, Copy, Debug)]
+pub struct Stereo {
+    pub ipd: f64,
+    pub convergence: Option<f64>,
+    pub layout: StereoLayout,
+}
+
+// 人の目の間隔. smallpt の部屋は 1 がおよそ 1cm
+pub const DEFAULT_IPD: f64 = 6.5;
+
+impl FromStr for StereoLayout {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<StereoLayout, String> {
+        match s {
+            "sbs" | "side-by-side" => Ok(StereoLayout::SideBySide),
+            "tb" | "top-bottom" => Ok(StereoLayout::TopBottom),
+            _ => Err(format!("unknown stereo layout '{}'", s)),
+        }
+    }
+}
+
+impl fmt::Display for StereoLayout {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StereoLayout::SideBySide => write!(f, "sbs"),
+            StereoLayout::TopBottom => write!(f, "tb"),
+        }
+    }
+}
+
+// "perspective", "ortho:200", "fisheye:180", "equirect" の形
+impl FromStr for Projection {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Projection, String> {
+        let (name, arg) = match s.split_once(':') {
+            Some((n, a)) => (n, Some(a)),
+            None => (s, None),
+        };
+        let value = |default: f64| -> Result<f64, String> {
+            arg.map_or(Ok(default), |a| a.parse().map_err(|_| format!("bad number '{}' in '{}'", a, s)))
+        };
+        match name {
+            "perspective" => Ok(Projection::Perspective),
+            "ortho" | "orthographic" => Ok(Projection::Orthographic { height: value(100.0)? }),
+            "fisheye" => Ok(Projection::Fisheye { fov: value(180.0)? }),
+            "equirect" | "equirectangular" => Ok(Projection::Equirectangular),
+            _ => Err(format!("unknown projection '{}'", s)),
+        }
+    }
+}
+
+// from_str で読み戻せる形
+impl fmt::Display for Projection {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Projection::Perspective => write!(f, "perspective"),
+            Projection::Orthographic { height } => write!(f, "ortho:{}", height),
+            Projection::Fisheye { fov } => write!(f, "fisheye:{}", fov),
+            Projection::Equirectangular => write!(f, "equirect"),
+        }
+    }
+}
+
+// 薄レンズのカメラ. vfov は縦の画角(度), 横の画角は width/height から決まる
+// near は視線方向に測った距離で, それより手前はレイの始点を進めて写さない
+// (元の smallpt はカメラが部屋の外にあり, 140だけ進めてから飛ばしていた)
+// aperture はレンズの半径で, 0 ならピンホール. ピントは視線方向に focus_dist の面に合う
+// focus_dist が None なら look_at までの距離. レンズのボケは透視投影のときだけ
+// 魚眼と正距円筒では near はレイに沿った距離になる
+// stereo があると width, height は片目の大きさで, 出力はその2枚分になる
+// shutter は (開く時刻, 閉じる時刻). レイの時刻はこの間から一様に選ぶ
+#[derive(Clone, Debug)]
+pub struct Camera {
+    pub pos: Vec3,
+    pub look_at: Vec3,
+    pub up: Vec3,
+    pub vfov: f64,
+    pub width: usize,
+    pub height: usize,
+    pub near: f64,
+    pub aperture: f64,
+    pub focus_dist: Option<f64>,
+    pub lens: Aperture,
+    pub projection: Projection,
+    pub stereo: Option<Stereo>,
+    pub shutter: (f64, f64),
+}
+
+// smallpt の 0.5135 に相当する縦の画角. 2 * atan(0.5135 / 2)
+pub const SMALLPT_VFOV: f64 = 28.799_316_231_093_748;
+
+impl Default for Camera {
+    // 元の main にあった固定のカメラ
+    fn default() -> Camera {
+        let pos = Vec3::new(50.0, 52.0, 295.6);
+        Camera {
+            pos,
+            // 向きは元のまま, 注視点は球のあたりに置いてピントの基準にする
+            look_at: pos + Vec3::new(0.0, -0.042612, -1.0).norm() * 220.0,
+            up: Vec3::new(0.0, 1.0, 0.0),
+            vfov: SMALLPT_VFOV,
+            width: 640,
+            height: 480,
+            near: 140.0,
+            aperture: 0.0,
+            focus_dist: None,
+            lens: Aperture::Disk,
+            projection: Projection::Perspective,
+            stereo: None,
+            shutter: (0.0, 0.0),
+        }
+    }
+}
+
+// レイを作るときに使う, 向きと画面の広がり
+#[derive(Clone)]
+pub struct CameraBasis {
+    pub o: Vec3,
+    // 視線方向
+    pub w: Vec3,
+    // 画面の右方向と上方向. 長さは画面の半分の幅と高さ (視線方向に距離1のところで)
+    pub u: Vec3,
+    pub v: Vec3,
+    near: f64,
+    // レンズ面の右と上. 長さはレンズの半径
+    lens_u: Vec3,
+    lens_v: Vec3,
+    focus: f64,
+    lens: Aperture,
+    projection: Projection,
+    // 縦横比 (幅 / 高さ)
+    aspect: f64,
+    // 目の位置. 中心から右へずらす距離 (左目は負)
+    eye: f64,
+    convergence: f64,
+    shutter: (f64, f64),
+}
+
+// 出力する画像全体. ステレオなら目ごとの画面を並べる
+pub struct Film {
+    pub width: usize,
+    pub height: usize,
+    // 片目の大きさ
+    pub view_width: usize,
+    pub view_height: usize,
+    // 目ごとの基底と, 出力の中での左上の位置
+    views: Vec<(CameraBasis, usize, usize)>,
+}
+
+impl Film {
+    // 出力の画素 (x, y) (左上が原点) を写す目と, その目の画面での位置
+    pub fn view(&self, x: usize, y: usize) -> (&CameraBasis, usize, usize) {
+        for (b, ox, oy) in self.views.iter().rev() {
+            if x >= *ox && y >= *oy {
+                return (b, x - ox, y - oy);
+            }
+        }
+        unreachable!("the first view starts at (0, 0)")
+    }
+}
+
+impl Camera {
+    pub fn new(pos: Vec3, look_at: Vec3, up: Vec3, vfov: f64) -> Camera {
+        Camera {
+            pos,
+            look_at,
+            up,
+            vfov,
+            ..Camera::default()
+        }
+    }
+
+    pub fn with_size(mut self, width: usize, height: usize) -> Camera {
+        self.width = width;
+        self.height = height;
+        self
+    }
+
+    pub fn with_near(mut self, near: f64) -> Camera {
+        self.near = near;
+        self
+    }
+
+    pub fn with_lens(mut self, aperture: f64, focus_dist: f64) -> Camera {
+        self.aperture = aperture;
+        self.focus_dist = Some(focus_dist);
+        self
+    }
+
+    pub fn with_aperture_shape(mut self, lens: Aperture) -> Camera {
+        self.lens = lens;
+        self
+    }
+
+    pub fn with_projection(mut self, projection: Projection) -> Camera {
+        self.projection = projection;
+        self
+    }
+
+    pub fn with_stereo(mut self, stereo: Stereo) -> Camera {
+        self.stereo = Some(stereo);
+        self
+    }
+
+    pub fn with_shutter(mut self, open: f64, close: f64) -> Camera {
+        self.shutter = (open, close);
+        self
+    }
+
+    pub fn focus_distance(&self) -> f64 {
+        self.focus_dist.unwrap_or_else(|| (self.look_at - self.pos).length().sqrt())
+    }
+
+    // 画素 (px, py) (左上が (0, 0)) の中心に見える物までの, 視線方向に測った距離
+    pub fn autofocus(&self, scene: &Scene, px: usize, py: usize) -> Option<f64> {
+        let b = self.basis();
+        let sx = (px as f64 + 0.5) / self.width as f64;
+        let sy = 1.0 - (py as f64 + 0.5) / self.height as f64;
+        let ray = b.center_ray(sx, sy)?;
+        let hit = scene.intersect(&ray)?;
+        Some((hit.p - b.o).dot(&b.w))
+    }
+
+    pub fn basis(&self) -> CameraBasis {
+        let w = (self.look_at - self.pos).norm();
+        let half_h = (self.vfov.to_radians() * 0.5).tan();
+        let half_w = half_h * self.width as f64 / self.height as f64;
+        let u = (w % self.up).norm();
+        let v = u % w;
+        CameraBasis {
+            o: self.pos,
+            w,
+            u: u * half_w,
+            v: v * half_h,
+            near: self.near,
+            lens_u: u * self.aperture,
+            lens_v: v * self.aperture,
+            focus: self.focus_distance(),
+            lens: self.lens.clone(),
+            projection: self.projection,
+            aspect: self.width as f64 / self.height as f64,
+            eye: 0.0,
+            convergence: INF,
+            shutter: self.shutter,
+        }
+    }
+
+    pub fn film(&self) -> Film {
+        let (w, h) = (self.width, self.height);
+        let center = self.basis();
+        let st = match self.stereo {
+            Some(st) => st,
+            None => {
+                return Film {
+                    width: w,
+                    height: h,
+                    view_width: w,
+                    view_height: h,
+                    views: vec![(center, 0, 0)],
+                }
+            }
+        };
+        let convergence = st.convergence.unwrap_or_else(|| self.focus_distance());
+        let eye = |e: f64| CameraBasis {
+            eye: e,
+            convergence,
+            ..center.clone()
+        };
+        let (left, right) = (eye(-st.ipd * 0.5), eye(st.ipd * 0.5));
+        match st.layout {
+            StereoLayout::SideBySide => Film {
+                width: w * 2,
+                height: h,
+                view_width: w,
+                view_height: h,
+                views: vec![(left, 0, 0), (right, w, 0)],
+            },
+            StereoLayout::TopBottom => Film {
+                width: w,
+                height: h * 2,
+                view_width: w,
+                view_height: h,
+                views: vec![(left, 0, 0), (right, 0, h)],
+            },
+        }
+    }
+}
+
+impl CameraBasis {
+    // 画面上の位置 (sx, sy) は左下が (0, 0), 右上が (1, 1)
+    // 魚眼の円の外のように何も写らないところは None
+    #[inline]
+    pub fn ray(&self, sx: f64, sy: f64) -> Option<Ray> {
+        let (open, close) = self.shutter;
+        let time = open + (close - open) * random();
+        self.ray_at(sx, sy).map(|r| Ray::timed(r.o, r.d, time))
+    }
+
+    #[inline]
+    fn ray_at(&self, sx: f64, sy: f64) -> Option<Ray> {
+        if self.projection != Projection::Perspective {
+            return self.center_ray(sx, sy);
+        }
+        let un = self.u.norm();
+        let eye = self.o + un * self.eye;
+        let mut d = self.w + self.u * (2.0 * sx - 1.0) + self.v * (2.0 * sy - 1.0);
+        // 輻輳距離の面で中心の目と同じ点を見るように画面をずらす (視線方向の成分は1のまま)
+        d = d - un * (self.eye / self.convergence);
+        if self.lens_u.length() == 0.0 {
+            // d は視線方向の成分が1なので, near 進めると近い面にちょうど乗る
+            return Some(Ray::new(eye + d * self.near, d.norm()));
+        }
+        // レンズ上の点からピントの面の同じ点へ向かう
+        let (lx, ly) = self.lens.sample();
+        let o = eye + self.lens_u * lx + self.lens_v * ly;
+        let dir = (eye + d * self.focus - o).norm();
+        Some(Ray::new(o + dir * (self.near / dir.dot(&self.w)), dir))
+    }
+
+    // レンズの中心を通るレイ
+    fn center_ray(&self, sx: f64, sy: f64) -> Option<Ray> {
+        let (x, y) = (2.0 * sx - 1.0, 2.0 * sy - 1.0);
+        let (un, vn) = (self.u.norm(), self.v.norm());
+        // 目をずらし, 輻輳距離で中心の目と同じ点を見るように向け直す
+        let along = |dir: Vec3, side: Vec3| {
+            let eye = self.o + side * self.eye;
+            let dir = if self.eye != 0.0 && self.convergence.is_finite() {
+                (self.o + dir * self.convergence - eye).norm()
+            } else {
+                dir
+            };
+            Some(Ray::new(eye + dir * self.near, dir))
+        };
+        match self.projection {
+            Projection::Perspective => {
+                let d = self.w + self.u * x + self.v * y - un * (self.eye / self.convergence);
+                Some(Ray::new(self.o + un * self.eye + d * self.near, d.norm()))
+            }
+            Projection::Orthographic { height } => {
+                // 平行投影では目をずらすだけ
+                let half_h = height * 0.5;
+                let o = self.o + un * (x * half_h * self.aspect + self.eye) + vn * (y * half_h) + self.w * self.near;
+                Some(Ray::new(o, self.w))
+            }
+            Projection::Fisheye { fov } => {
+                // 短い辺を半径1にそろえる
+                let (x, y) = if self.aspect >= 1.0 { (x * self.aspect, y) } else { (x, y / self.aspect) };
+                let r = (x * x + y * y).sqrt();
+                if r > 1.0 {
+                    return None;
+                }
+                let theta = r * fov.to_radians() * 0.5;
+                let phi = y.atan2(x);
+                let (st, ct) = theta.sin_cos();
+                along(self.w * ct + un * (st * phi.cos()) + vn * (st * phi.sin()), un)
+            }
+            Projection::Equirectangular => {
+                // 画面の中央が視線方向
+                let phi = x * PI;
+                let lat = y * PI * 0.5;
+                let (sl, cl) = lat.sin_cos();
+                let (sp, cp) = phi.sin_cos();
+                // 目は水平な視線に対して右手側に置く
+                along(self.w * (cl * cp) + un * (cl * sp) + vn * sl, un * cp - self.w * sp)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // p からレイの直線までの距離
+    fn dist_to_line(p: Vec3, r: &Ray) -> f64 {
+        ((p - r.o) % r.d).length().sqrt()
+    }
+
+    fn test_camera() -> Camera {
+        Camera::new(Vec3::new(10.0, 20.0, 30.0), Vec3::new(-5.0, 12.0, -40.0), Vec3::new(0.0, 1.0, 0.0), 40.0)
+            .with_size(320, 200)
+            .with_near(5.0)
+    }
+
+    #[test]
+    fn center_ray_hits_look_at() {
+        let mut cam = test_camera();
+        let b = cam.basis();
+        let r = b.ray(0.5, 0.5).unwrap();
+        assert!(dist_to_line(cam.look_at, &r) < 1e-9);
+        assert!((r.d - (cam.look_at - cam.pos).norm()).length() < 1e-20);
+        // 始点は near だけ進めてある
+        assert!((r.o - (cam.pos + r.d * 5.0)).length() < 1e-18);
+        // 画面の上端の中央は縦の画角の半分だけ上を向く
+        let top = b.ray(0.5, 1.0).unwrap();
+        assert!((top.d.dot(&b.w).acos().to_degrees() - 20.0).abs() < 1e-9);
+        assert!(top.d.dot(&cam.up) > r.d.dot(&cam.up));
+        // レンズがあっても, ピントが look_at なら中心のレイはどれもそこを通る
+        cam.aperture = 2.0;
+        let b = cam.basis();
+        for _ in 0..100 {
+            let r = b.ray(0.5, 0.5).unwrap();
+            assert!(dist_to_line(cam.look_at, &r) < 1e-9);
+        }
+        // 元の smallpt のカメラでも同じ
+        let cam = Camera::default();
+        assert!(dist_to_line(cam.look_at, &cam.basis().ray(0.5, 0.5).unwrap()) < 1e-9);
+    }
+
+    #[test]
+    fn projection_roundtrips_through_strings() {
+        for p in [
+            Projection::Perspective,
+            Projection::Orthographic { height: 120.5 },
+            Projection::Fisheye { fov: 200.0 },
+            Projection::Equirectangular,
+        ] {
+            assert_eq!(p.to_string().parse::<Projection>().unwrap(), p);
+        }
+        assert_eq!("ortho".parse::<Projection>().unwrap(), Projection::Orthographic { height: 100.0 });
+        assert_eq!("fisheye".parse::<Projection>().unwrap(), Projection::Fisheye { fov: 180.0 });
+        assert_eq!("equirectangular".parse::<Projection>().unwrap(), Projection::Equirectangular);
+        assert!("fisheye:wide".parse::<Projection>().is_err());
+        assert!("cylinder".parse::<Projection>().is_err());
+    }
+
+    #[test]
+    fn projections_look_along_the_view_direction() {
+        let cam = test_camera();
+        let w = (cam.look_at - cam.pos).norm();
+        for p in ["ortho:50", "fisheye:180", "equirect"] {
+            let b = cam.clone().with_projection(p.parse().unwrap()).basis();
+            let r = b.ray(0.5, 0.5).unwrap();
+            assert!((r.d - w).length() < 1e-20, "{}", p);
+            assert!(dist_to_line(cam.look_at, &r) < 1e-9, "{}", p);
+        }
+        // 平行投影はどこでも同じ向きで, 縦に height だけ並ぶ
+        let b = cam.clone().with_projection(Projection::Orthographic { height: 50.0 }).basis();
+        let (lo, hi) = (b.ray(0.5, 0.0).unwrap(), b.ray(0.5, 1.0).unwrap());
+        assert!((lo.d - hi.d).length() < 1e-20);
+        assert!(((hi.o - lo.o).length().sqrt() - 50.0).abs() < 1e-9);
+        // 180度の魚眼の円の縁は真横, 円の外は写らない
+        let b = cam.clone().with_projection(Projection::Fisheye { fov: 180.0 }).basis();
+        assert!(b.ray(0.5, 1.0).unwrap().d.dot(&w).abs() < 1e-12);
+        assert!(b.ray(0.0, 0.0).is_none());
+        // 正距円筒の左右の端は真後ろ, 上端は真上
+        let b = cam.clone().with_projection(Projection::Equirectangular).basis();
+        assert!((b.ray(0.0, 0.5).unwrap().d + w).length() < 1e-20);
+        assert!((b.ray(1.0, 0.5).unwrap().d + w).length() < 1e-20);
+        assert!((b.ray(0.3, 1.0).unwrap().d - b.v.norm()).length() < 1e-20);
+    }
+
+    #[test]
+    fn stereo_layout_roundtrips_through_strings() {
+        for l in [StereoLayout::SideBySide, StereoLayout::TopBottom] {
+            assert_eq!(l.to_string().parse::<StereoLayout>().unwrap(), l);
+        }
+        assert_eq!("side-by-side".parse::<StereoLayout>().unwrap(), StereoLayout::SideBySide);
+        assert_eq!("top-bottom".parse::<StereoLayout>().unwrap(), StereoLayout::TopBottom);
+        assert!("anaglyph".parse::<StereoLayout>().is_err());
+    }
+
+    #[test]
+    fn film_maps_pixels_to_eyes() {
+        let stereo = |layout| Stereo { ipd: 6.0, convergence: Some(80.0), layout };
+        let cam = test_camera();
+        let film = cam.film();
+        assert_eq!((film.width, film.height), (320, 200));
+        let (b, x, y) = film.view(319, 199);
+        assert_eq!((b.eye, x, y), (0.0, 319, 199));
+
+        let film = cam.clone().with_stereo(stereo(StereoLayout::SideBySide)).film();
+        assert_eq!((film.width, film.height, film.view_width, film.view_height), (640, 200, 320, 200));
+        let (b, x, y) = film.view(319, 199);
+        assert_eq!((b.eye, x, y), (-3.0, 319, 199));
+        let (b, x, y) = film.view(320, 0);
+        assert_eq!((b.eye, x, y), (3.0, 0, 0));
+        let (b, x, y) = film.view(639, 150);
+        assert_eq!((b.eye, x, y), (3.0, 319, 150));
+
+        let film = cam.clone().with_stereo(stereo(StereoLayout::TopBottom)).film();
+        assert_eq!((film.width, film.height, film.view_width, film.view_height), (320, 400, 320, 200));
+        let (b, x, y) = film.view(319, 199);
+        assert_eq!((b.eye, x, y), (-3.0, 319, 199));
+        let (b, x, y) = film.view(10, 200);
+        assert_eq!((b.eye, x, y), (3.0, 10, 0));
+        let (b, x, y) = film.view(319, 399);
+        assert_eq!((b.eye, x, y), (3.0, 319, 199));
+    }
+
+    #[test]
+    fn stereo_eyes_meet_at_the_convergence_distance() {
+        let cam = test_camera().with_stereo(Stereo { ipd: 6.0, convergence: Some(80.0), layout: StereoLayout::SideBySide });
+        let film = cam.film();
+        let w = (cam.look_at - cam.pos).norm();
+        let target = cam.pos + w * 80.0;
+        let (left, _, _) = film.view(0, 0);
+        let (right, _, _) = film.view(320, 0);
+        for b in [left, right] {
+            let r = b.ray(0.5, 0.5).unwrap();
+            assert!(dist_to_line(target, &r) < 1e-9);
+        }
+        // 目は右方向に ipd だけ離れている
+        let (l, r) = (left.ray(0.5, 0.5).unwrap(), right.ray(0.5, 0.5).unwrap());
+        let gap = (r.o - r.d * (5.0 / r.d.dot(&w))) - (l.o - l.d * (5.0 / l.d.dot(&w)));
+        assert!((gap - left.u.norm() * 6.0).length() < 1e-18);
+    }
+
+    #[test]
+    fn ray_times_fall_inside_the_shutter() {
+        let b = test_camera().basis();
+        assert_eq!(b.ray(0.2, 0.7).unwrap().time, 0.0);
+        let b = test_camera().with_shutter(0.25, 0.75).basis();
+        let times: Vec<f64> = (0..1000).map(|_| b.ray(0.2, 0.7).unwrap().time).collect();
+        assert!(times.iter().all(|t| (0.25..0.75).contains(t)));
+        // 一様に選ぶので前半と後半に半分ずつ入る
+        let early = times.iter().filter(|&&t| t < 0.5).count();
+        assert!((400..600).contains(&early));
+    }
+}
@@ -0,0 +1,196 @@
+use crate::raymod::*;
+
+// 1つの形状がレイと交わる回数の上限. トーラスでも4回
+const MAX_CROSSINGS: usize = 16;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CsgOp {
+    Union,
+    Intersection,
+    Difference,
+}
+
+impl CsgOp {
+    fn apply(self, a: bool, b: bool) -> bool {
+        match self {
+            CsgOp::Union => a || b,
+            CsgOp::Intersection => a && b,
+            CsgOp::Difference => a && !b,
+        }
+    }
+}
+
+// 閉じた形状2つを組み合わせる. 子にCSGを入れて入れ子にもできる
+// 交差はレイに沿った出入りの列を作ってから組み合わせる
+#[derive(Clone, Debug)]
+pub struct Csg {
+    pub op: CsgOp,
+    pub a: Box<Primitive>,
+    pub b: Box<Primitive>,
+}
+
+// 出入りの境界. enter は外から中へ入るとき true
+struct Crossing<'a> {
+    hit: HitInfo<'a>,
+    enter: bool,
+}
+
+// 境界を近い順に並べる. 外向きの法線とレイの向きで出入りを決める
+// 始点が中にあるかを知るため, t_max を越えた最初の1つまでは入れる
+fn crossings<'a>(s: &'a Primitive, ray: &Ray, t_max: f64) -> Vec<Crossing<'a>> {
+    let mut out = vec![];
+    let mut t0 = 0.0;
+    for _ in 0..MAX_CROSSINGS {
+        let r = Ray::timed(ray.o + ray.d * t0, ray.d, ray.time);
+        let mut hit = match s.intersect(&r, INF) {
+            Some(h) => h,
+            None => break,
+        };
+        hit.t += t0;
+        hit.p = ray.o + ray.d * hit.t;
+        t0 = hit.t;
+        let enter = hit.n.dot(&ray.d) < 0.0;
+        out.push(Crossing { hit, enter });
+        if t0 >= t_max {
+            break;
+        }
+    }
+    out
+}
+
+impl Csg {
+    pub fn new<A: Into<Primitive>, B: Into<Primitive>>(op: CsgOp, a: A, b: B) -> Csg {
+        Csg {
+            op,
+            a: Box::new(a.into()),
+            b: Box::new(b.into()),
+        }
+    }
+
+    pub fn union<A: Into<Primitive>, B: Into<Primitive>>(a: A, b: B) -> Csg {
+        Csg::new(CsgOp::Union, a, b)
+    }
+
+    pub fn intersection<A: Into<Primitive>, B: Into<Primitive>>(a: A, b: B) -> Csg {
+        Csg::new(CsgOp::Intersection, a, b)
+    }
+
+    pub fn difference<A: Into<Primitive>, B: Into<Primitive>>(a: A, b: B) -> Csg {
+        Csg::new(CsgOp::Difference, a, b)
+    }
+}
+
+impl Shape for Csg {
+    fn intersect(&self, ray: &Ray, t_max: f64) -> Option<HitInfo<'_>> {
+        let ca = crossings(&self.a, ray, t_max);
+        let cb = crossings(&self.b, ray, t_max);
+        // 最初の境界が出る向きならレイの始点は中にある
+        let mut in_a = ca.first().is_some_and(|c| !c.enter);
+        let mut in_b = cb.first().is_some_and(|c| !c.enter);
+        let mut inside = self.op.apply(in_a, in_b);
+
+        let (mut ia, mut ib) = (ca.into_iter().peekable(), cb.into_iter().peekable());
+        // 直前に通った a の境界の材質
+        let mut last_a: Option<&Material> = None;
+        loop {
+            let from_a = match (ia.peek(), ib.peek()) {
+                (Some(x), Some(y)) => x.hit.t <= y.hit.t,
+                (Some(_), None) => true,
+                (None, Some(_)) => false,
+                (None, None) => return None,
+            };
+            let c = if from_a { ia.next()? } else { ib.next()? };
+            if from_a {
+                in_a = c.enter;
+                last_a = Some(c.hit.m);
+            } else {
+                in_b = c.enter;
+            }
+            let now = self.op.apply(in_a, in_b);
+            if now == inside {
+                continue;
+            }
+            inside = now;
+            if c.hit.t < EPS {
+                continue;
+            }
+            if c.hit.t >= t_max {
+                return None;
+            }
+            let mut hit = c.hit;
+            // 差で b の面が見えるときは, b の内側が結果の外側になる
+            // 切り口は a の中なので a の材質にし, 切った立体を1つの材質と媒質にする
+            // a の境界をまだ通っていなければ, 始点が a の中にあり次の a の境界で出る
+            if !from_a && self.op == CsgOp::Difference {
+                hit.n = hit.n * -1.0;
+                if let Some(m) = last_a.or_else(|| ia.peek().map(|x| x.hit.m)) {
+                    hit.m = m;
+                    hit.color = None;
+                    hit.emission = None;
+                }
+            }
+            return Some(hit);
+        }
+    }
+
+    fn bounding_box(&self) -> Option<AABB> {
+        let a = self.a.bounding_box();
+        let b = self.b.bounding_box();
+        match self.op {
+            CsgOp::Union => Some(surrounding_box(&a?, &b?)),
+            CsgOp::Intersection => match (a, b) {
+                (Some(a), Some(b)) => Some(AABB::new(
+                    Vec3::new(a.min.x.max(b.min.x), a.min.y.max(b.min.y), a.min.z.max(b.min.z)),
+                    Vec3::new(a.max.x.min(b.max.x), a.max.y.min(b.max.y), a.max.z.min(b.max.z)),
+                )),
+                (a, b) => a.or(b),
+            },
+            CsgOp::Difference => a,
+        }
+    }
+
+    // 両方の境界をすべて集めてから選ぶので, 子を何度も調べることになる
+    fn cost(&self) -> f64 {
+        4.0 * (self.a.cost() + self.b.cost())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ball(c: Vec3) -> Sphere {
+        Sphere::new(1.0, Vec3::zero(), Vec3::zero(), c, Refl::Diff)
+    }
+
+    fn cutter(c: Vec3) -> Cuboid {
+        Cuboid::new(Vec3::new(0.0, -2.0, -2.0), Vec3::new(2.0, 2.0, 2.0), Material::new(Vec3::zero(), c, Refl::Diff))
+    }
+
+    #[test]
+    fn difference_cut_face_takes_the_material_of_a() {
+        let red = Vec3::new(1.0, 0.0, 0.0);
+        let csg = Csg::difference(ball(red), cutter(Vec3::new(0.0, 0.0, 1.0)));
+        // +x から来ると球の右半分は削られていて, 切り口 x = 0 に当たる
+        let h = csg.intersect(&Ray::new(Vec3::new(5.0, 0.0, 0.0), Vec3::new(-1.0, 0.0, 0.0)), INF).unwrap();
+        assert!((h.t - 5.0).abs() < 1e-9);
+        assert!((h.n - Vec3::new(1.0, 0.0, 0.0)).length() < 1e-12);
+        assert!((h.m.c - red).length() < 1e-12);
+        // 始点が切り口の奥, 球の中にあっても同じ
+        let h = csg.intersect(&Ray::new(Vec3::new(0.5, 0.0, 0.0), Vec3::new(-1.0, 0.0, 0.0)), INF).unwrap();
+        assert!((h.t - 0.5).abs() < 1e-9);
+        assert!((h.m.c - red).length() < 1e-12);
+    }
+
+    #[test]
+    fn union_and_intersection_boundaries() {
+        let u = Csg::union(ball(Vec3::zero()), cutter(Vec3::zero()));
+        let h = u.intersect(&Ray::new(Vec3::new(5.0, 0.0, 0.0), Vec3::new(-1.0, 0.0, 0.0)), INF).unwrap();
+        assert!((h.t - 3.0).abs() < 1e-9);
+        let i = Csg::intersection(ball(Vec3::zero()), cutter(Vec3::zero()));
+        let h = i.intersect(&Ray::new(Vec3::new(5.0, 0.0, 0.0), Vec3::new(-1.0, 0.0, 0.0)), INF).unwrap();
+        assert!((h.t - 4.0).abs() < 1e-9);
+        // 重なりの外, 球の左半分だけを通るレイ
+        assert!(i.intersect(&Ray::new(Vec3::new(-0.5, 5.0, 0.0), Vec3::new(0.0, -1.0, 0.0)), INF).is_none());
+    }
+}
@@ -0,0 +1,400 @@
+use crate::raymod::*;
+
+use gltf::camera::Projection as GltfProjection;
+use gltf::mesh::Mode;
+use gltf::texture::WrappingMode;
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Arc;
+
+// glTF 2.0 (.gltf と .glb) を読む
+// ノードの変換は頂点に焼き込み, プリミティブごとに1つのメッシュにする
+// マテリアルは metallic-roughness から近いものを選ぶ:
+//   transmission が 0.5 以上なら KHR_materials_ior の屈折率のガラス, metallic が 0.5 以上なら金属,
+//   それ以外は拡散面. ガラスと金属は roughness の粗さを持つ. テクスチャで変わる metallic, roughness, transmission は画像の平均で決める
+// 基本色, 発光, 法線のテクスチャは画素ごとに引く. occlusion は光を追うので使わない
+
+// glTF から読んだもの
+pub struct GltfScene {
+    pub meshes: Vec<NamedMesh>,
+    // シーンの中で最初に見つかったカメラ
+    pub camera: Option<Camera>,
+    pub warnings: Vec<LoadError>,
+}
+
+// 対応している拡張. ほかは警告を出して無視する
+const SUPPORTED_EXTENSIONS: [&str; 3] = [
+    "KHR_materials_emissive_strength",
+    "KHR_materials_transmission",
+    "KHR_materials_ior",
+];
+
+pub fn load_gltf(path: &Path) -> Result<GltfScene, LoadError> {
+    let (doc, buffers, images) = gltf::import(path).map_err(|e| LoadError::new(path, 0, e.to_string()))?;
+    let mut l = Loader {
+        path,
+        buffers,
+        images,
+        textures: HashMap::new(),
+        materials: HashMap::new(),
+        meshes: vec![],
+        camera: None,
+        warnings: vec![],
+    };
+    for ext in doc.extensions_used() {
+        if !SUPPORTED_EXTENSIONS.contains(&ext) {
+            l.warn(format!("extension {} is ignored", ext));
+        }
+    }
+    let scene = doc
+        .default_scene()
+        .or_else(|| doc.scenes().next())
+        .ok_or_else(|| LoadError::new(path, 0, "no scene in the file"))?;
+    for node in scene.nodes() {
+        l.node(&node, Mat4::identity());
+    }
+    Ok(GltfScene {
+        meshes: l.meshes,
+        camera: l.camera,
+        warnings: l.warnings,
+    })
+}
+
+// --scene で読むとき. カメラがなければ全体が見えるところに置き, 光るものがなければ白い空で照らす
+pub fn load_gltf_scene(path: &Path) -> Result<(Scene, RenderSettings, Vec<LoadError>), LoadError> {
+    let gltf = load_gltf(path)?;
+    let mut warnings = gltf.warnings;
+    let mut scene = Scene::init();
+    let mut bounds: Option<AABB> = None;
+    let mut lit = false;
+    for o in gltf.meshes {
+        for p in o.mesh.positions.iter() {
+            let b = AABB::new(*p, *p);
+            bounds = Some(match bounds {
+                Some(a) => surrounding_box(&a, &b),
+                None => b,
+            });
+        }
+        let e = o.mesh.m.e;
+        lit |= e.x.max(e.y).max(e.z) > 0.0;
+        scene.add_mesh(o.mesh);
+    }
+    let (center, radius) = match bounds {
+        Some(b) => ((b.min + b.max) * 0.5, (b.max - b.min).length().sqrt() * 0.5),
+        None => (Vec3::zero(), 1.0),
+    };
+    let radius = radius.max(1e-3);
+    scene.camera = match gltf.camera {
+        Some(c) => c,
+        None => {
+            // glTF の模型は +z を正面にするので, +z 側から見る
+            let mut c = Camera::default();
+            c.vfov = 40.0;
+            c.near = 0.0;
+            c.pos = center + Vec3::new(0.0, 0.0, radius / (c.vfov.to_radians() * 0.5).sin());
+            c.look_at = center;
+            c.up = Vec3::new(0.0, 1.0, 0.0);
+            c
+        }
+    };
+    if !lit {
+        warnings.push(LoadError::new(path, 0, "nothing emits light, adding a white sky"));
+        scene.add(Sphere::new(radius * 1e3, center, Vec3::new(1.0, 1.0, 1.0), Vec3::zero(), Refl::Diff));
+    }
+    Ok((scene, RenderSettings::default(), warnings))
+}
+
+struct Loader<'a> {
+    path: &'a Path,
+    buffers: Vec<gltf::buffer::Data>,
+    images: Vec<gltf::image::Data>,
+    // 画像の番号と sRGB かどうかごとに1つ作る
+    textures: HashMap<(usize, bool), Arc<Texture>>,
+    materials: HashMap<usize, (Material, MeshTextures)>,
+    meshes: Vec<NamedMesh>,
+    camera: Option<Camera>,
+    warnings: Vec<LoadError>,
+}
+
+impl Loader<'_> {
+    // 同じ警告は1度だけ出す
+    fn warn(&mut self, msg: impl Into<String>) {
+        let e = LoadError::new(self.path, 0, msg);
+        if !self.warnings.iter().any(|w| w.msg == e.msg) {
+            self.warnings.push(e);
+        }
+    }
+
+    fn node(&mut self, node: &gltf::Node, parent: Mat4) {
+        // glTF の行列は列ごとに並んでいる
+        let cols = node.transform().matrix();
+        let local = Mat4::new(std::array::from_fn(|r| std::array::from_fn(|c| cols[c][r] as f64)));
+        let world = parent * local;
+        if let Some(cam) = node.camera() {
+            if self.camera.is_none() {
+                self.camera = Some(self.camera(&cam, &world));
+            }
+        }
+        if let Some(mesh) = node.mesh() {
+            if node.skin().is_some() {
+                self.warn("skins are ignored, meshes are drawn in their bind pose");
+            }
+            match Transform::from_matrix(world) {
+                Some(xf) => self.mesh(&mesh, node.name(), &xf),
+                None => self.warn(format!("node {} has a singular transform, skipped", node.index())),
+            }
+        }
+        for child in node.children() {
+            self.node(&child, world);
+        }
+    }
+
+    fn camera(&mut self, cam: &gltf::Camera, world: &Mat4) -> Camera {
+        // カメラは -z を向き, +y が上
+        let mut c = Camera::default();
+        let pos = world.point(Vec3::zero());
+        c.pos = pos;
+        c.look_at = pos + world.dir(Vec3::new(0.0, 0.0, -1.0)).norm();
+        c.up = world.dir(Vec3::new(0.0, 1.0, 0.0)).norm();
+        c.height = 480;
+        match cam.projection() {
+            GltfProjection::Perspective(p) => {
+                c.vfov = (p.yfov() as f64).to_degrees();
+                c.near = p.znear() as f64;
+                if let Some(a) = p.aspect_ratio() {
+                    c.width = (c.height as f64 * a as f64).round().max(1.0) as usize;
+                }
+            }
+            GltfProjection::Orthographic(o) => {
+                c.projection = Projection::Orthographic { height: 2.0 * o.ymag() as f64 };
+                c.near = o.znear() as f64;
+                if o.ymag() > 0.0 {
+                    c.width = (c.height as f64 * (o.xmag() / o.ymag()) as f64).round().max(1.0) as usize;
+                }
+            }
+        }
+        c
+    }
+
+    fn mesh(&mut self, mesh: &gltf::Mesh, node_name: Option<&str>, xf: &Transform) {
+        let name = node_name.or(mesh.name()).map(|s| s.to_string()).unwrap_or_else(|| format!("mesh{}", mesh.index()));
+        let count = mesh.primitives().len();
+        // 鏡映しの変換では三角形の表裏が逆になるので頂点の順を入れ替える
+        let m = &xf.m.m;
+        let det = m[0][0] * (m[1][1] * m[2][2] - m[1][2] * m[2][1]) - m[0][1] * (m[1][0] * m[2][2] - m[1][2] * m[2][0])
+            + m[0][2] * (m[1][0] * m[2][1] - m[1][1] * m[2][0]);
+        for prim in mesh.primitives() {
+            if prim.morph_targets().next().is_some() {
+                self.warn("morph targets are ignored");
+            }
+            let buffers = &self.buffers;
+            let reader = prim.reader(|b| buffers.get(b.index()).map(|d| &d.0[..]));
+            let positions: Vec<Vec3> = match reader.read_positions() {
+                Some(p) => p.map(|p| xf.point(Vec3::new(p[0] as f64, p[1] as f64, p[2] as f64))).collect(),
+                None => continue,
+            };
+            let normals: Vec<Vec3> = reader
+                .read_normals()
+                .map(|n| n.map(|n| xf.normal(Vec3::new(n[0] as f64, n[1] as f64, n[2] as f64)).norm()).collect())
+                .unwrap_or_default();
+            let uvs: Vec<(f64, f64)> = reader
+                .read_tex_coords(0)
+                .map(|t| t.into_f32().map(|t| (t[0] as f64, t[1] as f64)).collect())
+                .unwrap_or_default();
+            let colors: Vec<Color> = reader
+                .read_colors(0)
+                .map(|c| c.into_rgb_f32().map(|c| Vec3::new(c[0] as f64, c[1] as f64, c[2] as f64)).collect())
+                .unwrap_or_default();
+            let idx: Vec<usize> = match reader.read_indices() {
+                Some(i) => i.into_u32().map(|i| i as usize).collect(),
+                None => (0..positions.len()).collect(),
+            };
+            let mut indices: Vec<[usize; 3]> = match prim.mode() {
+                Mode::Triangles => idx.chunks_exact(3).map(|f| [f[0], f[1], f[2]]).collect(),
+                // 帯は1枚おきに向きを揃える
+                Mode::TriangleStrip => (2..idx.len())
+                    .map(|i| if i % 2 == 0 { [idx[i - 2], idx[i - 1], idx[i]] } else { [idx[i - 1], idx[i - 2], idx[i]] })
+                    .collect(),
+                Mode::TriangleFan => (2..idx.len()).map(|i| [idx[0], idx[i - 1], idx[i]]).collect(),
+                _ => {
+                    self.warn("points and lines are not supported, skipped");
+                    continue;
+                }
+            };
+            if indices.iter().flatten().any(|&i| i >= positions.len()) {
+                self.warn(format!("mesh {} has an index out of range, skipped", name));
+                continue;
+            }
+            if det < 0.0 {
+                for f in indices.iter_mut() {
+                    f.swap(1, 2);
+                }
+            }
+            if indices.is_empty() {
+                continue;
+            }
+
+            let (mat, textures) = self.material(&prim.material());
+            let mut out = Mesh::new(positions, indices, mat).with_textures(textures);
+            if out.textures.normal.is_some() && normals.is_empty() {
+                // 法線マップは滑らかな法線を曲げるので, ない場合は作る
+                out.compute_normals();
+            }
+            if !normals.is_empty() && normals.len() == out.positions.len() {
+                out = out.with_normals(normals);
+            }
+            if !uvs.is_empty() && uvs.len() == out.positions.len() {
+                out = out.with_uvs(uvs);
+            }
+            if !colors.is_empty() && colors.len() == out.positions.len() {
+                // 頂点色は基本色に掛ける
+                let c = out.m.c;
+                out = out.with_colors(colors.into_iter().map(|v| v.mult(&c)).collect());
+            }
+            let name = if count > 1 { format!("{}.{}", name, prim.index()) } else { name.clone() };
+            self.meshes.push(NamedMesh { name, mesh: out });
+        }
+    }
+
+    fn material(&mut self, mat: &gltf::Material) -> (Material, MeshTextures) {
+        let index = match mat.index() {
+            Some(i) => i,
+            // マテリアルのないプリミティブは灰色の拡散面
+            None => return (Material::new(Vec3::zero(), Vec3::new(0.75, 0.75, 0.75), Refl::Diff), MeshTextures::default()),
+        };
+        if let Some(m) = self.materials.get(&index) {
+            return m.clone();
+        }
+        let name = mat.name().map(|s| s.to_string()).unwrap_or_else(|| format!("material{}", index));
+        let pbr = mat.pbr_metallic_roughness();
+        let bc = pbr.base_color_factor();
+        let base = Vec3::new(bc[0] as f64, bc[1] as f64, bc[2] as f64);
+        let mut textures = MeshTextures {
+            base_color: pbr.base_color_texture().map(|t| self.texture_info(&t, true)),
+            ..Default::default()
+        };
+
+        let mut metallic = pbr.metallic_factor() as f64;
+        let mut roughness = pbr.roughness_factor() as f64;
+        if let Some(t) = pbr.metallic_roughness_texture() {
+            // metallic は青, roughness は緑
+            let avg = self.texture_info(&t, false).average();
+            metallic *= avg.z;
+            roughness *= avg.y;
+        }
+        let mut transmission = 0.0;
+        if let Some(tr) = mat.transmission() {
+            transmission = tr.transmission_factor() as f64;
+            if let Some(t) = tr.transmission_texture() {
+                transmission *= self.texture_info(&t, false).average().x;
+            }
+        }
+        let refl = if transmission >= 0.5 {
+            Refl::Refr
+        } else if metallic >= 0.5 {
+            Refl::Spec
+        } else {
+            Refl::Diff
+        };
+
+        let ef = mat.emissive_factor();
+        let strength = mat.emissive_strength().unwrap_or(1.0) as f64;
+        let e = Vec3::new(ef[0] as f64, ef[1] as f64, ef[2] as f64) * strength;
+        if e.x.max(e.y).max(e.z) > 0.0 {
+            textures.emissive = mat.emissive_texture().map(|t| self.texture_info(&t, true));
+        }
+        if let Some(n) = mat.normal_texture() {
+            if n.tex_coord() != 0 {
+                self.warn("only TEXCOORD_0 is used for textures");
+            }
+            textures.normal = Some((self.texture(&n.texture(), false), n.scale() as f64));
+        }
+        if mat.alpha_mode() != gltf::material::AlphaMode::Opaque {
+            self.warn(format!("material {}: alpha is ignored, drawn as opaque", name));
+        }
+
+        let mut ior = mat.ior().map_or(GLASS_IOR, |i| i as f64);
+        if ior < 1.0 {
+            self.warn(format!("material {}: ior {} is not supported, using 1.5", name, ior));
+            ior = GLASS_IOR;
+        }
+        let mut m = Material::new(e, base, refl).with_ior(ior);
+        if let Refl::Spec | Refl::Refr = refl {
+            m = m.with_roughness(roughness);
+        }
+        let m = (m, textures);
+        self.materials.insert(index, m.clone());
+        m
+    }
+
+    fn texture_info(&mut self, info: &gltf::texture::Info, srgb: bool) -> Arc<Texture> {
+        if info.tex_coord() != 0 {
+            self.warn("only TEXCOORD_0 is used for textures");
+        }
+        self.texture(&info.texture(), srgb)
+    }
+
+    // 色の画像は sRGB, それ以外は線形のまま
+    fn texture(&mut self, tex: &gltf::Texture, srgb: bool) -> Arc<Texture> {
+        let index = tex.source().index();
+        if let Some(t) = self.textures.get(&(index, srgb)) {
+            return t.clone();
+        }
+        let img = &self.images[index];
+        let (channels, bytes) = match img.format {
+            gltf::image::Format::R8 => (1, 1),
+            gltf::image::Format::R8G8 => (2, 1),
+            gltf::image::Format::R8G8B8 => (3, 1),
+            gltf::image::Format::R8G8B8A8 => (4, 1),
+            gltf::image::Format::R16 => (1, 2),
+            gltf::image::Format::R16G16 => (2, 2),
+            gltf::image::Format::R16G16B16 => (3, 2),
+            gltf::image::Format::R16G16B16A16 => (4, 2),
+            gltf::image::Format::R32G32B32FLOAT => (3, 4),
+            gltf::image::Format::R32G32B32A32FLOAT => (4, 4),
+        };
+        // 値は 0..1 にする. 16bit と浮動小数はこの環境のバイト順で並んでいる
+        let value = |b: &[u8]| -> f64 {
+            match bytes {
+                1 => b[0] as f64 / 255.0,
+                2 => u16::from_ne_bytes([b[0], b[1]]) as f64 / 65535.0,
+                _ => f32::from_ne_bytes([b[0], b[1], b[2], b[3]]) as f64,
+            }
+        };
+        let decode = |x: f64| if srgb { srgb_to_linear(x) } else { x };
+        let texels = img
+            .pixels
+            .chunks_exact(channels * bytes)
+            .map(|px| {
+                let ch = |i: usize| decode(value(&px[i * bytes..(i + 1) * bytes]));
+                // 1, 2チャンネルは灰色 (とアルファ)
+                if channels < 3 { Vec3::new(ch(0), ch(0), ch(0)) } else { Vec3::new(ch(0), ch(1), ch(2)) }
+            })
+            .collect();
+        let wrap = |w: WrappingMode| match w {
+            WrappingMode::Repeat => Wrap::Repeat,
+            WrappingMode::MirroredRepeat => Wrap::Mirror,
+            WrappingMode::ClampToEdge => Wrap::Clamp,
+        };
+        let sampler = tex.sampler();
+        let t = Arc::new(
+            Texture::new(img.width as usize, img.height as usize, texels).with_wrap(wrap(sampler.wrap_s()), wrap(sampler.wrap_t())),
+        );
+        self.textures.insert((index, srgb), t.clone());
+        t
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn malformed_gltf() {
+        assert!(load_gltf(&test_file("not_json.gltf", b"{ not json")).is_err());
+        let e = load_gltf(&test_file("no_scene.gltf", br#"{"asset":{"version":"2.0"}}"#)).err().unwrap();
+        assert!(e.msg.contains("no scene"), "{}", e);
+        let e = load_gltf_scene(&test_file("no_scene2.gltf", br#"{"asset":{"version":"2.0"}}"#)).err().unwrap();
+        assert!(e.msg.contains("no scene"), "{}", e);
+    }
+}
@@ -0,0 +1,364 @@
+use crate::raymod::*;
+
+use std::path::Path;
+
+// 格子状に並んだ高さ. h は 0..1 で nx * nz 個, x が速く回る
+#[derive(Clone, Debug)]
+pub struct HeightMap {
+    pub nx: usize,
+    pub nz: usize,
+    pub h: Vec<f64>,
+}
+
+impl HeightMap {
+    pub fn new(nx: usize, nz: usize, h: Vec<f64>) -> HeightMap {
+        assert!(nx >= 2 && nz >= 2, "heightmap needs at least 2x2 samples");
+        assert_eq!(h.len(), nx * nz, "one height per sample");
+        HeightMap { nx, nz, h }
+    }
+
+    // 8bit, 16bit のグレースケール画像 (カラーなら輝度) を 0..1 の高さにする
+    pub fn load(path: &Path) -> Result<HeightMap, LoadError> {
+        let img = image::open(path).map_err(|e| LoadError::new(path, 0, e.to_string()))?;
+        let img = img.to_luma16();
+        let (w, h) = img.dimensions();
+        if w < 2 || h < 2 {
+            return Err(LoadError::new(path, 0, format!("image is {}x{}, need at least 2x2", w, h)));
+        }
+        let heights = img.pixels().map(|p| p.0[0] as f64 / 65535.0).collect();
+        Ok(HeightMap::new(w as usize, h as usize, heights))
+    }
+
+    // 値ノイズを octaves 回重ねた地形. 同じ seed なら同じ形になる
+    pub fn noise(nx: usize, nz: usize, octaves: usize, seed: u32) -> HeightMap {
+        let mut h = Vec::with_capacity(nx * nz);
+        for j in 0..nz {
+            for i in 0..nx {
+                let (x, z) = (i as f64 / (nx - 1) as f64, j as f64 / (nz - 1) as f64);
+                let mut amp = 1.0;
+                let mut freq = 4.0;
+                let mut v = 0.0;
+                for o in 0..octaves {
+                    v += amp * value_noise(x * freq, z * freq, seed.wrapping_add(o as u32));
+                    amp *= 0.5;
+                    freq *= 2.0;
+                }
+                h.push(v);
+            }
+        }
+        // 0..1 に広げる
+        let min = h.iter().cloned().fold(INF, f64::min);
+        let max = h.iter().cloned().fold(-INF, f64::max);
+        let range = if max > min { max - min } else { 1.0 };
+        for v in h.iter_mut() {
+            *v = (*v - min) / range;
+        }
+        HeightMap::new(nx, nz, h)
+    }
+
+    // 縁で0になるように (1-u²)(1-v²) を掛け (u, v は中心から縁までを -1..1), 一番高いところを 1 に戻す
+    // 格子の端が崖にならず, 島や山並みのように裾が下がる
+    pub fn fade_edges(mut self) -> HeightMap {
+        let (nx, nz) = (self.nx, self.nz);
+        for j in 0..nz {
+            for i in 0..nx {
+                let u = 2.0 * i as f64 / (nx - 1) as f64 - 1.0;
+                let v = 2.0 * j as f64 / (nz - 1) as f64 - 1.0;
+                self.h[i + j * nx] *= (1.0 - u * u) * (1.0 - v * v);
+            }
+        }
+        let max = self.h.iter().cloned().fold(0.0, f64::max);
+        if max > 0.0 {
+            for v in self.h.iter_mut() {
+                *v /= max;
+            }
+        }
+        self
+    }
+}
+
+// 格子点ごとの乱数を滑らかにつないだもの. 0..1
+fn value_noise(x: f64, z: f64, seed: u32) -> f64 {
+    let hash = |i: i64, j: i64| {
+        let mut n = (i as u32).wrapping_mul(374761393) ^ (j as u32).wrapping_mul(668265263) ^ seed.wrapping_mul(2246822519);
+        n = (n ^ (n >> 13)).wrapping_mul(1274126177);
+        (n ^ (n >> 16)) as f64 / u32::MAX as f64
+    };
+    let (i, j) = (x.floor() as i64, z.floor() as i64);
+    let (fx, fz) = (x - x.floor(), z - z.floor());
+    let s = |t: f64| t * t * (3.0 - 2.0 * t);
+    let (sx, sz) = (s(fx), s(fz));
+    let a = hash(i, j) + (hash(i + 1, j) - hash(i, j)) * sx;
+    let b = hash(i, j + 1) + (hash(i + 1, j + 1) - hash(i, j + 1)) * sx;
+    a + (b - a) * sz
+}
+
+// 高さの格子を三角形2枚ずつの地面として置いたもの
+// origin が格子の (0,0) で高さ0の位置, size が x, z の広がりと高さ1のときの y
+// レイは xz 平面上で格子を1マスずつ辿り, マスの高さの範囲に入るときだけ三角形を調べる
+#[derive(Clone, Debug)]
+pub struct Heightfield {
+    nx: usize,
+    nz: usize,
+    // ワールドの y
+    y: Vec<f64>,
+    normals: Vec<Vec3>,
+    // マスごとの高さの最小と最大
+    cell_range: Vec<(f64, f64)>,
+    origin: Vec3,
+    dx: f64,
+    dz: f64,
+    bbox: AABB,
+    pub m: Material,
+}
+
+impl Heightfield {
+    pub fn new(map: &HeightMap, origin: Vec3, size: Vec3, m: Material) -> Heightfield {
+        let (nx, nz) = (map.nx, map.nz);
+        let dx = size.x / (nx - 1) as f64;
+        let dz = size.z / (nz - 1) as f64;
+        let y: Vec<f64> = map.h.iter().map(|h| origin.y + h * size.y).collect();
+        let at = |i: usize, j: usize| y[i + j * nx];
+
+        // 中心差分の法線. 端では片側の差分
+        let mut normals = Vec::with_capacity(nx * nz);
+        for j in 0..nz {
+            for i in 0..nx {
+                let (i0, i1) = (i.saturating_sub(1), (i + 1).min(nx - 1));
+                let (j0, j1) = (j.saturating_sub(1), (j + 1).min(nz - 1));
+                let gx = (at(i1, j) - at(i0, j)) / ((i1 - i0) as f64 * dx);
+                let gz = (at(i, j1) - at(i, j0)) / ((j1 - j0) as f64 * dz);
+                normals.push(Vec3::new(-gx, 1.0, -gz).norm());
+            }
+        }
+
+        let mut cell_range = Vec::with_capacity((nx - 1) * (nz - 1));
+        for j in 0..nz - 1 {
+            for i in 0..nx - 1 {
+                let c = [at(i, j), at(i + 1, j), at(i, j + 1), at(i + 1, j + 1)];
+                let lo = c.iter().cloned().fold(INF, f64::min);
+                let hi = c.iter().cloned().fold(-INF, f64::max);
+                cell_range.push((lo, hi));
+            }
+        }
+
+        let lo = y.iter().cloned().fold(INF, f64::min);
+        let hi = y.iter().cloned().fold(-INF, f64::max);
+        let bbox = AABB::new(
+            Vec3::new(origin.x, lo - EPS, origin.z),
+            Vec3::new(origin.x + size.x, hi + EPS, origin.z + size.z),
+        );
+        Heightfield {
+            nx,
+            nz,
+            y,
+            normals,
+            cell_range,
+            origin,
+            dx,
+            dz,
+            bbox,
+            m,
+        }
+    }
+
+    // new に渡せば同じ地形になる高さの格子と origin, size
+    pub fn grid(&self) -> (HeightMap, Vec3, Vec3) {
+        let hi = self.y.iter().cloned().fold(-INF, f64::max);
+        let sy = if hi > self.origin.y { hi - self.origin.y } else { 1.0 };
+        let h = self.y.iter().map(|y| (y - self.origin.y) / sy).collect();
+        let size = Vec3::new(self.dx * (self.nx - 1) as f64, sy, self.dz * (self.nz - 1) as f64);
+        (HeightMap::new(self.nx, self.nz, h), self.origin, size)
+    }
+
+    fn vertex(&self, i: usize, j: usize) -> Vec3 {
+        Vec3::new(
+            self.origin.x + i as f64 * self.dx,
+            self.y[i + j * self.nx],
+            self.origin.z + j as f64 * self.dz,
+        )
+    }
+
+    // マス (i, j) の三角形2枚 (a, c, b), (b, c, d) のうち近い方
+    fn hit_cell(&self, ray: &Ray, i: usize, j: usize, t_max: f64) -> Option<HitInfo<'_>> {
+        let k = [(i, j), (i, j + 1), (i + 1, j), (i + 1, j + 1)];
+        let mut best: Option<(f64, [usize; 3], f64, f64)> = None;
+        for tri in [[0, 1, 2], [2, 1, 3]] {
+            let p = tri.map(|c| self.vertex(k[c].0, k[c].1));
+            let limit = best.map_or(t_max, |b| b.0);
+            if let Some((t, u, v)) = triangle_hit(ray, p[0], p[1], p[2], limit) {
+                let idx = [0, 1, 2].map(|c| k[tri[c]].0 + k[tri[c]].1 * self.nx);
+                best = Some((t, idx, u, v));
+            }
+        }
+        let (t, idx, u, v) = best?;
+        let w = 1.0 - u - v;
+        let n = (self.normals[idx[0]] * w + self.normals[idx[1]] * u + self.normals[idx[2]] * v).norm();
+        let x = ray.o + ray.d * t;
+        let uv = (
+            (x.x - self.origin.x) / (self.dx * (self.nx - 1) as f64),
+            (x.z - self.origin.z) / (self.dz * (self.nz - 1) as f64),
+        );
+        Some(HitInfo::new(t, x, n, &self.m).with_uv(uv.0, uv.1))
+    }
+}
+
+// Möller–Trumbore. (t, u, v) を返す
+#[inline]
+fn triangle_hit(ray: &Ray, p0: Vec3, p1: Vec3, p2: Vec3, t_max: f64) -> Option<(f64, f64, f64)> {
+    let e1 = p1 - p0;
+    let e2 = p2 - p0;
+    let pv = ray.d % e2;
+    let det = e1.dot(&pv);
+    if det.abs() < 1e-12 {
+        return None;
+    }
+    let inv_det = 1.0 / det;
+    let tv = ray.o - p0;
+    let u = tv.dot(&pv) * inv_det;
+    if !(0.0..=1.0).contains(&u) {
+        return None;
+    }
+    let qv = tv % e1;
+    let v = ray.d.dot(&qv) * inv_det;
+    if v < 0.0 || u + v > 1.0 {
+        return None;
+    }
+    let t = e2.dot(&qv) * inv_det;
+    if t < EPS || t >= t_max {
+        return None;
+    }
+    Some((t, u, v))
+}
+
+impl Shape for Heightfield {
+    fn intersect(&self, ray: &Ray, t_max: f64) -> Option<HitInfo<'_>> {
+        // 箱の中にある区間
+        let mut t0: f64 = 0.0;
+        let mut t1 = t_max;
+        for a in 0..3 {
+            // 軸に平行なら 0 * inf が NaN になるので, 始点が板の中かだけを見る
+            if ray.d[a].abs() < 1e-12 {
+                if ray.o[a] < self.bbox.min[a] || ray.o[a] > self.bbox.max[a] {
+                    return None;
+                }
+                continue;
+            }
+            let inv = 1.0 / ray.d[a];
+            let n = (self.bbox.min[a] - ray.o[a]) * inv;
+            let f = (self.bbox.max[a] - ray.o[a]) * inv;
+            let (n, f) = if n < f { (n, f) } else { (f, n) };
+            t0 = t0.max(n);
+            t1 = t1.min(f);
+            if t1 < t0 {
+                return None;
+            }
+        }
+
+        // 入った点のマスから xz 平面の DDA で辿る
+        let (cx, cz) = (self.nx - 1, self.nz - 1);
+        let p = ray.o + ray.d * t0;
+        let gx = (p.x - self.origin.x) / self.dx;
+        let gz = (p.z - self.origin.z) / self.dz;
+        let mut i = (gx.floor().max(0.0) as usize).min(cx - 1);
+        let mut j = (gz.floor().max(0.0) as usize).min(cz - 1);
+
+        let axis = |d: f64, o: f64, cell: usize, size: f64, origin: f64| -> (f64, f64) {
+            // (次の境界までの t, 1マス進む t)
+            if d > 0.0 {
+                (((cell + 1) as f64 * size + origin - o) / d, size / d)
+            } else if d < 0.0 {
+                ((cell as f64 * size + origin - o) / d, -size / d)
+            } else {
+                (INF, INF)
+            }
+        };
+        let (mut next_x, step_x) = axis(ray.d.x, ray.o.x, i, self.dx, self.origin.x);
+        let (mut next_z, step_z) = axis(ray.d.z, ray.o.z, j, self.dz, self.origin.z);
+
+        let mut t_in = t0;
+        loop {
+            let t_out = next_x.min(next_z).min(t1);
+            // この区間でのレイの高さがマスの高さの範囲にかかるときだけ調べる
+            let (y0, y1) = (ray.o.y + ray.d.y * t_in, ray.o.y + ray.d.y * t_out);
+            let (lo, hi) = self.cell_range[i + j * cx];
+            if y0.min(y1) <= hi + EPS && y0.max(y1) >= lo - EPS {
+                if let Some(h) = self.hit_cell(ray, i, j, t_max) {
+                    return Some(h);
+                }
+            }
+            if t_out >= t1 {
+                return None;
+            }
+            if next_x < next_z {
+                if ray.d.x > 0.0 {
+                    i += 1;
+                    if i >= cx {
+                        return None;
+                    }
+                } else {
+                    if i == 0 {
+                        return None;
+                    }
+                    i -= 1;
+                }
+                t_in = next_x;
+                next_x += step_x;
+            } else {
+                if ray.d.z > 0.0 {
+                    j += 1;
+                    if j >= cz {
+                        return None;
+                    }
+                } else {
+                    if j == 0 {
+                        return None;
+                    }
+                    j -= 1;
+                }
+                t_in = next_z;
+                next_z += step_z;
+            }
+        }
+    }
+
+    fn bounding_box(&self) -> Option<AABB> {
+        Some(self.bbox)
+    }
+
+    // 格子をいくつかたどって三角形を調べる
+    fn cost(&self) -> f64 {
+        8.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn slope() -> Heightfield {
+        // x に沿って 0 から 1 へ上がる坂
+        let h = (0..9).map(|k| (k % 3) as f64 / 2.0).collect();
+        let m = Material::new(Vec3::zero(), Vec3::new(0.5, 0.5, 0.5), Refl::Diff);
+        Heightfield::new(&HeightMap::new(3, 3, h), Vec3::zero(), Vec3::new(2.0, 1.0, 2.0), m)
+    }
+
+    #[test]
+    fn straight_down_ray_hits() {
+        // d.x = d.z = 0 のレイで前は 0 * inf の NaN になっていた
+        let f = slope();
+        let h = f.intersect(&Ray::new(Vec3::new(0.5, 5.0, 0.7), Vec3::new(0.0, -1.0, 0.0)), INF).unwrap();
+        assert!((h.t - 4.75).abs() < 1e-9);
+        assert!((h.n - Vec3::new(-0.5, 1.0, 0.0).norm()).length() < 1e-9);
+        // 外から真下へ向かうレイは当たらない
+        assert!(f.intersect(&Ray::new(Vec3::new(3.0, 5.0, 0.7), Vec3::new(0.0, -1.0, 0.0)), INF).is_none());
+    }
+
+    #[test]
+    fn grazing_ray_from_the_side() {
+        let f = slope();
+        let h = f.intersect(&Ray::new(Vec3::new(5.0, 0.5, 1.0), Vec3::new(-1.0, 0.0, 0.0)), INF).unwrap();
+        assert!((h.p.x - 1.0).abs() < 1e-9);
+        assert!(f.intersect(&Ray::new(Vec3::new(5.0, 1.5, 1.0), Vec3::new(-1.0, 0.0, 0.0)), INF).is_none());
+    }
+}
@@ -0,0 +1,171 @@
+use crate::raymod::*;
+
+use std::sync::Arc;
+
+// 使い回す形状のまとまり. 自分のBVHを持つので Instance から何度参照しても木は1つ
+#[derive(Debug)]
+pub struct Group {
+    pub objects: Vec<Primitive>,
+    bvh: BVH,
+    bbox: Option<AABB>,
+    cost: f64,
+}
+
+impl Group {
+    pub fn new(objects: Vec<Primitive>) -> Group {
+        let bvh = BVH::new(&objects, DEFAULT_LEAF_SIZE);
+        // 1つでも箱を持たないものがあれば全体も無限に広がる
+        let bbox = objects
+            .iter()
+            .try_fold(AABB::empty(), |b, o| Some(surrounding_box(&b, &o.bounding_box()?)));
+        let cost = bvh.cost(&objects);
+        Group { objects, bvh, bbox, cost }
+    }
+
+    pub fn from_mesh(mesh: Mesh) -> Group {
+        Group::new(Mesh::triangles(Arc::new(mesh)).map(Primitive::from).collect())
+    }
+}
+
+impl Shape for Group {
+    fn intersect(&self, ray: &Ray, t_max: f64) -> Option<HitInfo<'_>> {
+        self.bvh.intersect(&self.objects, ray, t_max)
+    }
+
+    fn bounding_box(&self) -> Option<AABB> {
+        self.bbox
+    }
+
+    fn cost(&self) -> f64 {
+        self.cost
+    }
+}
+
+// Group を変換して置いたもの. レイを物体の座標に戻して調べる
+#[derive(Clone, Debug)]
+pub struct Instance {
+    pub geom: Arc<Group>,
+    xf: Transform,
+    bbox: Option<AABB>,
+}
+
+impl Instance {
+    pub fn new(geom: Arc<Group>, xf: Transform) -> Instance {
+        let bbox = geom.bounding_box().map(|b| xf.bounds(&b));
+        Instance { geom, xf, bbox }
+    }
+
+    pub fn transform(&self) -> Transform {
+        self.xf
+    }
+}
+
+impl Shape for Instance {
+    #[inline]
+    fn intersect(&self, ray: &Ray, t_max: f64) -> Option<HitInfo<'_>> {
+        intersect_transformed(&self.geom, &self.xf, ray, t_max)
+    }
+
+    fn bounding_box(&self) -> Option<AABB> {
+        self.bbox
+    }
+
+    // レイを変換する分を足す
+    fn cost(&self) -> f64 {
+        1.0 + self.geom.cost()
+    }
+}
+
+// 逆行列でレイを物体の座標に戻して geom と調べる
+#[inline]
+pub fn intersect_transformed<'a>(geom: &'a Group, xf: &Transform, ray: &Ray, t_max: f64) -> Option<HitInfo<'a>> {
+    let inv = &xf.inv;
+    // 物体の座標でも方向は単位ベクトルにし, t は長さの比で換算する
+    let d = inv.dir(ray.d);
+    let len = d.dot(&d).sqrt();
+    let local = Ray::timed(inv.point(ray.o), d * (1.0 / len), ray.time);
+    let limit = if t_max >= INF { INF } else { t_max * len };
+    let mut hit = geom.intersect(&local, limit)?;
+    hit.t /= len;
+    hit.p = ray.o + ray.d * hit.t;
+    hit.n = inv.dir_transposed(hit.n).norm();
+    // 接線は面に沿った向きなので, 法線と違って行列そのもので移す
+    hit.tangent = hit.tangent.map(|t| xf.m.dir(t).norm());
+    Some(hit)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rays() -> Vec<Ray> {
+        // 原点付近を見込む向きのレイを格子状に
+        let mut rays = vec![];
+        for i in 0..15 {
+            for j in 0..15 {
+                let o = Vec3::new(-10.0 + i as f64 * 0.3, 20.0, -15.0 + j as f64 * 0.4);
+                let target = Vec3::new(3.0 + 0.2 * j as f64, -1.0, -4.0 + 0.3 * i as f64);
+                rays.push(Ray::new(o, (target - o).norm()));
+            }
+        }
+        rays
+    }
+
+    fn assert_same_hit(a: Option<HitInfo>, b: Option<HitInfo>) -> bool {
+        match (a, b) {
+            (Some(a), Some(b)) => {
+                assert!((a.t - b.t).abs() < 1e-9, "{} vs {}", a.t, b.t);
+                assert!((a.p - b.p).length() < 1e-16);
+                assert!((a.n - b.n).length() < 1e-16);
+                true
+            }
+            (None, None) => false,
+            (a, b) => panic!("{:?} vs {:?}", a.map(|h| h.t), b.map(|h| h.t)),
+        }
+    }
+
+    #[test]
+    fn instanced_sphere_matches_transformed_sphere() {
+        let z = Vec3::zero();
+        let c = Vec3::new(0.5, 0.5, 0.5);
+        let unit = Arc::new(Group::new(vec![Sphere::new(1.0, z, z, c, Refl::Diff).into()]));
+        let center = Vec3::new(5.0, 1.0, -3.0);
+        let xf = Transform::translate(center) * Transform::rotate_y(0.4) * Transform::uniform_scale(2.5);
+        let inst = Instance::new(unit, xf);
+        let sphere = Sphere::new(2.5, center, z, c, Refl::Diff);
+        let mut hits = 0;
+        for r in rays() {
+            if assert_same_hit(inst.intersect(&r, INF), sphere.intersect(&r, INF)) {
+                hits += 1;
+            }
+            // t_max も世界の長さで効く
+            assert_same_hit(inst.intersect(&r, 20.0), sphere.intersect(&r, 20.0));
+        }
+        assert!(hits > 20);
+    }
+
+    #[test]
+    fn instanced_rect_matches_transformed_rect() {
+        // 平行四辺形はアフィン変換しても平行四辺形なので, 角と辺を移したものと同じになる
+        let z = Vec3::zero();
+        let c = Vec3::new(0.5, 0.5, 0.5);
+        let (q, u, v) = (Vec3::new(-1.0, 0.0, -1.0), Vec3::new(2.0, 0.0, 0.0), Vec3::new(0.0, 0.3, 2.0));
+        let unit = Arc::new(Group::new(vec![Rect::new(q, u, v, z, c, Refl::Diff).into()]));
+        let xf = Transform::translate(Vec3::new(4.0, -1.0, -2.0))
+            * Transform::rotate(Vec3::new(1.0, 0.0, 1.0), 0.3)
+            * Transform::scale(Vec3::new(3.0, 1.0, 0.5));
+        let inst = Instance::new(unit, xf);
+        let rect = Rect::new(xf.point(q), xf.dir(u), xf.dir(v), z, c, Refl::Diff);
+        let mut hits = 0;
+        for r in rays() {
+            let (a, b) = (inst.intersect(&r, INF), rect.intersect(&r, INF));
+            if let (Some(a), Some(b)) = (&a, &b) {
+                assert!((a.uv.0 - b.uv.0).abs() < 1e-9 && (a.uv.1 - b.uv.1).abs() < 1e-9);
+            }
+            if assert_same_hit(a, b) {
+                hits += 1;
+            }
+        }
+        assert!(hits > 20);
+    }
+}
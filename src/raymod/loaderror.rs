@@ -0,0 +1,42 @@
+use std::fmt;
+use std::path::Path;
+
+// ファイル読み込みのエラー. どのファイルの何行目かを持つ
+#[derive(Debug)]
+pub struct LoadError {
+    pub file: String,
+    pub line: usize,
+    pub msg: String,
+}
+
+impl LoadError {
+    pub fn new(file: &Path, line: usize, msg: impl Into<String>) -> LoadError {
+        LoadError {
+            file: file.display().to_string(),
+            line,
+            msg: msg.into(),
+        }
+    }
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.line > 0 {
+            write!(f, "{}:{}: {}", self.file, self.line, self.msg)
+        } else {
+            write!(f, "{}: {}", self.file, self.msg)
+        }
+    }
+}
+
+impl std::error::Error for LoadError {}
+
+// テストで読ませるファイルを一時ディレクトリに書く. name はテストごとに変える
+#[cfg(test)]
+pub(crate) fn test_file(name: &str, data: &[u8]) -> std::path::PathBuf {
+    let dir = std::env::temp_dir().join(format!("rust_smallpt_test_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join(name);
+    std::fs::write(&path, data).unwrap();
+    path
+}
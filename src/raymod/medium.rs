@@ -0,0 +1,189 @@
+use crate::raymod::*;
+
+// 物体の外 (何の中にもいないとき) の屈折率
+pub const AIR_IOR: f64 = 1.0;
+
+// Refr の物体の中身
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Medium {
+    pub ior: f64,
+    pub priority: u32,
+}
+
+impl Medium {
+    pub fn of(m: &Material) -> Medium {
+        Medium {
+            ior: m.ior,
+            priority: m.priority,
+        }
+    }
+}
+
+// レイが今どの Refr の中にいるか. 入った順に積み, 出たら取り除く
+// 重なったところでは priority が最大のもの (同じなら後から入った方) の中にいるとみなす
+// 水に沈めたガラスのように入れ子になった物体の境目で, 両側の屈折率を正しく選ぶのに使う
+#[derive(Clone, Debug, Default)]
+pub struct MediumStack {
+    media: Vec<Medium>,
+}
+
+// Refr の面にぶつかったときにどうするか
+pub enum Crossing {
+    // 屈折する. 手前と向こうの屈折率と, 面を越えた後の積み方
+    Refract { n1: f64, n2: f64, inside: MediumStack },
+    // 重なった中で優先度の低い面か, 両側の屈折率が同じ面. 曲がらずに通り抜ける
+    Pass(MediumStack),
+}
+
+impl MediumStack {
+    pub fn current(&self) -> Option<Medium> {
+        // max_by_key は同じ値なら最後のものを返す
+        self.media.iter().copied().max_by_key(|m| m.priority)
+    }
+
+    pub fn ior(&self) -> f64 {
+        self.current().map_or(AIR_IOR, |m| m.ior)
+    }
+
+    fn with(&self, m: Medium) -> MediumStack {
+        let mut media = self.media.clone();
+        media.push(m);
+        MediumStack { media }
+    }
+
+    // 同じ中身のものは区別しなくてよいので, 最後に入ったものを取り除く
+    fn without(&self, m: Medium) -> MediumStack {
+        let mut media = self.media.clone();
+        if let Some(i) = media.iter().rposition(|k| *k == m) {
+            media.remove(i);
+        }
+        MediumStack { media }
+    }
+
+    // into は法線の外側から当たったとき
+    pub fn cross(&self, m: &Material, into: bool) -> Crossing {
+        let me = Medium::of(m);
+        let rest = self.without(me);
+        if rest.current().is_some_and(|k| k.priority > me.priority) {
+            return Crossing::Pass(if into { self.with(me) } else { rest });
+        }
+        let (n1, n2, inside) = if into {
+            (self.ior(), me.ior, self.with(me))
+        } else {
+            let n2 = rest.ior();
+            (me.ior, n2, rest)
+        };
+        if n1 == n2 {
+            Crossing::Pass(inside)
+        } else {
+            Crossing::Refract { n1, n2, inside }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn refr(ior: f64, priority: u32) -> Material {
+        Material::new(Vec3::zero(), Vec3::new(1.0, 1.0, 1.0), Refl::Refr)
+            .with_ior(ior)
+            .with_priority(priority)
+    }
+
+    fn refract(c: Crossing) -> (f64, f64, MediumStack) {
+        match c {
+            Crossing::Refract { n1, n2, inside } => (n1, n2, inside),
+            Crossing::Pass(_) => panic!("expected a refraction"),
+        }
+    }
+
+    fn pass(c: Crossing) -> MediumStack {
+        match c {
+            Crossing::Pass(inside) => inside,
+            Crossing::Refract { n1, n2, .. } => panic!("expected a pass, got {} -> {}", n1, n2),
+        }
+    }
+
+    #[test]
+    fn glass_in_water() {
+        let water = refr(1.33, 0);
+        let glass = refr(1.5, 1);
+        let air = MediumStack::default();
+        let (n1, n2, in_water) = refract(air.cross(&water, true));
+        assert_eq!((n1, n2), (1.0, 1.33));
+        // 水の中からガラスに入り, 出ると水に戻る
+        let (n1, n2, in_glass) = refract(in_water.cross(&glass, true));
+        assert_eq!((n1, n2), (1.33, 1.5));
+        assert_eq!(in_glass.ior(), 1.5);
+        let (n1, n2, back) = refract(in_glass.cross(&glass, false));
+        assert_eq!((n1, n2), (1.5, 1.33));
+        let (n1, n2, out) = refract(back.cross(&water, false));
+        assert_eq!((n1, n2), (1.33, 1.0));
+        assert!(out.current().is_none());
+    }
+
+    #[test]
+    fn lower_priority_surface_passes() {
+        // ガラスが水に半分沈んでいて, ガラスの中で水面を越える
+        let water = refr(1.33, 0);
+        let glass = refr(1.5, 1);
+        let (_, _, in_glass) = refract(MediumStack::default().cross(&glass, true));
+        let both = pass(in_glass.cross(&water, true));
+        assert_eq!(both.ior(), 1.5);
+        // 水の中でガラスから出ると水に入る
+        let (n1, n2, in_water) = refract(both.cross(&glass, false));
+        assert_eq!((n1, n2), (1.5, 1.33));
+        // ガラスの中で水から出ても曲がらず, ガラスの中のまま
+        let only_glass = pass(both.cross(&water, false));
+        assert_eq!(only_glass.ior(), 1.5);
+        assert_eq!(in_water.ior(), 1.33);
+        // 屈折率が同じ面も通り抜ける
+        let same = refr(1.33, 0);
+        assert_eq!(pass(in_water.cross(&same, true)).ior(), 1.33);
+    }
+
+    #[test]
+    fn leaving_a_medium_never_entered() {
+        // 始点が中にあるレイは積まずに始まるので, 出るときは空気へ出る
+        let glass = refr(1.5, 0);
+        let (n1, n2, out) = refract(MediumStack::default().cross(&glass, false));
+        assert_eq!((n1, n2), (1.5, 1.0));
+        assert!(out.current().is_none());
+        // 他の物の中にいるなら, そこへ出る. 積まれているものは取り除かない
+        let water = refr(1.33, 0);
+        let (_, _, in_water) = refract(MediumStack::default().cross(&water, true));
+        let (n1, n2, out) = refract(in_water.cross(&glass, false));
+        assert_eq!((n1, n2), (1.5, 1.33));
+        assert_eq!(out.ior(), 1.33);
+        // 優先度の高いものの中なら通り抜ける
+        let high = refr(1.7, 5);
+        let (_, _, in_high) = refract(MediumStack::default().cross(&high, true));
+        assert_eq!(pass(in_high.cross(&glass, false)).ior(), 1.7);
+    }
+
+    #[test]
+    fn builtin_water_is_water() {
+        // モデル3と4の水面を上から見下ろし, 当たった Refr がガラスでなく水になっていること
+        for n in [3, 4] {
+            let mut scene = load_builtin_scene(n).unwrap().unwrap().0;
+            scene.build_bvh(DEFAULT_LEAF_SIZE);
+            let mut hits = 0;
+            for i in 0..40 {
+                for j in 0..40 {
+                    let o = Vec3::new(-600.0 + i as f64 * 40.0, 500.0, -1800.0 + j as f64 * 55.0);
+                    let Some(h) = scene.intersect(&Ray::new(o, Vec3::new(0.0, -1.0, 0.0))) else {
+                        continue;
+                    };
+                    if !matches!(h.m.refl, Refl::Refr) {
+                        continue;
+                    }
+                    let (n1, n2, _) = refract(MediumStack::default().cross(h.m, h.n.y > 0.0));
+                    assert_eq!((n1, n2), (AIR_IOR, 1.33), "model {}", n);
+                    hits += 1;
+                }
+            }
+            assert!(hits > 20, "model {}: {} hits", n, hits);
+        }
+    }
+}
@@ -0,0 +1,293 @@
+use crate::raymod::*;
+
+use std::f64::consts::*;
+use std::path::Path;
+use std::sync::Arc;
+
+// 頂点を共有する三角形メッシュ
+// normals, uvs, colors は空か positions と同じ長さ
+// colors があればマテリアルの c の代わりに拡散色として使う
+#[derive(Clone, Debug)]
+pub struct Mesh {
+    pub positions: Vec<Vec3>,
+    pub normals: Vec<Vec3>,
+    pub uvs: Vec<(f64, f64)>,
+    pub colors: Vec<Color>,
+    pub indices: Vec<[usize; 3]>,
+    pub m: Material,
+    pub textures: MeshTextures,
+}
+
+// メッシュに貼るテクスチャ. どれも uvs で引くので, uvs がなければ使わない
+#[derive(Clone, Debug, Default)]
+pub struct MeshTextures {
+    // 拡散色 (頂点色かマテリアルの c) に掛ける
+    pub base_color: Option<Arc<Texture>>,
+    // マテリアルの e に掛ける
+    pub emissive: Option<Arc<Texture>>,
+    // 接空間の法線と, xy に掛ける強さ
+    pub normal: Option<(Arc<Texture>, f64)>,
+}
+
+// ファイルから読んだメッシュと, その中での名前
+pub struct NamedMesh {
+    pub name: String,
+    pub mesh: Mesh,
+}
+
+impl Mesh {
+    pub fn new(positions: Vec<Vec3>, indices: Vec<[usize; 3]>, m: Material) -> Mesh {
+        Mesh {
+            positions,
+            normals: vec![],
+            uvs: vec![],
+            colors: vec![],
+            indices,
+            m,
+            textures: MeshTextures::default(),
+        }
+    }
+
+    pub fn with_normals(mut self, normals: Vec<Vec3>) -> Mesh {
+        assert_eq!(normals.len(), self.positions.len(), "one normal per vertex");
+        self.normals = normals;
+        self
+    }
+
+    pub fn with_uvs(mut self, uvs: Vec<(f64, f64)>) -> Mesh {
+        assert_eq!(uvs.len(), self.positions.len(), "one uv per vertex");
+        self.uvs = uvs;
+        self
+    }
+
+    pub fn with_colors(mut self, colors: Vec<Color>) -> Mesh {
+        assert_eq!(colors.len(), self.positions.len(), "one color per vertex");
+        self.colors = colors;
+        self
+    }
+
+    pub fn with_textures(mut self, textures: MeshTextures) -> Mesh {
+        self.textures = textures;
+        self
+    }
+
+    // 面積で重み付けした頂点法線を作る. 滑らかに見せたいが法線を持たない場合に使う
+    pub fn compute_normals(&mut self) {
+        let mut normals = vec![Vec3::zero(); self.positions.len()];
+        for f in self.indices.iter() {
+            let p0 = self.positions[f[0]];
+            let n = (self.positions[f[1]] - p0) % (self.positions[f[2]] - p0);
+            for &i in f.iter() {
+                normals[i] = normals[i] + n;
+            }
+        }
+        self.normals = normals
+            .into_iter()
+            .map(|n| if n.length() > 0.0 { n.norm() } else { n })
+            .collect();
+    }
+
+    pub fn triangles(mesh: Arc<Mesh>) -> impl Iterator<Item = Triangle> {
+        (0..mesh.indices.len()).map(move |i| Triangle::new(mesh.clone(), i))
+    }
+
+    // 緯度経度で分割した球. 頂点法線とUVを持つ
+    pub fn uv_sphere(c: Vec3, rad: f64, slices: usize, stacks: usize, m: Material) -> Mesh {
+        let mut positions = vec![];
+        let mut normals = vec![];
+        let mut uvs = vec![];
+        for j in 0..=stacks {
+            let v = j as f64 / stacks as f64;
+            let theta = v * PI;
+            for i in 0..=slices {
+                let u = i as f64 / slices as f64;
+                let phi = u * 2.0 * PI;
+                let n = Vec3::new(theta.sin() * phi.cos(), theta.cos(), -theta.sin() * phi.sin());
+                positions.push(c + n * rad);
+                normals.push(n);
+                uvs.push((u, 1.0 - v));
+            }
+        }
+        let mut indices = vec![];
+        let row = slices + 1;
+        for j in 0..stacks {
+            for i in 0..slices {
+                let a = j * row + i;
+                let b = a + row;
+                if j != 0 {
+                    indices.push([a, b, a + 1]);
+                }
+                if j != stacks - 1 {
+                    indices.push([a + 1, b, b + 1]);
+                }
+            }
+        }
+        Mesh::new(positions, indices, m)
+            .with_normals(normals)
+            .with_uvs(uvs)
+    }
+}
+
+// 拡張子で形式を選んで読む
+pub fn load_mesh_file(path: &Path) -> Result<Vec<NamedMesh>, LoadError> {
+    let ext = path
+        .extension()
+        .map(|e| e.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+    let name = path
+        .file_stem()
+        .map(|s| s.to_string_lossy().to_string())
+        .unwrap_or_default();
+    match ext.as_str() {
+        "obj" => load_obj(path),
+        "ply" => Ok(vec![NamedMesh { name, mesh: load_ply(path)? }]),
+        "stl" => Ok(vec![NamedMesh { name, mesh: load_stl(path)? }]),
+        // カメラはいらないので捨てる. 警告はここで出す
+        "gltf" | "glb" => {
+            let gltf = load_gltf(path)?;
+            for w in gltf.warnings {
+                eprintln!("warning: {}", w);
+            }
+            Ok(gltf.meshes)
+        }
+        _ => Err(LoadError::new(path, 0, "unknown mesh format (use .obj, .ply, .stl, .gltf or .glb)")),
+    }
+}
+
+// メッシュの中の1枚. メッシュ本体は Arc で共有する
+#[derive(Clone, Debug)]
+pub struct Triangle {
+    pub mesh: Arc<Mesh>,
+    pub i: usize,
+}
+
+impl Triangle {
+    pub fn new(mesh: Arc<Mesh>, i: usize) -> Triangle {
+        Triangle { mesh, i }
+    }
+
+    fn vertices(&self) -> (Vec3, Vec3, Vec3) {
+        let f = &self.mesh.indices[self.i];
+        let p = &self.mesh.positions;
+        (p[f[0]], p[f[1]], p[f[2]])
+    }
+}
+
+impl Shape for Triangle {
+    // Möller–Trumbore
+    #[inline]
+    fn intersect(&self, ray: &Ray, t_max: f64) -> Option<HitInfo<'_>> {
+        let (p0, p1, p2) = self.vertices();
+        let e1 = p1 - p0;
+        let e2 = p2 - p0;
+        let pv = ray.d % e2;
+        let det = e1.dot(&pv);
+        if det.abs() < 1e-12 {
+            return None;
+        }
+        let inv_det = 1.0 / det;
+        let tv = ray.o - p0;
+        let u = tv.dot(&pv) * inv_det;
+        if !(0.0..=1.0).contains(&u) {
+            return None;
+        }
+        let qv = tv % e1;
+        let v = ray.d.dot(&qv) * inv_det;
+        if v < 0.0 || u + v > 1.0 {
+            return None;
+        }
+        let t = e2.dot(&qv) * inv_det;
+        if t < EPS || t >= t_max {
+            return None;
+        }
+
+        let mesh = &*self.mesh;
+        let f = &mesh.indices[self.i];
+        let w = 1.0 - u - v;
+        let ng = (e1 % e2).norm();
+        let n = if mesh.normals.is_empty() {
+            ng
+        } else {
+            let ns = (mesh.normals[f[0]] * w + mesh.normals[f[1]] * u + mesh.normals[f[2]] * v).norm();
+            // 頂点法線が面の裏を向いていたら面の向きに合わせる
+            if ns.dot(&ng) < 0.0 { ns * -1.0 } else { ns }
+        };
+        let mut hit = HitInfo::new(t, ray.o + ray.d * t, n, &mesh.m);
+        if !mesh.colors.is_empty() {
+            hit.color = Some(mesh.colors[f[0]] * w + mesh.colors[f[1]] * u + mesh.colors[f[2]] * v);
+        }
+        if mesh.uvs.is_empty() {
+            // UV は重心座標なので, u の向きは辺 e1
+            let tangent = uv_tangent(n, e1, e2, (1.0, 0.0), (0.0, 1.0)).map(|(t, _)| t);
+            return Some(hit.with_uv(u, v).with_tangent(tangent));
+        }
+        let (a, b, c) = (mesh.uvs[f[0]], mesh.uvs[f[1]], mesh.uvs[f[2]]);
+        let (d1, d2) = ((b.0 - a.0, b.1 - a.1), (c.0 - a.0, c.1 - a.1));
+        let basis = uv_tangent(n, e1, e2, d1, d2);
+        hit.tangent = basis.map(|(t, _)| t);
+        let uv = (a.0 * w + b.0 * u + c.0 * v, a.1 * w + b.1 * u + c.1 * v);
+        let tex = &mesh.textures;
+        if let Some(t) = &tex.base_color {
+            hit.color = Some(hit.color.unwrap_or(mesh.m.c).mult(&t.sample(uv)));
+        }
+        if let Some(t) = &tex.emissive {
+            hit.emission = Some(mesh.m.e.mult(&t.sample(uv)));
+        }
+        if let (Some((t, scale)), Some(basis)) = (&tex.normal, basis) {
+            if let Some(nm) = normal_mapped(n, basis, t.sample(uv), *scale) {
+                hit.n = nm;
+            }
+        }
+        Some(hit.with_uv(uv.0, uv.1))
+    }
+
+    fn bounding_box(&self) -> Option<AABB> {
+        let (p0, p1, p2) = self.vertices();
+        let min = Vec3::new(
+            p0.x.min(p1.x).min(p2.x),
+            p0.y.min(p1.y).min(p2.y),
+            p0.z.min(p1.z).min(p2.z),
+        );
+        let max = Vec3::new(
+            p0.x.max(p1.x).max(p2.x),
+            p0.y.max(p1.y).max(p2.y),
+            p0.z.max(p1.z).max(p2.z),
+        );
+        Some(AABB::new(min, max))
+    }
+}
+
+// 三角形の UV の u が増える向きの接線と dpdv. 接線は n に直交させる
+// n は補間した法線, e1, e2 は辺, d1, d2 はそれに沿った UV の差. 作れなければ None
+fn uv_tangent(n: Vec3, e1: Vec3, e2: Vec3, d1: (f64, f64), d2: (f64, f64)) -> Option<(Vec3, Vec3)> {
+    let det = d1.0 * d2.1 - d2.0 * d1.1;
+    if det.abs() < 1e-12 {
+        return None;
+    }
+    let r = 1.0 / det;
+    let dpdu = (e1 * d2.1 - e2 * d1.1) * r;
+    let dpdv = (e2 * d1.0 - e1 * d2.0) * r;
+    let t = dpdu - n * n.dot(&dpdu);
+    if t.length() < 1e-24 {
+        return None;
+    }
+    Some((t.norm(), dpdv))
+}
+
+// 法線マップで曲げた法線. (t, dpdv) は uv_tangent で作った接線
+fn normal_mapped(n: Vec3, (t, dpdv): (Vec3, Vec3), texel: Color, scale: f64) -> Option<Vec3> {
+    // v は画像の下向きなので, 鏡映しでなければ従法線は dpdv と逆を向く
+    let mut b = n % t;
+    if b.dot(&dpdv) > 0.0 {
+        b = b * -1.0;
+    }
+    let x = (texel.x * 2.0 - 1.0) * scale;
+    let y = (texel.y * 2.0 - 1.0) * scale;
+    let z = texel.z * 2.0 - 1.0;
+    let m = t * x + b * y + n * z;
+    // 面の裏へ回り込む法線は使わない
+    if m.length() < 1e-24 || m.dot(&n) <= 0.0 {
+        return None;
+    }
+    Some(m.norm())
+}
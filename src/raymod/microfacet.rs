@@ -0,0 +1,304 @@
+use crate::raymod::*;
+
+use std::f64::consts::*;
+
+// 粗さを二乗した α がこれより小さいと数値が暴れるので, ここで止める
+const MIN_ALPHA: f64 = 1e-4;
+
+// 交点の局所座標. z を法線, x を接線にとる
+#[derive(Clone, Copy, Debug)]
+pub struct ShadingFrame {
+    pub t: Vec3,
+    pub b: Vec3,
+    pub n: Vec3,
+}
+
+impl ShadingFrame {
+    // 接線は n に直交させて使う. 無いか n と平行なら適当な向きをとる
+    pub fn new(n: Vec3, tangent: Option<Vec3>) -> ShadingFrame {
+        let t = tangent
+            .map(|t| t - n * n.dot(&t))
+            .filter(|t| t.length() > 1e-12)
+            .map(|t| t.norm())
+            .unwrap_or_else(|| tangent_basis(&n).0);
+        ShadingFrame { t, b: n % t, n }
+    }
+
+    pub fn local(&self, d: Vec3) -> Vec3 {
+        Vec3::new(d.dot(&self.t), d.dot(&self.b), d.dot(&self.n))
+    }
+
+    pub fn world(&self, d: Vec3) -> Vec3 {
+        self.t * d.x + self.b * d.y + self.n * d.z
+    }
+}
+
+// GGX (Trowbridge-Reitz) の微小面の分布と, Smith の遮蔽関数
+// 向きはすべて ShadingFrame の局所座標で, 法線の側を z > 0 とする
+#[derive(Clone, Copy, Debug)]
+pub struct Ggx {
+    pub ax: f64,
+    pub ay: f64,
+}
+
+impl Ggx {
+    // 見た目の粗さ (接線, 従法線) から. α は粗さの二乗
+    pub fn new(roughness: (f64, f64)) -> Ggx {
+        Ggx {
+            ax: (roughness.0 * roughness.0).max(MIN_ALPHA),
+            ay: (roughness.1 * roughness.1).max(MIN_ALPHA),
+        }
+    }
+
+    // 法線 h の微小面の分布 D(h)
+    pub fn d(&self, h: Vec3) -> f64 {
+        if h.z <= 0.0 {
+            return 0.0;
+        }
+        let x = h.x / self.ax;
+        let y = h.y / self.ay;
+        let k = x * x + y * y + h.z * h.z;
+        1.0 / (PI * self.ax * self.ay * k * k)
+    }
+
+    fn lambda(&self, w: Vec3) -> f64 {
+        let a2 = (self.ax * w.x) * (self.ax * w.x) + (self.ay * w.y) * (self.ay * w.y);
+        (-1.0 + (1.0 + a2 / (w.z * w.z)).sqrt()) * 0.5
+    }
+
+    // w から見て隠れない割合
+    pub fn g1(&self, w: Vec3) -> f64 {
+        1.0 / (1.0 + self.lambda(w))
+    }
+
+    // 高さの相関を入れた wo と wi の両方から見える割合
+    pub fn g2(&self, wo: Vec3, wi: Vec3) -> f64 {
+        1.0 / (1.0 + self.lambda(wo) + self.lambda(wi))
+    }
+
+    // wo から見える微小面の法線を, 見える面積に比例して選ぶ (Heitz 2018)
+    // wo.z > 0 で使う
+    pub fn sample_visible(&self, wo: Vec3, u1: f64, u2: f64) -> Vec3 {
+        // 半球に引き伸ばした空間で考える
+        let vh = Vec3::new(self.ax * wo.x, self.ay * wo.y, wo.z).norm();
+        let len2 = vh.x * vh.x + vh.y * vh.y;
+        let t1 = if len2 > 0.0 {
+            Vec3::new(-vh.y, vh.x, 0.0) * (1.0 / len2.sqrt())
+        } else {
+            Vec3::new(1.0, 0.0, 0.0)
+        };
+        let t2 = vh % t1;
+        let r = u1.sqrt();
+        let phi = 2.0 * PI * u2;
+        let p1 = r * phi.cos();
+        let s = 0.5 * (1.0 + vh.z);
+        let p2 = (1.0 - s) * (1.0 - p1 * p1).sqrt() + s * r * phi.sin();
+        let nh = t1 * p1 + t2 * p2 + vh * (1.0 - p1 * p1 - p2 * p2).max(0.0).sqrt();
+        Vec3::new(self.ax * nh.x, self.ay * nh.y, nh.z.max(0.0)).norm()
+    }
+
+    // sample_visible で h を選ぶ確率密度
+    pub fn pdf_visible(&self, wo: Vec3, h: Vec3) -> f64 {
+        self.g1(wo) * wo.dot(&h).max(0.0) * self.d(h) / wo.z
+    }
+}
+
+// 正面の反射率 f0 からの Schlick の近似
+pub fn schlick(f0: Color, cos: f64) -> Color {
+    let k = (1.0 - cos).clamp(0.0, 1.0).powi(5);
+    f0 + (Vec3::new(1.0, 1.0, 1.0) - f0) * k
+}
+
+// 誘電体の境目のフレネル反射率 (偏光は平均する). eta は手前の屈折率 / 向こうの屈折率
+// cos_i は入る向きと法線の間の cos. 全反射なら 1
+pub fn fresnel_dielectric(cos_i: f64, eta: f64) -> f64 {
+    let sin2_t = eta * eta * (1.0 - cos_i * cos_i);
+    if sin2_t >= 1.0 {
+        return 1.0;
+    }
+    let cos_t = (1.0 - sin2_t).sqrt();
+    let rs = (eta * cos_i - cos_t) / (eta * cos_i + cos_t);
+    let rp = (cos_i - eta * cos_t) / (cos_i + eta * cos_t);
+    (rs * rs + rp * rp) * 0.5
+}
+
+// w を法線 h の面で屈折させた向き. w は面から外へ向き w·h > 0 とする. 全反射なら None
+pub fn refract(w: Vec3, h: Vec3, eta: f64) -> Option<Vec3> {
+    let cos_i = w.dot(&h);
+    let sin2_t = eta * eta * (1.0 - cos_i * cos_i);
+    if sin2_t >= 1.0 {
+        return None;
+    }
+    let cos_t = (1.0 - sin2_t).sqrt();
+    Some(w * -eta + h * (eta * cos_i - cos_t))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    const THETA_STEPS: usize = 1500;
+    const PHI_STEPS: usize = 300;
+
+    fn dir(theta: f64, phi: f64) -> Vec3 {
+        Vec3::new(theta.sin() * phi.cos(), theta.sin() * phi.sin(), theta.cos())
+    }
+
+    // 半球の (θ, φ) の範囲で f を中点則で積分する
+    fn integrate(f: impl Fn(Vec3) -> f64, th: (f64, f64), ph: (f64, f64), steps: (usize, usize)) -> f64 {
+        let (dt, dp) = ((th.1 - th.0) / steps.0 as f64, (ph.1 - ph.0) / steps.1 as f64);
+        let mut sum = 0.0;
+        for i in 0..steps.0 {
+            let theta = th.0 + (i as f64 + 0.5) * dt;
+            let s = theta.sin();
+            for j in 0..steps.1 {
+                sum += f(dir(theta, ph.0 + (j as f64 + 0.5) * dp)) * s;
+            }
+        }
+        sum * dt * dp
+    }
+
+    fn ggxs() -> [Ggx; 3] {
+        [Ggx::new((0.5, 0.5)), Ggx::new((0.8, 0.8)), Ggx::new((0.4, 0.9))]
+    }
+
+    #[test]
+    fn pdf_visible_integrates_to_one() {
+        for g in ggxs() {
+            // 投影した微小面の面積の合計は巨視的な面の面積
+            let d = integrate(|h| g.d(h) * h.z, (0.0, FRAC_PI_2), (0.0, 2.0 * PI), (THETA_STEPS, PHI_STEPS));
+            assert!((d - 1.0).abs() < 2e-3, "{:?}: {}", g, d);
+            for wo in [dir(0.0, 0.0), dir(0.7, 0.3), dir(1.4, 2.0)] {
+                let p = integrate(|h| g.pdf_visible(wo, h), (0.0, FRAC_PI_2), (0.0, 2.0 * PI), (THETA_STEPS, PHI_STEPS));
+                assert!((p - 1.0).abs() < 2e-3, "{:?} {:?}: {}", g, wo, p);
+            }
+        }
+    }
+
+    #[test]
+    fn sample_visible_matches_pdf() {
+        const BINS: usize = 6;
+        const N: usize = 200_000;
+        let mut rng = StdRng::seed_from_u64(4);
+        for g in ggxs() {
+            for wo in [dir(0.3, 1.0), dir(1.2, 4.0)] {
+                let mut hist = [[0usize; BINS]; BINS];
+                for _ in 0..N {
+                    let h = g.sample_visible(wo, rng.random(), rng.random());
+                    assert!(h.z >= 0.0 && (h.length() - 1.0).abs() < 1e-12);
+                    let theta = h.z.min(1.0).acos();
+                    let phi = h.y.atan2(h.x).rem_euclid(2.0 * PI);
+                    let i = ((theta / FRAC_PI_2 * BINS as f64) as usize).min(BINS - 1);
+                    let j = ((phi / (2.0 * PI) * BINS as f64) as usize).min(BINS - 1);
+                    hist[i][j] += 1;
+                }
+                let (dt, dp) = (FRAC_PI_2 / BINS as f64, 2.0 * PI / BINS as f64);
+                for (i, row) in hist.iter().enumerate() {
+                    for (j, &count) in row.iter().enumerate() {
+                        let th = (i as f64 * dt, (i + 1) as f64 * dt);
+                        let ph = (j as f64 * dp, (j + 1) as f64 * dp);
+                        let expect = integrate(|h| g.pdf_visible(wo, h), th, ph, (200, 40));
+                        let got = count as f64 / N as f64;
+                        assert!((got - expect).abs() < 3e-3, "{:?} {:?} bin ({}, {}): {} vs {}", g, wo, i, j, got, expect);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn anisotropic_alpha_stretches_along_the_rougher_axis() {
+        // ay が大きいと微小面の法線は y 方向に大きく傾く
+        let g = Ggx::new((0.3, 0.9));
+        let mut rng = StdRng::seed_from_u64(5);
+        let (mut sx, mut sy) = (0.0, 0.0);
+        for _ in 0..10_000 {
+            let h = g.sample_visible(Vec3::new(0.0, 0.0, 1.0), rng.random(), rng.random());
+            sx += h.x.abs();
+            sy += h.y.abs();
+        }
+        assert!(sy > 2.0 * sx);
+        // 等方的なら D は φ によらない
+        let g = Ggx::new((0.6, 0.6));
+        let d0 = g.d(dir(0.5, 0.0));
+        for phi in [0.7, 2.0, 4.5] {
+            assert!((g.d(dir(0.5, phi)) - d0).abs() < 1e-12 * d0);
+        }
+    }
+
+    #[test]
+    fn shading_frame_local_and_world_are_inverses() {
+        let mut rng = StdRng::seed_from_u64(6);
+        let mut unit = || Vec3::new(rng.random_range(-1.0..1.0), rng.random_range(-1.0..1.0), rng.random_range(-1.0..1.0)).norm();
+        for k in 0..200 {
+            let n = unit();
+            let tangent = match k % 3 {
+                0 => None,
+                // 法線と平行な接線は使えないので他の向きをとる
+                1 => Some(n * 2.0),
+                _ => Some(unit()),
+            };
+            let f = ShadingFrame::new(n, tangent);
+            for (a, b) in [(f.t, f.b), (f.b, f.n), (f.n, f.t)] {
+                assert!(a.dot(&b).abs() < 1e-12);
+                assert!((a.length() - 1.0).abs() < 1e-12);
+            }
+            assert!((f.t % f.b - f.n).length() < 1e-24);
+            let d = unit() * 3.0;
+            assert!((f.world(f.local(d)) - d).length() < 1e-24);
+            assert!((f.local(f.world(d)) - d).length() < 1e-24);
+            assert!((f.local(n) - Vec3::new(0.0, 0.0, 1.0)).length() < 1e-24);
+        }
+    }
+
+    #[test]
+    fn total_internal_reflection() {
+        // ガラスから空気へ. 臨界角は asin(1/1.5) ≈ 41.8°
+        let eta = GLASS_IOR;
+        let h = Vec3::new(0.0, 0.0, 1.0);
+        for deg in [42.0f64, 60.0, 89.0] {
+            let w = dir(deg.to_radians(), 0.4);
+            assert!(refract(w, h, eta).is_none(), "{}°", deg);
+            assert_eq!(fresnel_dielectric(w.z, eta), 1.0);
+        }
+        let w = dir(41.0f64.to_radians(), 0.4);
+        assert!(refract(w, h, eta).is_some());
+        assert!(fresnel_dielectric(w.z, eta) < 1.0);
+    }
+
+    #[test]
+    fn refract_follows_snells_law() {
+        let mut rng = StdRng::seed_from_u64(7);
+        let h = Vec3::new(0.3, -0.5, 0.8).norm();
+        let f = ShadingFrame::new(h, None);
+        for _ in 0..200 {
+            let eta = [1.0 / GLASS_IOR, 1.0 / 1.33, 1.33 / GLASS_IOR, GLASS_IOR][rng.random_range(0..4)];
+            let w = f.world(dir(rng.random_range(0.0..1.5), rng.random_range(0.0..2.0 * PI)));
+            let Some(t) = refract(w, h, eta) else {
+                continue;
+            };
+            assert!((t.length() - 1.0).abs() < 1e-12);
+            assert!(t.dot(&h) < 0.0);
+            // w, h, t は同じ平面にある
+            assert!((w % h).dot(&t).abs() < 1e-12);
+            let sin_i = (w % h).length().sqrt();
+            let sin_t = (t % h).length().sqrt();
+            assert!((eta * sin_i - sin_t).abs() < 1e-12, "eta {}: {} vs {}", eta, eta * sin_i, sin_t);
+        }
+    }
+
+    #[test]
+    fn fresnel_at_normal_incidence() {
+        for (n1, n2) in [(1.0, GLASS_IOR), (GLASS_IOR, 1.0), (1.33, GLASS_IOR), (1.0, 1.33)] {
+            let r = (n1 - n2) / (n1 + n2);
+            assert!((fresnel_dielectric(1.0, n1 / n2) - r * r).abs() < 1e-12);
+            let t = refract(Vec3::new(0.0, 0.0, 1.0), Vec3::new(0.0, 0.0, 1.0), n1 / n2).unwrap();
+            assert!((t - Vec3::new(0.0, 0.0, -1.0)).length() < 1e-24);
+        }
+        // 屈折率が同じなら反射しない
+        assert!(fresnel_dielectric(0.3, 1.0) < 1e-24);
+    }
+}
@@ -1,11 +1,60 @@
 mod vec3;
 mod rayunit;
-mod optarg;
 mod scene;
+mod shape;
+mod bvh;
+mod primitive;
+mod planar;
+mod solid;
+mod transform;
+mod instance;
+mod csg;
+mod sdf;
+mod heightfield;
+mod camera;
+mod aperture;
+mod motion;
+mod scenefile;
+mod mesh;
+mod loaderror;
+mod objfile;
+mod plyfile;
+mod stlfile;
+mod pbrtfile;
+mod gltffile;
+mod texture;
+mod medium;
+mod microfacet;
+mod render;
+mod builder;
 pub use self::vec3::*;
 pub use self::rayunit::*;
-pub use self::optarg::*;
-pub use self::scene::*;
+pub use self::shape::*;
+pub use self::bvh::*;
+pub use self::primitive::*;
+pub use self::planar::*;
+pub use self::solid::*;
+pub use self::transform::*;
+pub use self::instance::*;
+pub use self::csg::*;
+pub use self::sdf::*;
+pub use self::heightfield::*;
+pub use self::camera::*;
+pub use self::aperture::*;
+pub use self::motion::*;
+pub use self::scenefile::*;
+pub use self::mesh::*;
+pub use self::loaderror::*;
+pub use self::objfile::*;
+pub use self::plyfile::*;
+pub use self::stlfile::*;
+pub use self::pbrtfile::*;
+pub use self::gltffile::*;
+pub use self::texture::*;
+pub use self::medium::*;
+pub use self::microfacet::*;
+pub use self::render::*;
+pub use self::builder::*;
 
 pub const EPS: f64 = 1e-6;
 pub const INF: f64 = 1e20;
@@ -0,0 +1,284 @@
+use crate::raymod::*;
+
+use std::sync::Arc;
+
+// 回転を補間するための四元数. 単位長のものだけを使う
+#[derive(Clone, Copy, Debug)]
+pub struct Quat {
+    pub w: f64,
+    pub v: Vec3,
+}
+
+impl Quat {
+    pub fn identity() -> Quat {
+        Quat { w: 1.0, v: Vec3::zero() }
+    }
+
+    // axis まわりに theta ラジアン
+    pub fn from_axis_angle(axis: Vec3, theta: f64) -> Quat {
+        let (s, c) = (theta * 0.5).sin_cos();
+        Quat { w: c, v: axis.norm() * s }
+    }
+
+    fn dot(&self, q: &Quat) -> f64 {
+        self.w * q.w + self.v.dot(&q.v)
+    }
+
+    // 2つの間の回転角
+    fn angle(&self, q: &Quat) -> f64 {
+        2.0 * self.dot(q).abs().min(1.0).acos()
+    }
+
+    // 球面線形補間. 短い方の回り方をとる
+    fn slerp(&self, q: &Quat, s: f64) -> Quat {
+        let mut d = self.dot(q);
+        let q = if d < 0.0 {
+            d = -d;
+            Quat { w: -q.w, v: q.v * -1.0 }
+        } else {
+            *q
+        };
+        let (a, b) = if d > 0.9995 {
+            // ほとんど同じ向きなら線形補間で十分
+            (1.0 - s, s)
+        } else {
+            let theta = d.acos();
+            let sn = theta.sin();
+            (((1.0 - s) * theta).sin() / sn, (s * theta).sin() / sn)
+        };
+        let w = self.w * a + q.w * b;
+        let v = self.v * a + q.v * b;
+        let len = (w * w + v.dot(&v)).sqrt();
+        Quat { w: w / len, v: v * (1.0 / len) }
+    }
+
+    // 回転軸と角度. 回らないなら None
+    pub fn axis_angle(&self) -> Option<(Vec3, f64)> {
+        let s = self.v.dot(&self.v).sqrt();
+        if s < 1e-12 {
+            return None;
+        }
+        Some((self.v * (1.0 / s), 2.0 * s.atan2(self.w)))
+    }
+
+    fn transform(&self) -> Transform {
+        match self.axis_angle() {
+            Some((axis, theta)) => Transform::rotate(axis, theta),
+            None => Transform::identity(),
+        }
+    }
+}
+
+// ある時刻の姿勢. 拡大, 回転, 平行移動の順にかける
+#[derive(Clone, Copy, Debug)]
+pub struct Keyframe {
+    pub time: f64,
+    pub translate: Vec3,
+    pub rotate: Quat,
+    pub scale: Vec3,
+}
+
+impl Keyframe {
+    pub fn new(time: f64) -> Keyframe {
+        Keyframe {
+            time,
+            translate: Vec3::zero(),
+            rotate: Quat::identity(),
+            scale: Vec3::new(1.0, 1.0, 1.0),
+        }
+    }
+
+    pub fn translate(mut self, v: Vec3) -> Keyframe {
+        self.translate = v;
+        self
+    }
+
+    pub fn rotate(mut self, axis: Vec3, theta: f64) -> Keyframe {
+        self.rotate = Quat::from_axis_angle(axis, theta);
+        self
+    }
+
+    pub fn scale(mut self, v: Vec3) -> Keyframe {
+        self.scale = v;
+        self
+    }
+
+    pub fn transform(&self) -> Transform {
+        Transform::translate(self.translate) * self.rotate.transform() * Transform::scale(self.scale)
+    }
+
+    fn lerp(&self, k: &Keyframe, time: f64) -> Keyframe {
+        let s = if k.time > self.time { (time - self.time) / (k.time - self.time) } else { 0.0 };
+        Keyframe {
+            time,
+            translate: self.translate + (k.translate - self.translate) * s,
+            rotate: self.rotate.slerp(&k.rotate, s),
+            scale: self.scale + (k.scale - self.scale) * s,
+        }
+    }
+}
+
+// キーフレームの列. 間は補間し, 最初より前と最後より後は端の姿勢のまま
+#[derive(Clone, Debug)]
+pub struct Motion {
+    keys: Vec<Keyframe>,
+}
+
+// 箱を求めるときにキーフレームの間を区切る数
+const MOTION_BOUND_STEPS: usize = 16;
+
+impl Motion {
+    pub fn new(mut keys: Vec<Keyframe>) -> Motion {
+        assert!(!keys.is_empty(), "motion needs at least one keyframe");
+        keys.sort_by(|a, b| a.time.total_cmp(&b.time));
+        Motion { keys }
+    }
+
+    // 時刻 t0 に from, t1 に to にある等速の平行移動
+    pub fn linear(t0: f64, from: Vec3, t1: f64, to: Vec3) -> Motion {
+        Motion::new(vec![Keyframe::new(t0).translate(from), Keyframe::new(t1).translate(to)])
+    }
+
+    pub fn keys(&self) -> &[Keyframe] {
+        &self.keys
+    }
+
+    pub fn at(&self, time: f64) -> Transform {
+        let i = self.keys.partition_point(|k| k.time <= time);
+        if i == 0 {
+            return self.keys[0].transform();
+        }
+        if i == self.keys.len() {
+            return self.keys[i - 1].transform();
+        }
+        self.keys[i - 1].lerp(&self.keys[i], time).transform()
+    }
+
+    // 動く間に b が通るところを囲む箱
+    // キーフレームの間を区切った姿勢の箱を合わせ, 回転で区切りの間に膨らむ分を足す
+    pub fn bounds(&self, b: &AABB) -> AABB {
+        let mut out = self.keys[0].transform().bounds(b);
+        let mut pad: f64 = 0.0;
+        // 回転の中心 (物体の原点) から箱の角までの最大の距離
+        let far = (0..8)
+            .map(|i| {
+                let c = Vec3::new(
+                    if i & 1 == 0 { b.min.x } else { b.max.x },
+                    if i & 2 == 0 { b.min.y } else { b.max.y },
+                    if i & 4 == 0 { b.min.z } else { b.max.z },
+                );
+                c.length().sqrt()
+            })
+            .fold(0.0, f64::max);
+        for k in self.keys.windows(2) {
+            for i in 1..=MOTION_BOUND_STEPS {
+                let t = k[0].time + (k[1].time - k[0].time) * i as f64 / MOTION_BOUND_STEPS as f64;
+                out = surrounding_box(&out, &k[0].lerp(&k[1], t).transform().bounds(b));
+            }
+            // 1区切りで回る角度 δ のとき, 弦から弧が離れるのは半径の (1 - cos(δ/2)) 倍まで
+            let delta = k[0].rotate.angle(&k[1].rotate) / MOTION_BOUND_STEPS as f64;
+            let s = [k[0].scale, k[1].scale]
+                .iter()
+                .map(|s| s.x.abs().max(s.y.abs()).max(s.z.abs()))
+                .fold(0.0, f64::max);
+            pad = pad.max(far * s * (1.0 - (delta * 0.5).cos()));
+        }
+        AABB::new(out.min - Vec3::new(pad, pad, pad), out.max + Vec3::new(pad, pad, pad))
+    }
+}
+
+// 時間とともに動く Group. レイの時刻の姿勢で調べる
+#[derive(Clone, Debug)]
+pub struct Moving {
+    pub geom: Arc<Group>,
+    pub motion: Motion,
+    bbox: Option<AABB>,
+}
+
+impl Moving {
+    pub fn new(geom: Arc<Group>, motion: Motion) -> Moving {
+        let bbox = geom.bounding_box().map(|b| motion.bounds(&b));
+        Moving { geom, motion, bbox }
+    }
+}
+
+impl Shape for Moving {
+    fn intersect(&self, ray: &Ray, t_max: f64) -> Option<HitInfo<'_>> {
+        let xf = self.motion.at(ray.time);
+        intersect_transformed(&self.geom, &xf, ray, t_max)
+    }
+
+    fn bounding_box(&self) -> Option<AABB> {
+        self.bbox
+    }
+
+    // 毎回その時刻の姿勢と逆行列を作る
+    fn cost(&self) -> f64 {
+        4.0 + self.geom.cost()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::f64::consts::FRAC_PI_2;
+
+    fn close(a: Vec3, b: Vec3) -> bool {
+        (a - b).length() < 1e-18
+    }
+
+    #[test]
+    fn at_interpolates_between_keys_and_clamps_outside() {
+        let y = Vec3::new(0.0, 1.0, 0.0);
+        let m = Motion::new(vec![
+            // 順不同で渡しても時刻で並べ直す
+            Keyframe::new(1.0).translate(Vec3::new(10.0, 0.0, 0.0)).rotate(y, FRAC_PI_2).scale(Vec3::new(3.0, 1.0, 1.0)),
+            Keyframe::new(0.0),
+        ]);
+        assert_eq!(m.keys()[0].time, 0.0);
+        let p = Vec3::new(1.0, 0.0, 0.0);
+        // 端より外は端の姿勢のまま
+        assert!(close(m.at(-5.0).point(p), p));
+        assert!(close(m.at(0.0).point(p), p));
+        let end = Vec3::new(10.0, 0.0, -3.0);
+        assert!(close(m.at(1.0).point(p), end));
+        assert!(close(m.at(7.0).point(p), end));
+        // 真ん中では移動と拡大は半分, 回転は 45度
+        let (s, c) = (FRAC_PI_2 * 0.5).sin_cos();
+        assert!(close(m.at(0.5).point(p), Vec3::new(5.0 + 2.0 * c, 0.0, -2.0 * s)));
+        // 逆行列も同じ姿勢のもの
+        let xf = m.at(0.3);
+        assert!(close(xf.inv.point(xf.point(p)), p));
+
+        // 線形の移動
+        let m = Motion::linear(2.0, Vec3::zero(), 4.0, Vec3::new(0.0, 8.0, 0.0));
+        assert!(close(m.at(3.0).point(Vec3::zero()), Vec3::new(0.0, 4.0, 0.0)));
+        assert!(close(m.at(3.5).point(Vec3::zero()), Vec3::new(0.0, 6.0, 0.0)));
+    }
+
+    #[test]
+    fn bounds_enclose_sampled_poses() {
+        let b = AABB::new(Vec3::new(-1.0, -2.0, -0.5), Vec3::new(3.0, 1.0, 0.5));
+        let m = Motion::new(vec![
+            Keyframe::new(0.0),
+            Keyframe::new(0.4).translate(Vec3::new(5.0, 2.0, 0.0)).rotate(Vec3::new(0.0, 0.0, 1.0), 2.5),
+            Keyframe::new(1.0).translate(Vec3::new(-3.0, 0.0, 4.0)).rotate(Vec3::new(1.0, 1.0, 0.0), -1.0).scale(Vec3::new(2.0, 0.5, 1.0)),
+        ]);
+        let bound = m.bounds(&b);
+        for i in 0..=1000 {
+            let xf = m.at(i as f64 / 1000.0);
+            for k in 0..8 {
+                let c = Vec3::new(
+                    if k & 1 == 0 { b.min.x } else { b.max.x },
+                    if k & 2 == 0 { b.min.y } else { b.max.y },
+                    if k & 4 == 0 { b.min.z } else { b.max.z },
+                );
+                let p = xf.point(c);
+                for a in 0..3 {
+                    assert!(p[a] >= bound.min[a] - 1e-9 && p[a] <= bound.max[a] + 1e-9, "t={} {:?}", i, p);
+                }
+            }
+        }
+    }
+}
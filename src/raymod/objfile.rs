@@ -0,0 +1,390 @@
+use crate::raymod::*;
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+// MTLの1マテリアル. 読んだ値をそのまま持ち, to_material でReflに振り分ける
+#[derive(Clone, Debug)]
+pub struct MtlMaterial {
+    pub name: String,
+    pub kd: Vec3,
+    pub ks: Vec3,
+    pub ke: Vec3,
+    pub tf: Option<Vec3>,
+    pub ni: f64,
+    pub d: f64,
+    pub illum: u32,
+}
+
+impl MtlMaterial {
+    pub fn new(name: &str) -> MtlMaterial {
+        MtlMaterial {
+            name: name.to_string(),
+            kd: Vec3::new(0.75, 0.75, 0.75),
+            ks: Vec3::zero(),
+            ke: Vec3::zero(),
+            tf: None,
+            ni: 1.5,
+            d: 1.0,
+            illum: 2,
+        }
+    }
+
+    // 透明(d<1 か illum 4,6,7,9)なら Ni を屈折率にした Refr, 鏡面(illum 3,5,8 か Ks が Kd より強い)なら Spec,
+    // それ以外は Kd を色にした Diff. Ke はそのまま発光にする
+    pub fn to_material(&self) -> Material {
+        let max = |v: &Vec3| v.x.max(v.y).max(v.z);
+        if self.d < 1.0 || matches!(self.illum, 4 | 6 | 7 | 9) {
+            let c = self.tf.unwrap_or(Vec3::new(1.0, 1.0, 1.0) * 0.999);
+            // Ni を書かない書き出しでは 0 のことがある
+            let ior = if self.ni > 0.0 { self.ni } else { GLASS_IOR };
+            Material::new(self.ke, c, Refl::Refr).with_ior(ior)
+        } else if matches!(self.illum, 3 | 5 | 8) || max(&self.ks) > max(&self.kd) {
+            Material::new(self.ke, self.ks, Refl::Spec)
+        } else {
+            Material::new(self.ke, self.kd, Refl::Diff)
+        }
+    }
+}
+
+fn read_text(path: &Path) -> Result<String, LoadError> {
+    fs::read_to_string(path).map_err(|e| LoadError::new(path, 0, e.to_string()))
+}
+
+fn parse_f64(tok: Option<&str>, path: &Path, line: usize, what: &str) -> Result<f64, LoadError> {
+    let tok = tok.ok_or_else(|| LoadError::new(path, line, format!("missing {}", what)))?;
+    tok.parse()
+        .map_err(|_| LoadError::new(path, line, format!("bad number '{}' for {}", tok, what)))
+}
+
+// "Kd r g b" もしくは "Kd r" (灰色)
+fn parse_color<'a>(
+    mut toks: impl Iterator<Item = &'a str>,
+    path: &Path,
+    line: usize,
+    what: &str,
+) -> Result<Vec3, LoadError> {
+    let first = toks.next();
+    if first == Some("spectral") || first == Some("xyz") {
+        return Err(LoadError::new(path, line, format!("{} {} is not supported", what, first.unwrap())));
+    }
+    let r = parse_f64(first, path, line, what)?;
+    match toks.next() {
+        None => Ok(Vec3::new(r, r, r)),
+        g => {
+            let g = parse_f64(g, path, line, what)?;
+            let b = parse_f64(toks.next(), path, line, what)?;
+            Ok(Vec3::new(r, g, b))
+        }
+    }
+}
+
+pub fn load_mtl(path: &Path) -> Result<HashMap<String, MtlMaterial>, LoadError> {
+    let text = read_text(path)?;
+    let mut mats = HashMap::new();
+    let mut cur: Option<MtlMaterial> = None;
+    for (i, l) in text.lines().enumerate() {
+        let line = i + 1;
+        let l = l.split('#').next().unwrap().trim();
+        let mut toks = l.split_whitespace();
+        let key = match toks.next() {
+            Some(k) => k,
+            None => continue,
+        };
+        if key == "newmtl" {
+            let name = toks.collect::<Vec<_>>().join(" ");
+            if name.is_empty() {
+                return Err(LoadError::new(path, line, "newmtl without a name"));
+            }
+            if let Some(m) = cur.take() {
+                mats.insert(m.name.clone(), m);
+            }
+            cur = Some(MtlMaterial::new(&name));
+            continue;
+        }
+        let m = match cur.as_mut() {
+            Some(m) => m,
+            None => return Err(LoadError::new(path, line, format!("'{}' before newmtl", key))),
+        };
+        match key {
+            "Kd" => m.kd = parse_color(toks, path, line, "Kd")?,
+            "Ks" => m.ks = parse_color(toks, path, line, "Ks")?,
+            "Ke" => m.ke = parse_color(toks, path, line, "Ke")?,
+            "Tf" => m.tf = Some(parse_color(toks, path, line, "Tf")?),
+            "Ni" => m.ni = parse_f64(toks.next(), path, line, "Ni")?,
+            "d" => m.d = parse_f64(toks.next(), path, line, "d")?,
+            "Tr" => m.d = 1.0 - parse_f64(toks.next(), path, line, "Tr")?,
+            "illum" => {
+                let v = parse_f64(toks.next(), path, line, "illum")?;
+                if v < 0.0 || v.fract() != 0.0 {
+                    return Err(LoadError::new(path, line, format!("bad illum model {}", v)));
+                }
+                m.illum = v as u32;
+            }
+            // Ka, Ns, テクスチャ等は使わない
+            _ => {}
+        }
+    }
+    if let Some(m) = cur.take() {
+        mats.insert(m.name.clone(), m);
+    }
+    Ok(mats)
+}
+
+// 1つのメッシュを組み立てる途中の状態. (v, vt, vn) の組が同じ頂点は共有する
+struct MeshBuilder {
+    name: String,
+    m: Material,
+    verts: HashMap<(usize, Option<usize>, Option<usize>), usize>,
+    positions: Vec<Vec3>,
+    normals: Vec<Vec3>,
+    uvs: Vec<(f64, f64)>,
+    indices: Vec<[usize; 3]>,
+    all_normals: bool,
+    all_uvs: bool,
+}
+
+impl MeshBuilder {
+    fn new(name: &str, m: Material) -> MeshBuilder {
+        MeshBuilder {
+            name: name.to_string(),
+            m,
+            verts: HashMap::new(),
+            positions: vec![],
+            normals: vec![],
+            uvs: vec![],
+            indices: vec![],
+            all_normals: true,
+            all_uvs: true,
+        }
+    }
+
+    fn vertex(&mut self, key: (usize, Option<usize>, Option<usize>), v: &[Vec3], vt: &[(f64, f64)], vn: &[Vec3]) -> usize {
+        if let Some(&i) = self.verts.get(&key) {
+            return i;
+        }
+        let i = self.positions.len();
+        self.positions.push(v[key.0]);
+        match key.1 {
+            Some(t) => self.uvs.push(vt[t]),
+            None => {
+                self.all_uvs = false;
+                self.uvs.push((0.0, 0.0));
+            }
+        }
+        match key.2 {
+            Some(n) => self.normals.push(vn[n].norm()),
+            None => {
+                self.all_normals = false;
+                self.normals.push(Vec3::zero());
+            }
+        }
+        self.verts.insert(key, i);
+        i
+    }
+
+    fn finish(self) -> NamedMesh {
+        let mut mesh = Mesh::new(self.positions, self.indices, self.m);
+        if self.all_normals {
+            mesh.normals = self.normals;
+        }
+        if self.all_uvs {
+            mesh.uvs = self.uvs;
+        }
+        NamedMesh {
+            name: self.name,
+            mesh,
+        }
+    }
+}
+
+// OBJの番号(1始まり, 負なら末尾から)を0始まりにする
+fn resolve_index(tok: &str, len: usize, path: &Path, line: usize, what: &str) -> Result<usize, LoadError> {
+    let i: i64 = tok
+        .parse()
+        .map_err(|_| LoadError::new(path, line, format!("bad {} index '{}'", what, tok)))?;
+    let r = if i > 0 { i - 1 } else { len as i64 + i };
+    if i == 0 || r < 0 || r >= len as i64 {
+        return Err(LoadError::new(
+            path,
+            line,
+            format!("{} index {} out of range (have {})", what, i, len),
+        ));
+    }
+    Ok(r as usize)
+}
+
+// OBJのグループ(g/o)とマテリアルの組ごとに1つのメッシュを作る
+pub fn load_obj(path: &Path) -> Result<Vec<NamedMesh>, LoadError> {
+    let text = read_text(path)?;
+    let dir = path.parent().unwrap_or(Path::new(""));
+    let default_material = MtlMaterial::new("default").to_material();
+
+    let mut v: Vec<Vec3> = vec![];
+    let mut vt: Vec<(f64, f64)> = vec![];
+    let mut vn: Vec<Vec3> = vec![];
+    let mut mtls: HashMap<String, MtlMaterial> = HashMap::new();
+
+    let mut builders: Vec<MeshBuilder> = vec![];
+    // (グループ名, マテリアル名) から builders の番号
+    let mut lookup: HashMap<(String, String), usize> = HashMap::new();
+    let mut group = String::from("default");
+    let mut mtl_name = String::new();
+    let mut material = default_material;
+    let mut current: Option<usize> = None;
+
+    for (i, l) in text.lines().enumerate() {
+        let line = i + 1;
+        let l = l.split('#').next().unwrap().trim();
+        let mut toks = l.split_whitespace();
+        let key = match toks.next() {
+            Some(k) => k,
+            None => continue,
+        };
+        match key {
+            "v" => {
+                let x = parse_f64(toks.next(), path, line, "v x")?;
+                let y = parse_f64(toks.next(), path, line, "v y")?;
+                let z = parse_f64(toks.next(), path, line, "v z")?;
+                v.push(Vec3::new(x, y, z));
+            }
+            "vt" => {
+                let s = parse_f64(toks.next(), path, line, "vt u")?;
+                let t = match toks.next() {
+                    Some(t) => parse_f64(Some(t), path, line, "vt v")?,
+                    None => 0.0,
+                };
+                vt.push((s, t));
+            }
+            "vn" => {
+                let x = parse_f64(toks.next(), path, line, "vn x")?;
+                let y = parse_f64(toks.next(), path, line, "vn y")?;
+                let z = parse_f64(toks.next(), path, line, "vn z")?;
+                vn.push(Vec3::new(x, y, z));
+            }
+            "g" | "o" => {
+                let name = toks.collect::<Vec<_>>().join(" ");
+                group = if name.is_empty() { String::from("default") } else { name };
+                current = None;
+            }
+            "mtllib" => {
+                for f in toks {
+                    let m = load_mtl(&dir.join(f)).map_err(|e| {
+                        LoadError::new(path, line, format!("mtllib {}: {}", f, e))
+                    })?;
+                    mtls.extend(m);
+                }
+            }
+            "usemtl" => {
+                let name = toks.collect::<Vec<_>>().join(" ");
+                material = match mtls.get(&name) {
+                    Some(m) => m.to_material(),
+                    None => {
+                        return Err(LoadError::new(path, line, format!("unknown material '{}'", name)))
+                    }
+                };
+                mtl_name = name;
+                current = None;
+            }
+            "f" => {
+                let mut face = vec![];
+                for tok in toks {
+                    let mut parts = tok.split('/');
+                    let pv = resolve_index(parts.next().unwrap(), v.len(), path, line, "vertex")?;
+                    let pt = match parts.next() {
+                        Some("") | None => None,
+                        Some(t) => Some(resolve_index(t, vt.len(), path, line, "texture")?),
+                    };
+                    let pn = match parts.next() {
+                        Some("") | None => None,
+                        Some(n) => Some(resolve_index(n, vn.len(), path, line, "normal")?),
+                    };
+                    if parts.next().is_some() {
+                        return Err(LoadError::new(path, line, format!("bad face vertex '{}'", tok)));
+                    }
+                    face.push((pv, pt, pn));
+                }
+                if face.len() < 3 {
+                    return Err(LoadError::new(path, line, "face with fewer than 3 vertices"));
+                }
+                let bi = match current {
+                    Some(bi) => bi,
+                    None => {
+                        let key = (group.clone(), mtl_name.clone());
+                        let bi = *lookup.entry(key).or_insert_with(|| {
+                            builders.push(MeshBuilder::new(&group, material));
+                            builders.len() - 1
+                        });
+                        current = Some(bi);
+                        bi
+                    }
+                };
+                let b = &mut builders[bi];
+                let idx: Vec<usize> = face.into_iter().map(|k| b.vertex(k, &v, &vt, &vn)).collect();
+                // 多角形は扇形に三角形分割する
+                for k in 1..idx.len() - 1 {
+                    b.indices.push([idx[0], idx[k], idx[k + 1]]);
+                }
+            }
+            // s, l, p などは使わない
+            _ => {}
+        }
+    }
+
+    let objects: Vec<NamedMesh> = builders
+        .into_iter()
+        .filter(|b| !b.indices.is_empty())
+        .map(|b| b.finish())
+        .collect();
+    if objects.is_empty() {
+        return Err(LoadError::new(path, 0, "no faces"));
+    }
+    Ok(objects)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn obj_err(name: &str, text: &str) -> LoadError {
+        load_obj(&test_file(name, text.as_bytes())).err().unwrap()
+    }
+
+    #[test]
+    fn malformed_obj_reports_the_line() {
+        let e = obj_err("bad_number.obj", "v 0 0 0\nv 1 x 0\n");
+        assert_eq!(e.line, 2);
+        assert!(e.msg.contains("bad number 'x'"), "{}", e);
+        let e = obj_err("out_of_range.obj", "v 0 0 0\nv 1 0 0\nv 0 1 0\nf 1 2 4\n");
+        assert_eq!(e.line, 4);
+        assert!(e.msg.contains("out of range"), "{}", e);
+        let e = obj_err("zero_index.obj", "v 0 0 0\nv 1 0 0\nv 0 1 0\nf 0 1 2\n");
+        assert!(e.msg.contains("out of range"), "{}", e);
+        let e = obj_err("two_vertices.obj", "v 0 0 0\nv 1 0 0\nf 1 2\n");
+        assert_eq!(e.line, 3);
+        assert!(e.msg.contains("fewer than 3"), "{}", e);
+        let e = obj_err("unknown_mtl.obj", "v 0 0 0\nv 1 0 0\nv 0 1 0\nusemtl red\nf 1 2 3\n");
+        assert_eq!(e.line, 4);
+        assert!(e.msg.contains("unknown material 'red'"), "{}", e);
+        let e = obj_err("no_faces.obj", "v 0 0 0\n");
+        assert!(e.msg.contains("no faces"), "{}", e);
+    }
+
+    #[test]
+    fn negative_obj_index_counts_from_the_end() {
+        let meshes = load_obj(&test_file("negative.obj", b"v 0 0 0\nv 1 0 0\nv 0 1 0\nf -3 -2 -1\n")).unwrap();
+        assert_eq!(meshes.len(), 1);
+    }
+
+    #[test]
+    fn malformed_mtl_reports_the_line() {
+        let e = load_mtl(&test_file("before_newmtl.mtl", b"Kd 1 0 0\n")).err().unwrap();
+        assert_eq!(e.line, 1);
+        assert!(e.msg.contains("before newmtl"), "{}", e);
+        let e = load_mtl(&test_file("bad_kd.mtl", b"newmtl a\nKd 1 zero 0\n")).err().unwrap();
+        assert_eq!(e.line, 2);
+        let e = load_mtl(&test_file("no_name.mtl", b"newmtl\n")).err().unwrap();
+        assert!(e.msg.contains("without a name"), "{}", e);
+    }
+}
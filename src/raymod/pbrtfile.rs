@@ -0,0 +1,860 @@
+use crate::raymod::*;
+
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::f64::consts::PI;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+// pbrt-v3 のシーンファイルのうちよく使うものを読む
+//
+// LookAt, Translate, Rotate, Scale, Transform, ConcatTransform, Identity
+// AttributeBegin/End, TransformBegin/End, Include
+// Camera "perspective" (fov, lensradius, focaldistance), Film (xresolution, yresolution, filename)
+// Sampler (pixelsamples)
+// Shape "sphere", "trianglemesh", "plymesh"
+// Material, MakeNamedMaterial, NamedMaterial の "matte", "mirror", "glass", "metal" (glass と metal は粗さも読む)
+// LightSource "point", "infinite", AreaLightSource "diffuse"
+//
+// それ以外の命令や引数は読み飛ばし, 警告として返す
+// 点光源は小さな光る球, infinite はシーンを囲む大きな光る球にする
+// pbrt は左手系なので, カメラから見て左右が逆になるときはシーン全体を鏡に映して合わせる
+
+// Include で読むファイルの数の上限. 自分を読み込むファイルで止まらなくなるのを防ぐ
+const MAX_INCLUDES: usize = 256;
+
+#[derive(Debug)]
+enum Tok {
+    Ident(String),
+    Str(String),
+    Num(f64),
+    Open,
+    Close,
+}
+
+// 字句と, それがあったファイルの番号と行
+struct Token {
+    tok: Tok,
+    file: usize,
+    line: usize,
+}
+
+fn tokenize(text: &str, file: usize) -> Result<Vec<Token>, (usize, String)> {
+    let mut out = vec![];
+    let mut line = 1;
+    let mut chars = text.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        match c {
+            '\n' => {
+                line += 1;
+                chars.next();
+            }
+            c if c.is_whitespace() => {
+                chars.next();
+            }
+            '#' => {
+                while chars.peek().is_some_and(|&c| c != '\n') {
+                    chars.next();
+                }
+            }
+            '[' | ']' => {
+                chars.next();
+                let tok = if c == '[' { Tok::Open } else { Tok::Close };
+                out.push(Token { tok, file, line });
+            }
+            '"' => {
+                chars.next();
+                let start = line;
+                let mut s = String::new();
+                loop {
+                    match chars.next() {
+                        None => return Err((start, "unterminated string".to_string())),
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some('n') => s.push('\n'),
+                            Some('t') => s.push('\t'),
+                            Some(e) => s.push(e),
+                            None => return Err((start, "unterminated string".to_string())),
+                        },
+                        Some('\n') => return Err((start, "newline in string".to_string())),
+                        Some(c) => s.push(c),
+                    }
+                }
+                out.push(Token { tok: Tok::Str(s), file, line: start });
+            }
+            _ => {
+                let mut s = String::new();
+                while let Some(&c) = chars.peek() {
+                    if c.is_whitespace() || matches!(c, '"' | '[' | ']' | '#') {
+                        break;
+                    }
+                    s.push(c);
+                    chars.next();
+                }
+                let tok = if s.starts_with(|c: char| c.is_ascii_digit() || matches!(c, '-' | '+' | '.')) {
+                    match s.parse() {
+                        Ok(v) => Tok::Num(v),
+                        Err(_) => return Err((line, format!("bad number '{}'", s))),
+                    }
+                } else {
+                    Tok::Ident(s)
+                };
+                out.push(Token { tok, file, line });
+            }
+        }
+    }
+    Ok(out)
+}
+
+// "型 名前" と値. 読んだものは used に印をつけ, 残ったものを警告にする
+struct Param {
+    ty: String,
+    name: String,
+    nums: Vec<f64>,
+    strs: Vec<String>,
+    used: Cell<bool>,
+}
+
+struct Params {
+    list: Vec<Param>,
+    file: usize,
+    line: usize,
+}
+
+impl Params {
+    fn get(&self, name: &str) -> Option<&Param> {
+        let p = self.list.iter().find(|p| p.name == name)?;
+        p.used.set(true);
+        Some(p)
+    }
+
+    fn float(&self, name: &str, default: f64) -> f64 {
+        self.get(name).and_then(|p| p.nums.first().copied()).unwrap_or(default)
+    }
+
+    fn string(&self, name: &str) -> Option<String> {
+        self.get(name).and_then(|p| p.strs.first().cloned())
+    }
+
+    // 命令ごと使わないときは, 引数ひとつずつの警告は出さない
+    fn skip_all(&self) {
+        for p in self.list.iter() {
+            p.used.set(true);
+        }
+    }
+}
+
+// pbrt の粗さを Material の粗さにする. pbrt は α をそのまま持つか,
+// remaproughness なら RoughnessToAlpha で α に直す. Material の粗さは α の平方根
+fn pbrt_roughness(r: f64, remap: bool) -> f64 {
+    let alpha = if remap {
+        let x = r.max(1e-3).ln();
+        1.62142 + 0.819955 * x + 0.1734 * x * x + 0.0171201 * x * x * x + 0.000640711 * x * x * x * x
+    } else {
+        r
+    };
+    alpha.max(0.0).sqrt()
+}
+
+// AttributeBegin で積む状態. TransformBegin は ctm だけを戻す
+#[derive(Clone)]
+struct State {
+    ctm: Transform,
+    material: Material,
+    // AreaLightSource の放射輝度. この後の形が光る
+    area: Option<Vec3>,
+    transform_only: bool,
+}
+
+// Camera の命令を WorldBegin まで取っておく (Film が後に来ることがある)
+struct CameraSpec {
+    cam_to_world: Transform,
+    fov: f64,
+    lens_radius: f64,
+    focal_distance: f64,
+}
+
+fn default_material() -> Material {
+    Material::new(Vec3::zero(), Vec3::new(0.5, 0.5, 0.5), Refl::Diff)
+}
+
+struct Parser {
+    files: Vec<PathBuf>,
+    toks: Vec<Token>,
+    pos: usize,
+    warnings: Vec<LoadError>,
+    state: State,
+    stack: Vec<State>,
+    named: HashMap<String, Material>,
+    camera: Option<CameraSpec>,
+    resolution: (usize, usize),
+    // カメラの左右を合わせるための鏡. WorldBegin で決まる
+    mirror: Transform,
+    in_world: bool,
+    // ObjectBegin の中の形は置かない
+    in_object: bool,
+    point_lights: Vec<(Vec3, Vec3)>,
+    env: Option<Vec3>,
+    scene: Scene,
+    settings: RenderSettings,
+}
+
+pub fn load_pbrt_file(path: &Path) -> Result<(Scene, RenderSettings, Vec<LoadError>), LoadError> {
+    let text = fs::read_to_string(path).map_err(|e| LoadError::new(path, 0, e.to_string()))?;
+    let toks = tokenize(&text, 0).map_err(|(line, msg)| LoadError::new(path, line, msg))?;
+    let mut scene = Scene::init();
+    // pbrt にはカメラの手前を切る距離がない
+    scene.camera.near = 0.0;
+    let mut p = Parser {
+        files: vec![path.to_path_buf()],
+        toks,
+        pos: 0,
+        warnings: vec![],
+        state: State {
+            ctm: Transform::identity(),
+            material: default_material(),
+            area: None,
+            transform_only: false,
+        },
+        stack: vec![],
+        named: HashMap::new(),
+        camera: None,
+        resolution: (640, 480),
+        mirror: Transform::identity(),
+        in_world: false,
+        in_object: false,
+        point_lights: vec![],
+        env: None,
+        scene,
+        settings: RenderSettings::default(),
+    };
+    while p.pos < p.toks.len() {
+        p.directive()?;
+    }
+    if !p.in_world {
+        p.begin_world();
+    }
+    p.add_lights();
+    Ok((p.scene, p.settings, p.warnings))
+}
+
+impl Parser {
+    fn path(&self, file: usize) -> &Path {
+        &self.files[file]
+    }
+
+    fn here(&self) -> (usize, usize) {
+        match self.toks.get(self.pos).or(self.toks.last()) {
+            Some(t) => (t.file, t.line),
+            None => (0, 0),
+        }
+    }
+
+    fn error<T>(&self, msg: impl Into<String>) -> Result<T, LoadError> {
+        let (file, line) = self.here();
+        Err(LoadError::new(self.path(file), line, msg))
+    }
+
+    // 同じ内容の警告は最初の1つだけ残す
+    fn warn(&mut self, file: usize, line: usize, msg: impl Into<String>) {
+        let msg = msg.into();
+        if !self.warnings.iter().any(|w| w.msg == msg) {
+            let w = LoadError::new(self.path(file), line, msg);
+            self.warnings.push(w);
+        }
+    }
+
+    fn next(&mut self) -> Option<&Tok> {
+        let t = self.toks.get(self.pos)?;
+        self.pos += 1;
+        Some(&t.tok)
+    }
+
+    fn string(&mut self) -> Result<String, LoadError> {
+        match self.next() {
+            Some(Tok::Str(s)) => Ok(s.clone()),
+            _ => {
+                self.pos -= 1;
+                self.error("expected a string")
+            }
+        }
+    }
+
+    // 数を n 個. [ ] で囲んであってもよい
+    fn nums(&mut self, n: usize) -> Result<Vec<f64>, LoadError> {
+        let bracket = matches!(self.toks.get(self.pos).map(|t| &t.tok), Some(Tok::Open));
+        if bracket {
+            self.pos += 1;
+        }
+        let mut out = Vec::with_capacity(n);
+        for _ in 0..n {
+            match self.next() {
+                Some(Tok::Num(v)) => out.push(*v),
+                _ => {
+                    self.pos -= 1;
+                    return self.error(format!("expected {} numbers", n));
+                }
+            }
+        }
+        if bracket && !matches!(self.next(), Some(Tok::Close)) {
+            self.pos -= 1;
+            return self.error("expected ']'");
+        }
+        Ok(out)
+    }
+
+    fn params(&mut self) -> Result<Params, LoadError> {
+        let (file, line) = self.here();
+        let mut list = vec![];
+        while let Some(Tok::Str(decl)) = self.toks.get(self.pos).map(|t| &t.tok) {
+            let words: Vec<&str> = decl.split_whitespace().collect();
+            if words.len() != 2 {
+                return self.error(format!("bad parameter declaration '{}'", decl));
+            }
+            let (ty, name) = (words[0].to_string(), words[1].to_string());
+            self.pos += 1;
+            let mut nums = vec![];
+            let mut strs = vec![];
+            let mut value = |t: &Tok| match t {
+                Tok::Num(v) => {
+                    nums.push(*v);
+                    true
+                }
+                Tok::Str(s) => {
+                    strs.push(s.clone());
+                    true
+                }
+                // v3 の bool は文字列だが, 括弧なしで書いたものも受ける
+                Tok::Ident(s) if s == "true" || s == "false" => {
+                    strs.push(s.clone());
+                    true
+                }
+                _ => false,
+            };
+            match self.toks.get(self.pos).map(|t| &t.tok) {
+                Some(Tok::Open) => {
+                    self.pos += 1;
+                    loop {
+                        match self.toks.get(self.pos).map(|t| &t.tok) {
+                            Some(Tok::Close) => break,
+                            Some(t) if value(t) => self.pos += 1,
+                            _ => return self.error(format!("expected ']' after the values of '{}'", name)),
+                        }
+                    }
+                    self.pos += 1;
+                }
+                Some(t) if value(t) => self.pos += 1,
+                _ => return self.error(format!("missing value for '{}'", name)),
+            }
+            list.push(Param {
+                ty,
+                name,
+                nums,
+                strs,
+                used: Cell::new(false),
+            });
+        }
+        Ok(Params { list, file, line })
+    }
+
+    // 読まなかった引数を警告にする
+    fn finish(&mut self, p: &Params, what: &str) {
+        for q in p.list.iter().filter(|q| !q.used.get()) {
+            let msg = format!("{}: parameter '{} {}' is not supported", what, q.ty, q.name);
+            self.warn(p.file, p.line, msg);
+        }
+    }
+
+    fn color(&mut self, p: &Params, name: &str, default: Vec3) -> Vec3 {
+        match p.get(name) {
+            None => default,
+            Some(q) if matches!(q.ty.as_str(), "rgb" | "color") && q.nums.len() == 3 => {
+                Vec3::new(q.nums[0], q.nums[1], q.nums[2])
+            }
+            Some(q) => {
+                let msg = format!("'{} {}' is not supported, using the default", q.ty, q.name);
+                self.warn(p.file, p.line, msg);
+                default
+            }
+        }
+    }
+
+    // 命令の後の値を次の命令まで読み飛ばす
+    fn skip(&mut self, name: &str, file: usize, line: usize) {
+        self.warn(file, line, format!("{} is not supported", name));
+        while let Some(t) = self.toks.get(self.pos) {
+            if let Tok::Ident(_) = t.tok {
+                break;
+            }
+            self.pos += 1;
+        }
+    }
+
+    fn concat(&mut self, t: Transform) {
+        self.state.ctm = self.state.ctm * t;
+    }
+
+    fn directive(&mut self) -> Result<(), LoadError> {
+        let (file, line) = self.here();
+        let name = match self.next() {
+            Some(Tok::Ident(s)) => s.clone(),
+            _ => {
+                self.pos -= 1;
+                return self.error("expected a directive");
+            }
+        };
+        match name.as_str() {
+            "Identity" => self.state.ctm = Transform::identity(),
+            "Translate" => {
+                let v = self.nums(3)?;
+                self.concat(Transform::translate(Vec3::new(v[0], v[1], v[2])));
+            }
+            "Scale" => {
+                let v = self.nums(3)?;
+                self.concat(Transform::scale(Vec3::new(v[0], v[1], v[2])));
+            }
+            "Rotate" => {
+                let v = self.nums(4)?;
+                self.concat(Transform::rotate(Vec3::new(v[1], v[2], v[3]), v[0].to_radians()));
+            }
+            "LookAt" => {
+                let v = self.nums(9)?;
+                let eye = Vec3::new(v[0], v[1], v[2]);
+                let dir = (Vec3::new(v[3], v[4], v[5]) - eye).norm();
+                let right = (Vec3::new(v[6], v[7], v[8]).norm() % dir).norm();
+                let up = dir % right;
+                let m = Mat4::new([
+                    [right.x, up.x, dir.x, eye.x],
+                    [right.y, up.y, dir.y, eye.y],
+                    [right.z, up.z, dir.z, eye.z],
+                    [0.0, 0.0, 0.0, 1.0],
+                ]);
+                match Transform::from_matrix(m) {
+                    Some(c2w) => self.concat(c2w.inverse()),
+                    None => self.warn(file, line, "LookAt with the up vector along the view direction is ignored"),
+                }
+            }
+            "Transform" | "ConcatTransform" => {
+                let v = self.nums(16)?;
+                // 列ごとに並んでいる
+                let mut m = [[0.0; 4]; 4];
+                for (r, row) in m.iter_mut().enumerate() {
+                    for (c, x) in row.iter_mut().enumerate() {
+                        *x = v[c * 4 + r];
+                    }
+                }
+                match Transform::from_matrix(Mat4::new(m)) {
+                    Some(t) if name == "Transform" => self.state.ctm = t,
+                    Some(t) => self.concat(t),
+                    None => self.warn(file, line, format!("{} with a singular matrix is ignored", name)),
+                }
+            }
+            "AttributeBegin" | "TransformBegin" => {
+                let mut s = self.state.clone();
+                s.transform_only = name == "TransformBegin";
+                self.stack.push(s);
+            }
+            "AttributeEnd" | "TransformEnd" => match self.stack.pop() {
+                Some(s) => {
+                    if s.transform_only != (name == "TransformEnd") {
+                        self.warn(file, line, format!("{} does not match its Begin", name));
+                    }
+                    if s.transform_only {
+                        self.state.ctm = s.ctm;
+                    } else {
+                        self.state = s;
+                    }
+                }
+                None => self.warn(file, line, format!("{} without a matching Begin", name)),
+            },
+            "Include" => {
+                let f = self.string()?;
+                if self.files.len() > MAX_INCLUDES {
+                    return Err(LoadError::new(self.path(file), line, format!("more than {} Include files", MAX_INCLUDES)));
+                }
+                let inc = self.path(file).parent().unwrap_or(Path::new("")).join(&f);
+                let text = fs::read_to_string(&inc).map_err(|e| LoadError::new(&inc, 0, e.to_string()))?;
+                let idx = self.files.len();
+                let toks = tokenize(&text, idx).map_err(|(l, msg)| LoadError::new(&inc, l, msg))?;
+                self.files.push(inc);
+                let at = self.pos;
+                self.toks.splice(at..at, toks);
+            }
+            "Camera" => {
+                let ty = self.string()?;
+                let p = self.params()?;
+                if ty != "perspective" {
+                    self.warn(file, line, format!("camera '{}' is not supported, using perspective", ty));
+                }
+                self.camera = Some(CameraSpec {
+                    cam_to_world: self.state.ctm.inverse(),
+                    fov: p.float("fov", 90.0),
+                    lens_radius: p.float("lensradius", 0.0),
+                    focal_distance: p.float("focaldistance", 1e6),
+                });
+                self.finish(&p, "Camera");
+            }
+            "Film" => {
+                let _ty = self.string()?;
+                let p = self.params()?;
+                let w = p.float("xresolution", 640.0);
+                let h = p.float("yresolution", 480.0);
+                self.resolution = (w.max(1.0) as usize, h.max(1.0) as usize);
+                if let Some(f) = p.string("filename") {
+                    self.settings.output = Some(Path::new(&f).with_extension("png").display().to_string());
+                }
+                self.finish(&p, "Film");
+            }
+            "Sampler" => {
+                let _ty = self.string()?;
+                let p = self.params()?;
+                // 1画素を 2x2 に分けて samples ずつ取るので 4 で割る
+                if p.get("pixelsamples").is_some() {
+                    let n = p.float("pixelsamples", 16.0).max(1.0) as usize;
+                    self.settings.samples = Some(n.div_ceil(4));
+                }
+                self.finish(&p, "Sampler");
+            }
+            "WorldBegin" => self.begin_world(),
+            "WorldEnd" => {}
+            "Material" => {
+                let ty = self.string()?;
+                let p = self.params()?;
+                self.state.material = self.material(&ty, &p);
+            }
+            "MakeNamedMaterial" => {
+                let n = self.string()?;
+                let p = self.params()?;
+                let ty = p.string("type").unwrap_or_default();
+                let m = self.material(&ty, &p);
+                self.named.insert(n, m);
+            }
+            "NamedMaterial" => {
+                let n = self.string()?;
+                match self.named.get(&n) {
+                    Some(m) => self.state.material = *m,
+                    None => self.warn(file, line, format!("unknown named material '{}'", n)),
+                }
+            }
+            "AreaLightSource" => {
+                let ty = self.string()?;
+                let p = self.params()?;
+                if ty == "diffuse" {
+                    let l = self.color(&p, "L", Vec3::new(1.0, 1.0, 1.0));
+                    self.state.area = Some(l * p.float("scale", 1.0));
+                    p.get("twosided");
+                } else {
+                    self.warn(file, line, format!("area light '{}' is not supported", ty));
+                    p.skip_all();
+                }
+                self.finish(&p, "AreaLightSource");
+            }
+            "LightSource" => {
+                let ty = self.string()?;
+                let p = self.params()?;
+                let scale = p.float("scale", 1.0);
+                match ty.as_str() {
+                    "point" => {
+                        let i = self.color(&p, "I", Vec3::new(1.0, 1.0, 1.0)) * scale;
+                        let from = p.get("from").filter(|q| q.nums.len() == 3).map_or(Vec3::zero(), |q| {
+                            Vec3::new(q.nums[0], q.nums[1], q.nums[2])
+                        });
+                        let at = (self.mirror * self.state.ctm).point(from);
+                        self.point_lights.push((at, i));
+                    }
+                    "infinite" => {
+                        let l = self.color(&p, "L", Vec3::new(1.0, 1.0, 1.0)) * scale;
+                        if p.get("mapname").is_some() {
+                            self.warn(file, line, "infinite light maps are not supported, using a constant color");
+                        }
+                        self.env = Some(self.env.unwrap_or(Vec3::zero()) + l);
+                    }
+                    _ => {
+                        self.warn(file, line, format!("light '{}' is not supported", ty));
+                        p.skip_all();
+                    }
+                }
+                self.finish(&p, "LightSource");
+            }
+            "Shape" => {
+                let ty = self.string()?;
+                let p = self.params()?;
+                if !self.in_object {
+                    self.shape(&ty, &p)?;
+                }
+                self.finish(&p, "Shape");
+            }
+            "ObjectBegin" => {
+                self.string()?;
+                self.warn(file, line, "ObjectBegin is not supported, its shapes are skipped");
+                self.in_object = true;
+            }
+            "ObjectEnd" => self.in_object = false,
+            "ActiveTransform" => {
+                self.warn(file, line, "ActiveTransform is not supported");
+                self.next();
+            }
+            "ReverseOrientation" => {}
+            _ => self.skip(&name, file, line),
+        }
+        Ok(())
+    }
+
+    // カメラを決め, pbrt と左右が逆ならシーンを映す鏡を用意する
+    fn begin_world(&mut self) {
+        self.in_world = true;
+        self.state.ctm = Transform::identity();
+        let spec = match &self.camera {
+            Some(c) => c,
+            None => return,
+        };
+        let c2w = spec.cam_to_world;
+        let (w, h) = self.resolution;
+        let pos = c2w.point(Vec3::zero());
+        let dir = c2w.dir(Vec3::new(0.0, 0.0, 1.0)).norm();
+        let up = c2w.dir(Vec3::new(0.0, 1.0, 0.0)).norm();
+        let right = c2w.dir(Vec3::new(1.0, 0.0, 0.0)).norm();
+        // fov は短い方の辺の画角
+        let half = (spec.fov.to_radians() * 0.5).tan();
+        let vfov = if w < h { 2.0 * (half * h as f64 / w as f64).atan() } else { 2.0 * half.atan() };
+
+        let cam = &mut self.scene.camera;
+        cam.pos = pos;
+        cam.look_at = pos + dir;
+        cam.up = up;
+        cam.vfov = vfov.to_degrees();
+        cam.width = w;
+        cam.height = h;
+        if spec.lens_radius > 0.0 {
+            cam.aperture = spec.lens_radius;
+            cam.focus_dist = Some(spec.focal_distance);
+        }
+
+        if (dir % up).dot(&right) < 0.0 {
+            // pos を通り right に垂直な面で映す. 鏡は自分自身が逆変換
+            let n = right;
+            let d = 2.0 * pos.dot(&n);
+            let m = Mat4::new([
+                [1.0 - 2.0 * n.x * n.x, -2.0 * n.x * n.y, -2.0 * n.x * n.z, d * n.x],
+                [-2.0 * n.y * n.x, 1.0 - 2.0 * n.y * n.y, -2.0 * n.y * n.z, d * n.y],
+                [-2.0 * n.z * n.x, -2.0 * n.z * n.y, 1.0 - 2.0 * n.z * n.z, d * n.z],
+                [0.0, 0.0, 0.0, 1.0],
+            ]);
+            self.mirror = Transform { m, inv: m };
+        }
+    }
+
+    fn material(&mut self, ty: &str, p: &Params) -> Material {
+        let (file, line) = (p.file, p.line);
+        let m = match ty {
+            "matte" => Material::new(Vec3::zero(), self.color(p, "Kd", Vec3::new(0.5, 0.5, 0.5)), Refl::Diff),
+            "mirror" => Material::new(Vec3::zero(), self.color(p, "Kr", Vec3::new(0.9, 0.9, 0.9)), Refl::Spec),
+            "glass" => {
+                let mut eta = p.get("eta").or(p.get("index")).and_then(|q| q.nums.first().copied()).unwrap_or(1.5);
+                if eta <= 0.0 {
+                    self.warn(file, line, format!("glass index {} is not positive, using 1.5", eta));
+                    eta = GLASS_IOR;
+                }
+                self.color(p, "Kr", Vec3::new(1.0, 1.0, 1.0));
+                // pbrt と同じく, 粗さが両方 0 のときだけ透明なガラスにする
+                let (ru, rv) = (p.float("uroughness", 0.0), p.float("vroughness", 0.0));
+                let remap = p.string("remaproughness").is_none_or(|s| s != "false");
+                let m = Material::new(Vec3::zero(), self.color(p, "Kt", Vec3::new(1.0, 1.0, 1.0)), Refl::Refr).with_ior(eta);
+                if ru == 0.0 && rv == 0.0 {
+                    m
+                } else {
+                    m.with_anisotropic_roughness(pbrt_roughness(ru, remap), pbrt_roughness(rv, remap))
+                }
+            }
+            "metal" => {
+                // 真上から見たときのフレネル反射率を鏡の色にする. 既定は pbrt と同じ銅
+                let eta = self.color(p, "eta", Vec3::new(0.200, 0.924, 1.102));
+                let k = self.color(p, "k", Vec3::new(3.912, 2.452, 2.142));
+                let f0 = |n: f64, k: f64| ((n - 1.0) * (n - 1.0) + k * k) / ((n + 1.0) * (n + 1.0) + k * k);
+                // 既定は pbrt と同じ. uroughness と vroughness は roughness より優先する
+                let rough = p.float("roughness", 0.01);
+                let (ru, rv) = (p.float("uroughness", rough), p.float("vroughness", rough));
+                let remap = p.string("remaproughness").is_none_or(|s| s != "false");
+                let (ru, rv) = (pbrt_roughness(ru, remap), pbrt_roughness(rv, remap));
+                Material::new(Vec3::zero(), Vec3::new(f0(eta.x, k.x), f0(eta.y, k.y), f0(eta.z, k.z)), Refl::Spec)
+                    .with_anisotropic_roughness(ru, rv)
+            }
+            "" | "none" => {
+                self.warn(file, line, "material 'none' is not supported, using matte");
+                p.skip_all();
+                default_material()
+            }
+            _ => {
+                self.warn(file, line, format!("material '{}' is not supported, using matte", ty));
+                let kd = self.color(p, "Kd", Vec3::new(0.5, 0.5, 0.5));
+                p.skip_all();
+                Material::new(Vec3::zero(), kd, Refl::Diff)
+            }
+        };
+        p.get("type");
+        self.finish(p, &format!("Material '{}'", ty));
+        m
+    }
+
+    // 今の状態のマテリアル. AreaLightSource の中なら光る
+    fn current_material(&self) -> Material {
+        let mut m = self.state.material;
+        if let Some(l) = self.state.area {
+            m.e = l;
+        }
+        m
+    }
+
+    fn shape(&mut self, ty: &str, p: &Params) -> Result<(), LoadError> {
+        let xf = self.mirror * self.state.ctm;
+        let m = self.current_material();
+        match ty {
+            "sphere" => {
+                let r = p.float("radius", 1.0);
+                for q in ["zmin", "zmax", "phimax"] {
+                    if p.get(q).is_some() {
+                        self.warn(p.file, p.line, "partial spheres are rendered whole");
+                    }
+                }
+                match similarity_scale(&xf) {
+                    Some(s) => self.scene.add(Sphere { rad: r * s, p: xf.point(Vec3::zero()), m }),
+                    None => {
+                        let g = Group::new(vec![Sphere { rad: r, p: Vec3::zero(), m }.into()]);
+                        self.scene.add(Instance::new(Arc::new(g), xf));
+                    }
+                }
+            }
+            "trianglemesh" => {
+                let idx = p.get("indices").map(|q| q.nums.clone()).unwrap_or_default();
+                let pts = p.get("P").map(|q| q.nums.clone()).unwrap_or_default();
+                if !idx.len().is_multiple_of(3) || !pts.len().is_multiple_of(3) || pts.is_empty() {
+                    return Err(LoadError::new(self.path(p.file), p.line, "trianglemesh needs P and indices in threes"));
+                }
+                let n = pts.len() / 3;
+                if idx.iter().any(|&i| i < 0.0 || i.fract() != 0.0 || i as usize >= n) {
+                    return Err(LoadError::new(self.path(p.file), p.line, "trianglemesh index is negative, fractional or out of range"));
+                }
+                let positions = pts.chunks(3).map(|c| Vec3::new(c[0], c[1], c[2])).collect();
+                let indices = idx.chunks(3).map(|c| [c[0] as usize, c[1] as usize, c[2] as usize]).collect();
+                let mut mesh = Mesh::new(positions, indices, m);
+                if let Some(q) = p.get("N").filter(|q| q.nums.len() == pts.len()) {
+                    mesh.normals = q.nums.chunks(3).map(|c| Vec3::new(c[0], c[1], c[2])).collect();
+                }
+                if let Some(q) = p.get("uv").or(p.get("st")).filter(|q| q.nums.len() == 2 * n) {
+                    mesh.uvs = q.nums.chunks(2).map(|c| (c[0], c[1])).collect();
+                }
+                for q in ["S", "alpha", "shadowalpha"] {
+                    p.get(q);
+                }
+                self.add_mesh(mesh, &xf);
+            }
+            "plymesh" => {
+                let f = match p.string("filename") {
+                    Some(f) => f,
+                    None => return Err(LoadError::new(self.path(p.file), p.line, "plymesh needs a filename")),
+                };
+                let ply = self.path(p.file).parent().unwrap_or(Path::new("")).join(f);
+                let mut mesh = load_ply(&ply)?;
+                mesh.m = m;
+                self.add_mesh(mesh, &xf);
+            }
+            _ => {
+                self.warn(p.file, p.line, format!("shape '{}' is not supported", ty));
+                p.skip_all();
+            }
+        }
+        Ok(())
+    }
+
+    fn add_mesh(&mut self, mut mesh: Mesh, xf: &Transform) {
+        for v in mesh.positions.iter_mut() {
+            *v = xf.point(*v);
+        }
+        for n in mesh.normals.iter_mut() {
+            *n = xf.normal(*n);
+        }
+        self.scene.add_mesh(mesh);
+    }
+
+    // 点光源と infinite はシーンの大きさが分かってから置く
+    fn add_lights(&mut self) {
+        let bounds = self
+            .scene
+            .objects
+            .iter()
+            .filter_map(|o| o.bounding_box())
+            .reduce(|a, b| surrounding_box(&a, &b));
+        let (center, diag) = match bounds {
+            Some(b) => ((b.min + b.max) * 0.5, (b.max - b.min).length().sqrt()),
+            None => (Vec3::zero(), 1.0),
+        };
+        // 強さ I の点光源を, 正面から見た面積 πr² で割った放射輝度で光る小球にする
+        let r = diag.max(1e-3) * 1e-3;
+        for (p, i) in std::mem::take(&mut self.point_lights) {
+            self.scene.add(Sphere::new(r, p, i * (1.0 / (PI * r * r)), Vec3::zero(), Refl::Diff));
+        }
+        if let Some(l) = self.env {
+            let far = diag.max(1.0) * 1e3;
+            self.scene.add(Sphere::new(far, center, l, Vec3::zero(), Refl::Diff));
+        }
+    }
+}
+
+// 回転, 一様な拡大, 平行移動だけなら拡大率
+fn similarity_scale(xf: &Transform) -> Option<f64> {
+    let cols = [0, 1, 2].map(|c| Vec3::new(xf.m.m[0][c], xf.m.m[1][c], xf.m.m[2][c]));
+    let len = cols.map(|c| c.dot(&c).sqrt());
+    let s = len[0];
+    let close = |a: f64, b: f64| (a - b).abs() <= 1e-9 * s.max(1.0);
+    if !close(len[1], s) || !close(len[2], s) {
+        return None;
+    }
+    if !close(cols[0].dot(&cols[1]) / s, 0.0) || !close(cols[1].dot(&cols[2]) / s, 0.0) || !close(cols[0].dot(&cols[2]) / s, 0.0) {
+        return None;
+    }
+    Some(s)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TRI: &str = "\"point3 P\" [0 0 0 1 0 0 0 1 0]";
+
+    fn pbrt_err(name: &str, text: &str) -> LoadError {
+        load_pbrt_file(&test_file(name, text.as_bytes())).err().unwrap()
+    }
+
+    #[test]
+    fn tokenizer_errors_carry_the_line() {
+        assert_eq!(tokenize("Shape\n\"sphere", 0).err().unwrap(), (2, "unterminated string".to_string()));
+        assert_eq!(tokenize("\"a\nb\"", 0).err().unwrap(), (1, "newline in string".to_string()));
+        assert_eq!(tokenize("\n\nScale 1 2.x 3", 0).err().unwrap(), (3, "bad number '2.x'".to_string()));
+        assert_eq!(tokenize("Shape \"sphere\" # \"\n[ 1 ]", 0).unwrap().len(), 5);
+    }
+
+    #[test]
+    fn trianglemesh_indices_are_checked() {
+        let ok = format!("WorldBegin\nShape \"trianglemesh\" {} \"integer indices\" [0 1 2]\n", TRI);
+        let (scene, _, _) = load_pbrt_file(&test_file("tri.pbrt", ok.as_bytes())).unwrap();
+        assert!(!scene.objects.is_empty());
+        for (name, idx) in [("tri_frac.pbrt", "0 1.5 2"), ("tri_neg.pbrt", "0 -1 2"), ("tri_range.pbrt", "0 1 3")] {
+            let e = pbrt_err(name, &format!("WorldBegin\n\nShape \"trianglemesh\" {} \"integer indices\" [{}]\n", TRI, idx));
+            assert_eq!(e.line, 3);
+            assert!(e.msg.contains("negative, fractional or out of range"), "{}", e);
+        }
+        let e = pbrt_err("tri_short.pbrt", &format!("WorldBegin\nShape \"trianglemesh\" {} \"integer indices\" [0 1]\n", TRI));
+        assert!(e.msg.contains("in threes"), "{}", e);
+    }
+
+    #[test]
+    fn include_loops_and_missing_files() {
+        let e = pbrt_err("self_include.pbrt", "Include \"self_include.pbrt\"\n");
+        assert!(e.msg.contains("Include files"), "{}", e);
+        let e = pbrt_err("missing_include.pbrt", "Include \"no_such_file.pbrt\"\n");
+        assert!(e.file.ends_with("no_such_file.pbrt"), "{}", e);
+        let e = pbrt_err("bad_param.pbrt", "WorldBegin\nShape \"sphere\" \"radius\" [1]\n");
+        assert!(e.msg.contains("bad parameter declaration 'radius'"), "{}", e);
+    }
+}
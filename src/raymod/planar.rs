@@ -0,0 +1,226 @@
+use crate::raymod::*;
+
+use std::f64::consts::*;
+
+// 平らな形状の箱は厚さ0になるので, 平行なレイでも外さないよう少し膨らませる
+const FLAT_PAD: f64 = 1e-4;
+
+// n に垂直な2本の単位ベクトル. UVの軸に使う
+pub fn tangent_basis(n: &Vec3) -> (Vec3, Vec3) {
+    let a = if n.x.abs() > 0.9 {
+        Vec3::new(0.0, 1.0, 0.0)
+    } else {
+        Vec3::new(1.0, 0.0, 0.0)
+    };
+    let t = (a % *n).norm();
+    let b = *n % t;
+    (t, b)
+}
+
+fn pad_box(min: Vec3, max: Vec3) -> AABB {
+    let pad = |lo: f64, hi: f64| if hi - lo < FLAT_PAD { (lo - FLAT_PAD, hi + FLAT_PAD) } else { (lo, hi) };
+    let (x0, x1) = pad(min.x, max.x);
+    let (y0, y1) = pad(min.y, max.y);
+    let (z0, z1) = pad(min.z, max.z);
+    AABB::new(Vec3::new(x0, y0, z0), Vec3::new(x1, y1, z1))
+}
+
+// 点 p を通り法線 n の無限平面. 箱を持たないのでBVHの外で直接調べる
+#[derive(Clone, Debug)]
+pub struct Plane {
+    pub p: Vec3,
+    pub n: Vec3,
+    pub m: Material,
+}
+
+impl Plane {
+    pub fn new(p: Vec3, n: Vec3, e: Vec3, c: Vec3, refl: Refl) -> Plane {
+        Plane {
+            p,
+            n: n.norm(),
+            m: Material::new(e, c, refl),
+        }
+    }
+}
+
+impl Shape for Plane {
+    #[inline]
+    fn intersect(&self, ray: &Ray, t_max: f64) -> Option<HitInfo<'_>> {
+        let denom = self.n.dot(&ray.d);
+        if denom.abs() < 1e-12 {
+            return None;
+        }
+        let t = (self.p - ray.o).dot(&self.n) / denom;
+        if t < EPS || t >= t_max {
+            return None;
+        }
+        let x = ray.o + ray.d * t;
+        // UVは平面上の座標そのもの (1単位で1周期)
+        let (tu, tv) = tangent_basis(&self.n);
+        let d = x - self.p;
+        Some(HitInfo::new(t, x, self.n, &self.m).with_uv(d.dot(&tu), d.dot(&tv)).with_tangent(Some(tu)))
+    }
+
+    fn bounding_box(&self) -> Option<AABB> {
+        None
+    }
+}
+
+// 角 q と2辺 u, v で張る平行四辺形. 法線は u × v の向き
+// u, v を軸に平行にとれば軸に沿った長方形になる
+#[derive(Clone, Debug)]
+pub struct Rect {
+    pub q: Vec3,
+    pub u: Vec3,
+    pub v: Vec3,
+    pub m: Material,
+    n: Vec3,
+    // 交点から (α, β) を求めるための u × v / |u × v|^2
+    w: Vec3,
+}
+
+impl Rect {
+    pub fn new(q: Vec3, u: Vec3, v: Vec3, e: Vec3, c: Vec3, refl: Refl) -> Rect {
+        let nn = u % v;
+        Rect {
+            q,
+            u,
+            v,
+            m: Material::new(e, c, refl),
+            n: nn.norm(),
+            w: nn * (1.0 / nn.dot(&nn)),
+        }
+    }
+}
+
+impl Shape for Rect {
+    #[inline]
+    fn intersect(&self, ray: &Ray, t_max: f64) -> Option<HitInfo<'_>> {
+        let denom = self.n.dot(&ray.d);
+        if denom.abs() < 1e-12 {
+            return None;
+        }
+        let t = (self.q - ray.o).dot(&self.n) / denom;
+        if t < EPS || t >= t_max {
+            return None;
+        }
+        let x = ray.o + ray.d * t;
+        let d = x - self.q;
+        let a = self.w.dot(&(d % self.v));
+        let b = self.w.dot(&(self.u % d));
+        if !(0.0..=1.0).contains(&a) || !(0.0..=1.0).contains(&b) {
+            return None;
+        }
+        Some(HitInfo::new(t, x, self.n, &self.m).with_uv(a, b).with_tangent(Some(self.u.norm())))
+    }
+
+    fn bounding_box(&self) -> Option<AABB> {
+        let ps = [self.q, self.q + self.u, self.q + self.v, self.q + self.u + self.v];
+        let mut min = ps[0];
+        let mut max = ps[0];
+        for p in ps.iter().skip(1) {
+            min = Vec3::new(min.x.min(p.x), min.y.min(p.y), min.z.min(p.z));
+            max = Vec3::new(max.x.max(p.x), max.y.max(p.y), max.z.max(p.z));
+        }
+        Some(pad_box(min, max))
+    }
+}
+
+// 中心 p, 法線 n, 半径 rad の円板
+#[derive(Clone, Debug)]
+pub struct Disk {
+    pub p: Vec3,
+    pub n: Vec3,
+    pub rad: f64,
+    pub m: Material,
+}
+
+impl Disk {
+    pub fn new(p: Vec3, n: Vec3, rad: f64, e: Vec3, c: Vec3, refl: Refl) -> Disk {
+        Disk {
+            p,
+            n: n.norm(),
+            rad,
+            m: Material::new(e, c, refl),
+        }
+    }
+}
+
+impl Shape for Disk {
+    #[inline]
+    fn intersect(&self, ray: &Ray, t_max: f64) -> Option<HitInfo<'_>> {
+        let denom = self.n.dot(&ray.d);
+        if denom.abs() < 1e-12 {
+            return None;
+        }
+        let t = (self.p - ray.o).dot(&self.n) / denom;
+        if t < EPS || t >= t_max {
+            return None;
+        }
+        let x = ray.o + ray.d * t;
+        let d = x - self.p;
+        let r2 = d.dot(&d);
+        if r2 > self.rad * self.rad {
+            return None;
+        }
+        // u は中心からの距離の割合, v は角度
+        let (tu, tv) = tangent_basis(&self.n);
+        let phi = d.dot(&tv).atan2(d.dot(&tu));
+        let phi = if phi < 0.0 { phi + 2.0 * PI } else { phi };
+        // u は中心から外へ向かって増える
+        let tangent = if r2 > 1e-24 { Some(d * (1.0 / r2.sqrt())) } else { None };
+        Some(HitInfo::new(t, x, self.n, &self.m).with_uv(r2.sqrt() / self.rad, phi / (2.0 * PI)).with_tangent(tangent))
+    }
+
+    fn bounding_box(&self) -> Option<AABB> {
+        // 各軸方向への広がりは rad * sqrt(1 - n_a^2)
+        let n = self.n;
+        let e = Vec3::new(
+            self.rad * (1.0 - n.x * n.x).max(0.0).sqrt(),
+            self.rad * (1.0 - n.y * n.y).max(0.0).sqrt(),
+            self.rad * (1.0 - n.z * n.z).max(0.0).sqrt(),
+        );
+        Some(pad_box(self.p - e, self.p + e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn down() -> Ray {
+        Ray::new(Vec3::new(0.2, 3.0, 0.3), Vec3::new(0.0, -1.0, 0.0))
+    }
+
+    #[test]
+    fn plane_hit_and_parallel_ray() {
+        let p = Plane::new(Vec3::zero(), Vec3::new(0.0, 1.0, 0.0), Vec3::zero(), Vec3::new(0.5, 0.5, 0.5), Refl::Diff);
+        let h = p.intersect(&down(), INF).unwrap();
+        assert!((h.t - 3.0).abs() < 1e-12);
+        assert!((h.n - Vec3::new(0.0, 1.0, 0.0)).length() < 1e-12);
+        assert!(p.intersect(&Ray::new(Vec3::new(0.0, 1.0, 0.0), Vec3::new(1.0, 0.0, 0.0)), INF).is_none());
+        assert!(p.intersect(&Ray::new(Vec3::new(0.0, 1.0, 0.0), Vec3::new(0.0, 1.0, 0.0)), INF).is_none());
+    }
+
+    #[test]
+    fn rect_uv_and_edges() {
+        // u × v = (0, 0, 1) × (1, 0, 0) で法線は +y
+        let r = Rect::new(Vec3::zero(), Vec3::new(0.0, 0.0, 1.0), Vec3::new(1.0, 0.0, 0.0), Vec3::zero(), Vec3::new(0.5, 0.5, 0.5), Refl::Diff);
+        let h = r.intersect(&down(), INF).unwrap();
+        assert!((h.t - 3.0).abs() < 1e-12);
+        assert!((h.n - Vec3::new(0.0, 1.0, 0.0)).length() < 1e-12);
+        let (u, v) = h.uv;
+        assert!((u - 0.3).abs() < 1e-12 && (v - 0.2).abs() < 1e-12);
+        assert!(r.intersect(&Ray::new(Vec3::new(1.2, 3.0, 0.3), Vec3::new(0.0, -1.0, 0.0)), INF).is_none());
+        assert!(r.intersect(&Ray::new(Vec3::new(0.2, 3.0, -0.1), Vec3::new(0.0, -1.0, 0.0)), INF).is_none());
+    }
+
+    #[test]
+    fn disk_inside_and_outside_radius() {
+        let d = Disk::new(Vec3::zero(), Vec3::new(0.0, 1.0, 0.0), 0.5, Vec3::zero(), Vec3::new(0.5, 0.5, 0.5), Refl::Diff);
+        let h = d.intersect(&down(), INF).unwrap();
+        assert!((h.t - 3.0).abs() < 1e-12);
+        assert!(d.intersect(&Ray::new(Vec3::new(0.4, 3.0, 0.4), Vec3::new(0.0, -1.0, 0.0)), INF).is_none());
+        assert!(d.intersect(&Ray::new(Vec3::new(0.0, 0.0, 0.0), Vec3::new(1.0, 0.0, 0.0)), INF).is_none());
+    }
+}
@@ -0,0 +1,381 @@
+use crate::raymod::*;
+
+use std::fs;
+use std::path::Path;
+
+#[derive(Clone, Copy, PartialEq)]
+enum PlyType {
+    I8,
+    U8,
+    I16,
+    U16,
+    I32,
+    U32,
+    F32,
+    F64,
+}
+
+impl PlyType {
+    fn parse(s: &str) -> Option<PlyType> {
+        Some(match s {
+            "char" | "int8" => PlyType::I8,
+            "uchar" | "uint8" => PlyType::U8,
+            "short" | "int16" => PlyType::I16,
+            "ushort" | "uint16" => PlyType::U16,
+            "int" | "int32" => PlyType::I32,
+            "uint" | "uint32" => PlyType::U32,
+            "float" | "float32" => PlyType::F32,
+            "double" | "float64" => PlyType::F64,
+            _ => return None,
+        })
+    }
+
+    fn size(self) -> usize {
+        match self {
+            PlyType::I8 | PlyType::U8 => 1,
+            PlyType::I16 | PlyType::U16 => 2,
+            PlyType::I32 | PlyType::U32 | PlyType::F32 => 4,
+            PlyType::F64 => 8,
+        }
+    }
+
+    // 整数の色は0..255などのsRGBなので線形の0..1に直す. 浮動小数はそのまま
+    fn linear_color(self, x: f64) -> f64 {
+        match self {
+            PlyType::U8 => (x / 255.0).powf(2.2),
+            PlyType::U16 => (x / 65535.0).powf(2.2),
+            _ => x,
+        }
+    }
+}
+
+enum PlyProp {
+    Scalar(String, PlyType),
+    List(String, PlyType, PlyType),
+}
+
+struct PlyElement {
+    name: String,
+    count: usize,
+    props: Vec<PlyProp>,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum PlyFormat {
+    Ascii,
+    BinaryLE,
+    BinaryBE,
+}
+
+// ヘッダ以降のデータを型に合わせて1つずつ読む
+struct PlyReader<'a> {
+    path: &'a Path,
+    format: PlyFormat,
+    data: &'a [u8],
+    pos: usize,
+    // ascii のとき今の行番号
+    line: usize,
+}
+
+impl<'a> PlyReader<'a> {
+    fn error(&self, msg: impl Into<String>) -> LoadError {
+        match self.format {
+            PlyFormat::Ascii => LoadError::new(self.path, self.line, msg),
+            _ => LoadError::new(self.path, 0, format!("at byte {}: {}", self.pos, msg.into())),
+        }
+    }
+
+    fn next_token(&mut self) -> Option<&'a str> {
+        while self.pos < self.data.len() && self.data[self.pos].is_ascii_whitespace() {
+            if self.data[self.pos] == b'\n' {
+                self.line += 1;
+            }
+            self.pos += 1;
+        }
+        let start = self.pos;
+        while self.pos < self.data.len() && !self.data[self.pos].is_ascii_whitespace() {
+            self.pos += 1;
+        }
+        if start == self.pos {
+            None
+        } else {
+            std::str::from_utf8(&self.data[start..self.pos]).ok()
+        }
+    }
+
+    fn read(&mut self, ty: PlyType) -> Result<f64, LoadError> {
+        if self.format == PlyFormat::Ascii {
+            let tok = self.next_token().ok_or_else(|| self.error("unexpected end of data"))?;
+            return tok
+                .parse::<f64>()
+                .map_err(|_| self.error(format!("bad number '{}'", tok)));
+        }
+        let n = ty.size();
+        if self.pos + n > self.data.len() {
+            return Err(self.error("unexpected end of data"));
+        }
+        let mut b = [0u8; 8];
+        b[..n].copy_from_slice(&self.data[self.pos..self.pos + n]);
+        if self.format == PlyFormat::BinaryBE {
+            b[..n].reverse();
+        }
+        self.pos += n;
+        Ok(match ty {
+            PlyType::I8 => b[0] as i8 as f64,
+            PlyType::U8 => b[0] as f64,
+            PlyType::I16 => i16::from_le_bytes([b[0], b[1]]) as f64,
+            PlyType::U16 => u16::from_le_bytes([b[0], b[1]]) as f64,
+            PlyType::I32 => i32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64,
+            PlyType::U32 => u32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64,
+            PlyType::F32 => f32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64,
+            PlyType::F64 => f64::from_le_bytes(b),
+        })
+    }
+}
+
+fn find_end_header(bytes: &[u8]) -> Option<usize> {
+    let key = b"end_header";
+    let at = bytes.windows(key.len()).position(|w| w == key)?;
+    let mut p = at + key.len();
+    if bytes.get(p) == Some(&b'\r') {
+        p += 1;
+    }
+    if bytes.get(p) == Some(&b'\n') {
+        Some(p + 1)
+    } else {
+        None
+    }
+}
+
+// ASCII / バイナリ(リトルエンディアン, ビッグエンディアン)のPLY
+// 頂点の位置, 法線, UV, 色と面のリストを読む. 整数の色はガンマ2.2を外して線形にする
+pub fn load_ply(path: &Path) -> Result<Mesh, LoadError> {
+    let bytes = fs::read(path).map_err(|e| LoadError::new(path, 0, e.to_string()))?;
+    let data_start = find_end_header(&bytes).ok_or_else(|| LoadError::new(path, 0, "no end_header"))?;
+    let header = String::from_utf8_lossy(&bytes[..data_start]);
+
+    let mut format = None;
+    let mut elements: Vec<PlyElement> = vec![];
+    let mut header_lines = 0;
+    for (i, l) in header.lines().enumerate() {
+        let line = i + 1;
+        header_lines = line;
+        let mut toks = l.split_whitespace();
+        let key = toks.next().unwrap_or("");
+        if line == 1 {
+            if key != "ply" {
+                return Err(LoadError::new(path, line, "not a PLY file"));
+            }
+            continue;
+        }
+        match key {
+            "format" => {
+                format = Some(match toks.next() {
+                    Some("ascii") => PlyFormat::Ascii,
+                    Some("binary_little_endian") => PlyFormat::BinaryLE,
+                    Some("binary_big_endian") => PlyFormat::BinaryBE,
+                    f => {
+                        return Err(LoadError::new(path, line, format!("unknown format {:?}", f)))
+                    }
+                });
+            }
+            "element" => {
+                let name = toks.next().unwrap_or("").to_string();
+                let count = toks
+                    .next()
+                    .and_then(|c| c.parse().ok())
+                    .ok_or_else(|| LoadError::new(path, line, "bad element count"))?;
+                elements.push(PlyElement {
+                    name,
+                    count,
+                    props: vec![],
+                });
+            }
+            "property" => {
+                let el = elements
+                    .last_mut()
+                    .ok_or_else(|| LoadError::new(path, line, "property before element"))?;
+                let t = toks.next().unwrap_or("");
+                let bad_type = |t: &str| LoadError::new(path, line, format!("unknown type '{}'", t));
+                if t == "list" {
+                    let ct = toks.next().unwrap_or("");
+                    let it = toks.next().unwrap_or("");
+                    let ct = PlyType::parse(ct).ok_or_else(|| bad_type(ct))?;
+                    let it = PlyType::parse(it).ok_or_else(|| bad_type(it))?;
+                    let name = toks.next().unwrap_or("").to_string();
+                    el.props.push(PlyProp::List(name, ct, it));
+                } else {
+                    let ty = PlyType::parse(t).ok_or_else(|| bad_type(t))?;
+                    let name = toks.next().unwrap_or("").to_string();
+                    el.props.push(PlyProp::Scalar(name, ty));
+                }
+            }
+            "comment" | "obj_info" | "end_header" | "" => {}
+            _ => return Err(LoadError::new(path, line, format!("unknown header line '{}'", key))),
+        }
+    }
+    let format = format.ok_or_else(|| LoadError::new(path, 0, "no format line"))?;
+
+    let mut r = PlyReader {
+        path,
+        format,
+        data: &bytes[data_start..],
+        pos: 0,
+        line: header_lines + 1,
+    };
+
+    let mut positions = vec![];
+    let mut normals = vec![];
+    let mut uvs = vec![];
+    let mut colors = vec![];
+    let mut indices = vec![];
+    let mut has_normals = false;
+    let mut has_uvs = false;
+    let mut has_colors = false;
+
+    for el in elements.iter() {
+        let is_vertex = el.name == "vertex";
+        let is_face = el.name == "face";
+        if is_vertex {
+            let has = |names: &[&str]| {
+                el.props
+                    .iter()
+                    .any(|p| matches!(p, PlyProp::Scalar(n, _) if names.contains(&n.as_str())))
+            };
+            has_normals = has(&["nx"]);
+            has_uvs = has(&["u", "s", "texture_u", "texture_s"]);
+            has_colors = has(&["red", "r", "diffuse_red"]);
+        }
+        for _ in 0..el.count {
+            let mut p = Vec3::zero();
+            let mut n = Vec3::zero();
+            let mut uv = (0.0, 0.0);
+            let mut c = Vec3::new(1.0, 1.0, 1.0);
+            for prop in el.props.iter() {
+                match prop {
+                    PlyProp::Scalar(name, ty) => {
+                        let x = r.read(*ty)?;
+                        if !is_vertex {
+                            continue;
+                        }
+                        match name.as_str() {
+                            "x" => p.x = x,
+                            "y" => p.y = x,
+                            "z" => p.z = x,
+                            "nx" => n.x = x,
+                            "ny" => n.y = x,
+                            "nz" => n.z = x,
+                            "u" | "s" | "texture_u" | "texture_s" => uv.0 = x,
+                            "v" | "t" | "texture_v" | "texture_t" => uv.1 = x,
+                            "red" | "r" | "diffuse_red" => c.x = ty.linear_color(x),
+                            "green" | "g" | "diffuse_green" => c.y = ty.linear_color(x),
+                            "blue" | "b" | "diffuse_blue" => c.z = ty.linear_color(x),
+                            _ => {}
+                        }
+                    }
+                    PlyProp::List(name, ct, it) => {
+                        let count = r.read(*ct)?;
+                        if count < 0.0 {
+                            return Err(r.error(format!("negative list length {}", count)));
+                        }
+                        // 長さはファイルの値なので, 先に確保せず読めた分だけ積む
+                        let mut face = vec![];
+                        for _ in 0..count as usize {
+                            face.push(r.read(*it)?);
+                        }
+                        if !is_face || (name != "vertex_indices" && name != "vertex_index") {
+                            continue;
+                        }
+                        if face.len() < 3 {
+                            return Err(r.error("face with fewer than 3 vertices"));
+                        }
+                        if let Some(bad) = face.iter().find(|&&i| i < 0.0 || i.fract() != 0.0) {
+                            return Err(r.error(format!("bad vertex index {}", bad)));
+                        }
+                        let idx: Vec<usize> = face.iter().map(|&i| i as usize).collect();
+                        for k in 1..idx.len() - 1 {
+                            indices.push([idx[0], idx[k], idx[k + 1]]);
+                        }
+                    }
+                }
+            }
+            if is_vertex {
+                positions.push(p);
+                normals.push(if n.length() > 0.0 { n.norm() } else { n });
+                uvs.push(uv);
+                colors.push(c);
+            }
+        }
+    }
+
+    if indices.is_empty() {
+        return Err(LoadError::new(path, 0, "no faces"));
+    }
+    if let Some(bad) = indices.iter().flatten().find(|&&i| i >= positions.len()) {
+        return Err(LoadError::new(
+            path,
+            0,
+            format!("face refers to vertex {} but there are only {}", bad, positions.len()),
+        ));
+    }
+    let mut mesh = Mesh::new(positions, indices, Material::new(Vec3::zero(), Vec3::new(0.75, 0.75, 0.75), Refl::Diff));
+    if has_normals {
+        mesh.normals = normals;
+    }
+    if has_uvs {
+        mesh.uvs = uvs;
+    }
+    if has_colors {
+        mesh.colors = colors;
+    }
+    Ok(mesh)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HEADER: &str = "ply\nformat ascii 1.0\nelement vertex 3\nproperty float x\nproperty float y\nproperty float z\nelement face 1\nproperty list uchar int vertex_indices\nend_header\n0 0 0\n1 0 0\n0 1 0\n";
+
+    fn ply_err(name: &str, data: &[u8]) -> LoadError {
+        load_ply(&test_file(name, data)).err().unwrap()
+    }
+
+    #[test]
+    fn ascii_triangle_loads() {
+        let mesh = load_ply(&test_file("ok.ply", format!("{}3 0 1 2\n", HEADER).as_bytes())).unwrap();
+        assert_eq!(mesh.indices.len(), 1);
+    }
+
+    #[test]
+    fn malformed_header() {
+        let e = ply_err("no_end.ply", b"ply\nformat ascii 1.0\nelement vertex 3\n");
+        assert!(e.msg.contains("no end_header"), "{}", e);
+        let e = ply_err("not_ply.ply", b"plx\nend_header\n");
+        assert!(e.msg.contains("not a PLY file"), "{}", e);
+        let e = ply_err("bad_count.ply", b"ply\nformat ascii 1.0\nelement vertex -1\nend_header\n");
+        assert_eq!(e.line, 3);
+    }
+
+    #[test]
+    fn bad_face_indices() {
+        let e = ply_err("negative.ply", format!("{}3 0 -1 2\n", HEADER).as_bytes());
+        assert!(e.msg.contains("bad vertex index -1"), "{}", e);
+        let e = ply_err("fraction.ply", format!("{}3 0 1.5 2\n", HEADER).as_bytes());
+        assert!(e.msg.contains("bad vertex index 1.5"), "{}", e);
+        let e = ply_err("short_face.ply", format!("{}2 0 1\n", HEADER).as_bytes());
+        assert!(e.msg.contains("fewer than 3"), "{}", e);
+    }
+
+    #[test]
+    fn huge_count_and_truncated_binary() {
+        // 個数を信じて確保すると落ちる大きさ. データが足りないエラーになればよい
+        let text = HEADER.replace("element face 1", "element face 4000000000");
+        let e = ply_err("huge.ply", text.as_bytes());
+        assert!(e.msg.contains("unexpected end of data"), "{}", e);
+        let mut data = HEADER.replace("ascii", "binary_little_endian").split("0 0 0").next().unwrap().as_bytes().to_vec();
+        data.extend_from_slice(&[0u8; 20]);
+        let e = ply_err("truncated.ply", &data);
+        assert!(e.msg.contains("at byte") && e.msg.contains("unexpected end of data"), "{}", e);
+    }
+}
@@ -0,0 +1,164 @@
+use crate::raymod::*;
+
+// BVHに入れる具体的な形状. dyn Shapeにすると遅いのでenumで静的に振り分ける
+#[derive(Clone, Debug)]
+pub enum Primitive {
+    Sphere(Sphere),
+    Triangle(Triangle),
+    Plane(Plane),
+    Rect(Rect),
+    Disk(Disk),
+    Cuboid(Cuboid),
+    Cylinder(Cylinder),
+    Cone(Cone),
+    Torus(Torus),
+    Instance(Instance),
+    Csg(Csg),
+    Sdf(SdfShape),
+    Heightfield(Heightfield),
+    Moving(Moving),
+}
+
+impl Shape for Primitive {
+    #[inline]
+    fn intersect(&self, ray: &Ray, t_max: f64) -> Option<HitInfo<'_>> {
+        match self {
+            Primitive::Sphere(s) => s.intersect(ray, t_max),
+            Primitive::Triangle(s) => s.intersect(ray, t_max),
+            Primitive::Plane(s) => s.intersect(ray, t_max),
+            Primitive::Rect(s) => s.intersect(ray, t_max),
+            Primitive::Disk(s) => s.intersect(ray, t_max),
+            Primitive::Cuboid(s) => s.intersect(ray, t_max),
+            Primitive::Cylinder(s) => s.intersect(ray, t_max),
+            Primitive::Cone(s) => s.intersect(ray, t_max),
+            Primitive::Torus(s) => s.intersect(ray, t_max),
+            Primitive::Instance(s) => s.intersect(ray, t_max),
+            Primitive::Csg(s) => s.intersect(ray, t_max),
+            Primitive::Sdf(s) => s.intersect(ray, t_max),
+            Primitive::Heightfield(s) => s.intersect(ray, t_max),
+            Primitive::Moving(s) => s.intersect(ray, t_max),
+        }
+    }
+
+    fn bounding_box(&self) -> Option<AABB> {
+        match self {
+            Primitive::Sphere(s) => s.bounding_box(),
+            Primitive::Triangle(s) => s.bounding_box(),
+            Primitive::Plane(s) => s.bounding_box(),
+            Primitive::Rect(s) => s.bounding_box(),
+            Primitive::Disk(s) => s.bounding_box(),
+            Primitive::Cuboid(s) => s.bounding_box(),
+            Primitive::Cylinder(s) => s.bounding_box(),
+            Primitive::Cone(s) => s.bounding_box(),
+            Primitive::Torus(s) => s.bounding_box(),
+            Primitive::Instance(s) => s.bounding_box(),
+            Primitive::Csg(s) => s.bounding_box(),
+            Primitive::Sdf(s) => s.bounding_box(),
+            Primitive::Heightfield(s) => s.bounding_box(),
+            Primitive::Moving(s) => s.bounding_box(),
+        }
+    }
+
+    fn cost(&self) -> f64 {
+        match self {
+            Primitive::Sphere(s) => s.cost(),
+            Primitive::Triangle(s) => s.cost(),
+            Primitive::Plane(s) => s.cost(),
+            Primitive::Rect(s) => s.cost(),
+            Primitive::Disk(s) => s.cost(),
+            Primitive::Cuboid(s) => s.cost(),
+            Primitive::Cylinder(s) => s.cost(),
+            Primitive::Cone(s) => s.cost(),
+            Primitive::Torus(s) => s.cost(),
+            Primitive::Instance(s) => s.cost(),
+            Primitive::Csg(s) => s.cost(),
+            Primitive::Sdf(s) => s.cost(),
+            Primitive::Heightfield(s) => s.cost(),
+            Primitive::Moving(s) => s.cost(),
+        }
+    }
+}
+
+impl From<Sphere> for Primitive {
+    fn from(s: Sphere) -> Primitive {
+        Primitive::Sphere(s)
+    }
+}
+
+impl From<Triangle> for Primitive {
+    fn from(s: Triangle) -> Primitive {
+        Primitive::Triangle(s)
+    }
+}
+
+impl From<Plane> for Primitive {
+    fn from(s: Plane) -> Primitive {
+        Primitive::Plane(s)
+    }
+}
+
+impl From<Rect> for Primitive {
+    fn from(s: Rect) -> Primitive {
+        Primitive::Rect(s)
+    }
+}
+
+impl From<Disk> for Primitive {
+    fn from(s: Disk) -> Primitive {
+        Primitive::Disk(s)
+    }
+}
+
+impl From<Cuboid> for Primitive {
+    fn from(s: Cuboid) -> Primitive {
+        Primitive::Cuboid(s)
+    }
+}
+
+impl From<Cylinder> for Primitive {
+    fn from(s: Cylinder) -> Primitive {
+        Primitive::Cylinder(s)
+    }
+}
+
+impl From<Cone> for Primitive {
+    fn from(s: Cone) -> Primitive {
+        Primitive::Cone(s)
+    }
+}
+
+impl From<Torus> for Primitive {
+    fn from(s: Torus) -> Primitive {
+        Primitive::Torus(s)
+    }
+}
+
+impl From<Instance> for Primitive {
+    fn from(s: Instance) -> Primitive {
+        Primitive::Instance(s)
+    }
+}
+
+impl From<Csg> for Primitive {
+    fn from(s: Csg) -> Primitive {
+        Primitive::Csg(s)
+    }
+}
+
+impl From<SdfShape> for Primitive {
+    fn from(s: SdfShape) -> Primitive {
+        Primitive::Sdf(s)
+    }
+}
+
+impl From<Heightfield> for Primitive {
+    fn from(s: Heightfield) -> Primitive {
+        Primitive::Heightfield(s)
+    }
+}
+
+impl From<Moving> for Primitive {
+    fn from(s: Moving) -> Primitive {
+        Primitive::Moving(s)
+    }
+}
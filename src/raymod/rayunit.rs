@@ -1,6 +1,6 @@
 use crate::raymod::*;
 
-use std::f64::consts::*;
+use std::sync::Arc;
 
 pub fn random() -> f64 {
     rand::random::<f64>()
@@ -10,26 +10,88 @@ pub fn random() -> f64 {
 pub struct Ray {
     pub o: Vec3,
     pub d: Vec3,
+    // シャッターが開いている間のどの時刻のレイか. 動く物体はこの時刻の位置で調べる
+    pub time: f64,
 }
 
 impl Ray {
     pub fn new(o: Vec3, d: Vec3) -> Ray {
-        Ray { o, d }
+        Ray { o, d, time: 0.0 }
+    }
+
+    pub fn timed(o: Vec3, d: Vec3, time: f64) -> Ray {
+        Ray { o, d, time }
     }
 }
 
+#[derive(Clone, Copy, Debug)]
 pub enum Refl {
     Diff,
     Spec,
     Refr,
 }
 
-pub struct Sphere {
-    pub rad: f64,
-    pub p: Vec3,
+// 屈折率の目安
+pub const GLASS_IOR: f64 = 1.5;
+pub const WATER_IOR: f64 = 1.33;
+
+#[derive(Clone, Copy, Debug)]
+pub struct Material {
     pub e: Vec3,
     pub c: Vec3,
     pub refl: Refl,
+    // Refr の中の屈折率. 外側の屈折率はレイがどの物体の中にいるかで決まる
+    pub ior: f64,
+    // Refr が重なったところでは priority の大きい方の中にいるとみなす
+    pub priority: u32,
+    // Spec と Refr の面の粗さ. 0 なら鏡や透明なガラス, 1 に近いほどぼやける
+    // 異方性のときは (接線の向き, 従法線の向き) で, 接線は交点の u が増える向き
+    pub roughness: (f64, f64),
+}
+
+impl Material {
+    pub fn new(e: Vec3, c: Vec3, refl: Refl) -> Material {
+        Material {
+            e,
+            c,
+            refl,
+            ior: GLASS_IOR,
+            priority: 0,
+            roughness: (0.0, 0.0),
+        }
+    }
+
+    pub fn with_ior(mut self, ior: f64) -> Material {
+        self.ior = ior;
+        self
+    }
+
+    pub fn with_priority(mut self, priority: u32) -> Material {
+        self.priority = priority;
+        self
+    }
+
+    pub fn with_roughness(mut self, roughness: f64) -> Material {
+        self.roughness = (roughness, roughness);
+        self
+    }
+
+    // 接線の向きと従法線の向きで違う粗さ. ヘアラインの金属など
+    pub fn with_anisotropic_roughness(mut self, ru: f64, rv: f64) -> Material {
+        self.roughness = (ru, rv);
+        self
+    }
+
+    pub fn is_rough(&self) -> bool {
+        self.roughness.0 > 0.0 || self.roughness.1 > 0.0
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Sphere {
+    pub rad: f64,
+    pub p: Vec3,
+    pub m: Material,
 }
 
 
@@ -37,12 +99,15 @@ impl Sphere {
 	pub fn new(rad:f64,p:Vec3,e:Vec3,c:Vec3,refl:Refl)->Sphere{
 		Sphere {
 			rad,
-			p,e,c,
-			refl,
+			p,
+			m: Material::new(e, c, refl),
 		}
 	}
-		
-    pub fn intersect(&self, ray: &Ray) -> Option<f64> {
+}
+
+impl Shape for Sphere {
+    #[inline]
+    fn intersect(&self, ray: &Ray, t_max: f64) -> Option<HitInfo<'_>> {
         let po = self.p - ray.o;
         let b = po.dot(&ray.d);
         let d4 = b * b - po.dot(&po) + self.rad * self.rad;
@@ -59,49 +124,95 @@ impl Sphere {
             return None;
         }
 
-        if t1 > EPS {
-            return Some(t1);
-        } else {
-            return Some(t2);
+        let t = if t1 > EPS { t1 } else { t2 };
+        if t >= t_max {
+            return None;
         }
+        let p = ray.o + ray.d * t;
+        let n = (p - self.p).norm();
+        // 接線は y 軸のまわりを回る向き. 極では None
+        let (x, z) = (p.x - self.p.x, p.z - self.p.z);
+        let r = (x * x + z * z).sqrt();
+        let tangent = if r > 1e-12 { Some(Vec3::new(-z / r, 0.0, x / r)) } else { None };
+        Some(HitInfo::new(t, p, n, &self.m).with_tangent(tangent))
     }
-}
 
-pub struct InterStruct{
-    pub b:bool,
-    pub t:f64,
-    pub id:usize,
+    fn bounding_box(&self) -> Option<AABB> {
+        let r = Vec3::new(self.rad, self.rad, self.rad);
+        Some(AABB::new(self.p - r, self.p + r))
+    }
 }
 
 // #[derive(Default)]
 pub struct Scene {
-	pub objects: Vec<Sphere>,
+	pub objects: Vec<Primitive>,
+	pub bvh: Option<BVH>,
+	pub camera: Camera,
 }
 
 impl Scene {
-    pub fn add(&mut self, obj: Sphere) {
-	self.objects.push(obj);
+    pub fn add<P: Into<Primitive>>(&mut self, obj: P) {
+	self.objects.push(obj.into());
+	self.bvh = None;
+    }
+
+    // メッシュの三角形を全部登録する
+    pub fn add_mesh(&mut self, mesh: Mesh) {
+        for tri in Mesh::triangles(Arc::new(mesh)) {
+            self.add(tri);
+        }
     }
 
     pub fn init() -> Scene {
 	Scene {
 	    objects: vec![],
+	    bvh: None,
+	    camera: Camera::default(),
         }
     }
-    pub fn intersect(&self, r: &Ray) -> InterStruct {
-        let mut ir=InterStruct{b:false,t:INF,id:0};
-        for i in (0..self.objects.len() ).rev() {
-            match self.objects[i].intersect(r) {
-                Some(d)=>{
-                    if d < ir.t {
-                        ir.t=d;
-                        ir.id=i;
-                    }
-                },
-                None=>{},
+
+    // オブジェクトを追加し終わったら呼ぶ
+    pub fn build_bvh(&mut self, leaf_size: usize) {
+        self.bvh = if self.objects.is_empty() {
+            None
+        } else {
+            Some(BVH::new(&self.objects, leaf_size))
+        };
+    }
+
+    pub fn intersect(&self, r: &Ray) -> Option<HitInfo<'_>> {
+        // 葉1つだけの木では飛ばせるものがないので, 下の線形探索で調べる
+        if let Some(bvh) = self.bvh.as_ref().filter(|b| !b.is_flat()) {
+            return bvh.intersect(&self.objects, r, INF);
+        }
+        let mut hit: Option<HitInfo> = None;
+        let mut closest = INF;
+        for obj in self.objects.iter().rev() {
+            if let Some(h) = obj.intersect(r, closest) {
+                closest = h.t;
+                hit = Some(h);
             }
         }
-        ir.b=ir.t<INF;
-        return ir;
+        hit
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sphere_hits_from_outside_and_inside() {
+        let s = Sphere::new(1.0, Vec3::new(0.0, 0.0, -5.0), Vec3::zero(), Vec3::new(0.5, 0.5, 0.5), Refl::Diff);
+        let h = s.intersect(&Ray::new(Vec3::zero(), Vec3::new(0.0, 0.0, -1.0)), INF).unwrap();
+        assert!((h.t - 4.0).abs() < 1e-12);
+        assert!((h.n - Vec3::new(0.0, 0.0, 1.0)).length() < 1e-12);
+        // 中からは奥の面に当たり, 法線は外向き
+        let h = s.intersect(&Ray::new(Vec3::new(0.0, 0.0, -5.0), Vec3::new(0.0, 1.0, 0.0)), INF).unwrap();
+        assert!((h.t - 1.0).abs() < 1e-12);
+        assert!((h.n - Vec3::new(0.0, 1.0, 0.0)).length() < 1e-12);
+        assert!(s.intersect(&Ray::new(Vec3::new(0.0, 2.0, 0.0), Vec3::new(0.0, 0.0, -1.0)), INF).is_none());
+        assert!(s.intersect(&Ray::new(Vec3::zero(), Vec3::new(0.0, 0.0, 1.0)), INF).is_none());
+        assert!(s.intersect(&Ray::new(Vec3::zero(), Vec3::new(0.0, 0.0, -1.0)), 3.0).is_none());
     }
 }
@@ -0,0 +1,252 @@
+use crate::raymod::*;
+
+use rayon::prelude::*;
+use std::f64::consts::*;
+use std::path::Path;
+
+// 鏡とガラスの間を跳ね続けるとスタックが溢れるので, ここで打ち切る
+const MAX_DEPTH: u8 = 64;
+
+// media はレイが今いる Refr の物体. カメラからのレイは空で始める
+pub fn radiance(r: &Ray, depth: u8, scene: &Scene, media: &MediumStack) -> Vec3 {
+    let hit = match scene.intersect(r) {
+        Some(hit) => hit,
+        None => return Vec3::zero(),
+    };
+    let obj = hit.m;
+    let x = hit.p;
+    let n = hit.n;
+    let nl = if n.dot(&r.d) < 0.0 { n } else { n * -1.0 };
+    let e = hit.emission.unwrap_or(obj.e);
+    let c = hit.color.unwrap_or(obj.c);
+    let mut f = c;
+    let p = f64::max(f.x,f64::max(f.y,f.z));
+    let depth = depth.saturating_add(1);
+    if depth > MAX_DEPTH {
+        return e;
+    }
+    let into = n.dot(&nl) > 0.0;
+    let crossing = match obj.refl {
+        Refl::Refr => Some(media.cross(obj, into)),
+        _ => None,
+    };
+    if let Some(Crossing::Pass(inside)) = &crossing {
+        return e + radiance(&Ray::timed(x, r.d, r.time), depth, scene, inside);
+    }
+    // ロシアンルーレットで生き残ったレイの重み
+    let mut rr = 1.0;
+    if depth > 5 {
+        if random() < p {
+            rr = 1.0 / p;
+            f = f * rr;
+        } else {
+            return e ;
+        }
+    }
+
+    match (obj.refl, crossing) {
+        (Refl::Diff, _) => {
+            let r1 = 2.0 * PI * random();
+            let r2 = random();
+            let r2s = r2.sqrt();
+            let w = nl;
+            let u = ((
+                if w.x.abs() > 0.1 {Vec3::new(0.0, 1.0, 0.0)
+                } else if w.z.abs()>0.1  { Vec3::new(1.0, 0.0, 0.0) }
+                else {Vec3::new(0.0,0.0,1.0)}) % w).norm();
+            let v = w % u;
+            let d = (u * f64::cos(r1) * r2s + v * f64::sin(r1) * r2s + w * (1.0 - r2).sqrt()).norm();
+            e  + f.mult(&radiance(&Ray::timed(x, d, r.time), depth, scene, media))
+        }
+        (Refl::Refr, Some(Crossing::Refract { n1, n2, inside })) if obj.is_rough() => {
+            // すりガラス (Walter et al. 2007). 見える微小面の法線を選び, その面で反射か屈折をする
+            let frame = ShadingFrame::new(nl, hit.tangent);
+            let wo = frame.local(r.d * -1.0);
+            if wo.z <= 0.0 {
+                return e;
+            }
+            let ggx = Ggx::new(obj.roughness);
+            let h = ggx.sample_visible(wo, random(), random());
+            let eta = n1 / n2;
+            let wr = h * (2.0 * wo.dot(&h)) - wo;
+            let wt = refract(wo, h, eta);
+            let re = fresnel_dielectric(wo.dot(&h), eta);
+            // 反射も屈折も, フレネルで選べば重みは G2 / G1 になる
+            // 選んだ向きが面の逆の側へ出たら, 隣の微小面に遮られたとみなす
+            let reflect = || {
+                if wr.z > 0.0 {
+                    radiance(&Ray::timed(x, frame.world(wr), r.time), depth, scene, media) * (ggx.g2(wo, wr) / ggx.g1(wo))
+                } else {
+                    Vec3::zero()
+                }
+            };
+            let transmit = |wt: Vec3| {
+                if wt.z < 0.0 {
+                    radiance(&Ray::timed(x, frame.world(wt), r.time), depth, scene, &inside) * (ggx.g2(wo, wt) / ggx.g1(wo))
+                } else {
+                    Vec3::zero()
+                }
+            };
+            let l = match wt {
+                None => reflect(),
+                Some(wt) if depth > 2 => {
+                    if random() < re {
+                        reflect()
+                    } else {
+                        transmit(wt)
+                    }
+                }
+                Some(wt) => reflect() * re + transmit(wt) * (1.0 - re),
+            };
+            e + f.mult(&l)
+        }
+        (Refl::Refr, Some(Crossing::Refract { n1, n2, inside })) => {
+            // n1 はレイが来た側, n2 は向こう側の屈折率
+            let refl_ray = Ray::timed(x, r.d - n * 2.0 * n.dot(&r.d), r.time);
+            let nnt = n1 / n2;
+            let ddn = r.d.dot(&nl);
+            let cos2t = 1.0 - nnt * nnt * (1.0 - ddn * ddn);
+            if cos2t < 0.0 {
+                e + f.mult(&radiance(&refl_ray, depth, scene, media))
+            } else {
+                let tdir = (r.d * nnt - nl * (ddn * nnt + cos2t.sqrt())).norm();
+                let a = n2 - n1;
+                let b = n2 + n1;
+                let r0 = a * a / (b * b);
+                // Schlick の近似は屈折率の小さい側の角度で使う
+                let c = 1.0 - (if n1 <= n2 { -ddn } else { -tdir.dot(&nl) });
+                let re = r0 + (1.0 - r0) * c * c * c * c * c;
+                let tr = 1.0 - re;
+                let p = 0.25 + 0.5 * re;
+                let rp = re / p;
+                let tp = tr / (1.0 - p);
+                e
+                    + f.mult(
+                        &(if depth > 2 {
+                            if random() < p {
+                                radiance(&refl_ray, depth, scene, media) * rp
+                            } else {
+                                radiance(&Ray::timed(x, tdir, r.time), depth, scene, &inside) * tp
+                            }
+                        } else {
+                            radiance(&refl_ray, depth, scene, media) * re
+                                + radiance(&Ray::timed(x, tdir, r.time), depth, scene, &inside) * tr
+                        }),
+                    )
+            }
+        }
+        (Refl::Spec, _) if obj.is_rough() => {
+            // 粗い金属. 見える微小面の法線を選んでそこで鏡のように跳ね返す
+            let frame = ShadingFrame::new(nl, hit.tangent);
+            let wo = frame.local(r.d * -1.0);
+            if wo.z <= 0.0 {
+                return e;
+            }
+            let ggx = Ggx::new(obj.roughness);
+            let h = ggx.sample_visible(wo, random(), random());
+            let wi = h * (2.0 * wo.dot(&h)) - wo;
+            if wi.z <= 0.0 {
+                return e;
+            }
+            // 見える法線の分布で選んだので, D と cos は確率密度と打ち消し合い G2 / G1 が残る
+            let w = schlick(c, wo.dot(&h)) * (ggx.g2(wo, wi) / ggx.g1(wo) * rr);
+            e + w.mult(&radiance(&Ray::timed(x, frame.world(wi), r.time), depth, scene, media))
+        }
+        _ => {
+            // Refl.Spec
+            e + f.mult(&radiance( &Ray::timed(x, r.d - n * 2.0 * n.dot(&r.d), r.time), depth, scene, media))
+        }
+    }
+}
+
+// 描いた画像. 画素は線形の RGB で, 左上から行ごとに並ぶ
+#[derive(Clone, Debug)]
+pub struct Image {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<Color>,
+}
+
+impl Image {
+    pub fn new(width: usize, height: usize) -> Image {
+        Image {
+            width,
+            height,
+            pixels: vec![Color::zero(); width * height],
+        }
+    }
+
+    pub fn get(&self, x: usize, y: usize) -> Color {
+        self.pixels[y * self.width + x]
+    }
+
+    // ガンマ 2.2 をかけた 8bit の RGB
+    pub fn to_rgb8(&self) -> image::RgbImage {
+        image::RgbImage::from_fn(self.width as u32, self.height as u32, |x, y| {
+            let c = self.get(x as usize, y as usize);
+            image::Rgb([to_int(c.x), to_int(c.y), to_int(c.z)])
+        })
+    }
+
+    // 形式は拡張子で決まる
+    pub fn save(&self, path: &Path) -> image::ImageResult<()> {
+        self.to_rgb8().save(path)
+    }
+}
+
+// シーンを描く. 画素を 2x2 に分け, それぞれで samples 本のレイを追う
+// Scene の BVH は先に作っておく (なければ全部の物体を順に調べる)
+#[derive(Clone, Debug, Default)]
+pub struct Renderer {
+    // 進み具合を標準エラーに出す
+    pub progress: bool,
+}
+
+impl Renderer {
+    pub fn new() -> Renderer {
+        Renderer::default()
+    }
+
+    pub fn with_progress(mut self, progress: bool) -> Renderer {
+        self.progress = progress;
+        self
+    }
+
+    // settings は samples だけを見る. 既定は 1
+    pub fn render(&self, scene: &Scene, settings: &RenderSettings) -> Image {
+        let film = scene.camera.film();
+        let (w, h) = (film.view_width, film.view_height);
+        let samps = settings.samples.unwrap_or(1);
+        let mut image = Image::new(film.width, film.height);
+
+        let bands: Vec<(usize, &mut [Color])> = image.pixels.chunks_mut(film.width).enumerate().collect();
+        bands.into_par_iter().for_each(|(y, band)| {
+            if self.progress && (y % 10) == 0 {
+                eprintln!( "Rendering ({} spp) {:5.2}%", samps * 4,100.0 * (y as f64) / ((film.height as f64) - 1.0) );
+            }
+            for (x, pixel) in band.iter_mut().enumerate() {
+                let (cam, x, y) = film.view(x, y);
+                let y2 = h - y - 1;
+                let mut r = Vec3::zero();
+                for sy in 0..2 {
+                    for sx in 0..2 {
+                        for _s in 0..samps {
+                            let r1 = 2.0 * random();
+                            let dx = if r1 < 1.0 { r1.sqrt() - 1.0 } else { 1.0 - (2.0 - r1).sqrt() };
+                            let r2 = 2.0 * random();
+                            let dy = if r2 < 1.0 { r2.sqrt() - 1.0 } else { 1.0 - (2.0 - r2).sqrt() };
+                            let ray = cam.ray((((sx as f64) + 0.5 + dx) / 2.0 + (x as f64)) / (w as f64),
+                                              (((sy as f64) + 0.5 + dy) / 2.0 + (y2 as f64)) / (h as f64));
+                            if let Some(ray) = ray {
+                                r = r + radiance(&ray, 0, scene, &MediumStack::default()) * (1.0 / (samps as f64));
+                            }
+                        }
+                        *pixel = *pixel + r * (1.0 / 4.0);
+                        r = Vec3::zero();
+                    }
+                }
+            }
+        });
+        image
+    }
+}
@@ -1,35 +1,42 @@
 use crate::raymod::*;
 
 use std::f64::consts::*;
+use std::sync::Arc;
 
 
+// -m 0〜9 は scenes/model0.toml〜model9.toml から作る. ここにあるのはそのファイルの元になった組み立てで,
+// ファイルと同じシーンになるかをテストで確かめる
+#[cfg(test)]
 impl Scene {
     pub fn model_init0(&mut self)->bool{
         //-------------Debug Scene sc1-------------
-	self.add( Sphere::new( 1e5,   Vec3::new( 1e5 + 1.0,     40.8, 81.6),Vec3::zero(),                Vec3::new(0.75, 0.25, 0.25), Refl::Diff ));//left
-	self.add( Sphere::new( 1e5,   Vec3::new(-1e5 + 99.0,    40.8, 81.6),Vec3::zero(),                Vec3::new(0.25, 0.25, 0.75), Refl::Diff ));//right
-	self.add( Sphere::new( 1e5,   Vec3::new(50.0,            40.8, 1e5),Vec3::zero(),                Vec3::new(0.75, 0.75, 0.75), Refl::Diff ));//front
-	self.add( Sphere::new( 1e5,   Vec3::new(50.0,    40.8,-1e5 + 170.0),Vec3::zero(),                Vec3::zero(), Refl::Diff ));//back
-	self.add( Sphere::new( 1e5,   Vec3::new(50.0,            1e5, 81.6),Vec3::zero(),                Vec3::new(0.75, 0.75, 0.75), Refl::Diff ));//bottom
-	self.add( Sphere::new( 1e5,   Vec3::new(50.0,-1e5 + 81.6+4.0, 81.6),Vec3::zero(),                Vec3::new(0.75, 0.75, 0.75), Refl::Diff ));//top
+        // 壁は無限平面, 天井の光源は長方形にする
+	self.add( Plane::new( Vec3::new( 1.0,  40.8,  81.6), Vec3::new( 1.0, 0.0, 0.0), Vec3::zero(), Vec3::new(0.75, 0.25, 0.25), Refl::Diff ));//left
+	self.add( Plane::new( Vec3::new(99.0,  40.8,  81.6), Vec3::new(-1.0, 0.0, 0.0), Vec3::zero(), Vec3::new(0.25, 0.25, 0.75), Refl::Diff ));//right
+	self.add( Plane::new( Vec3::new(50.0,  40.8,   0.0), Vec3::new( 0.0, 0.0, 1.0), Vec3::zero(), Vec3::new(0.75, 0.75, 0.75), Refl::Diff ));//front
+	self.add( Plane::new( Vec3::new(50.0,  40.8, 170.0), Vec3::new( 0.0, 0.0,-1.0), Vec3::zero(), Vec3::zero(), Refl::Diff ));//back
+	self.add( Plane::new( Vec3::new(50.0,   0.0,  81.6), Vec3::new( 0.0, 1.0, 0.0), Vec3::zero(), Vec3::new(0.75, 0.75, 0.75), Refl::Diff ));//bottom
+	self.add( Plane::new( Vec3::new(50.0,  85.6,  81.6), Vec3::new( 0.0,-1.0, 0.0), Vec3::zero(), Vec3::new(0.75, 0.75, 0.75), Refl::Diff ));//top
 	self.add( Sphere::new( 16.5,  Vec3::new(27.0,           16.5, 47.0),Vec3::zero(),                Vec3::new(1.0, 1.0, 1.0) * 0.999, Refl::Spec));
 	self.add( Sphere::new( 16.5,  Vec3::new(73.0,           16.5, 78.0),Vec3::zero(),                Vec3::new(1.0, 1.0, 1.0) * 0.999, Refl::Refr));
-	self.add( Sphere::new( 600.0, Vec3::new(50.0, 681.6-0.27+4.0, 81.6),Vec3::new(12.0, 12.0, 12.0), Vec3::zero(), Refl::Diff));
+        // 元の半径600の球が天井から出ていた部分(半径18の円)とほぼ同じ面積. 天井から少し下げて下向きにする
+	self.add( Rect::new( Vec3::new(34.0, 85.5, 65.6), Vec3::new(32.0, 0.0, 0.0), Vec3::new(0.0, 0.0, 32.0), Vec3::new(12.0, 12.0, 12.0), Vec3::zero(), Refl::Diff));//light
 	true
     }
     
 
     pub fn model_init1(&mut self)->bool{
         //----------cornel box sc1-----------
-        self.add( Sphere::new( 1e5,   Vec3::new(1e5 + 1.0,      40.8, 81.6), Vec3::zero(),                 Vec3::new(0.75, 0.25, 0.25), Refl::Diff ));
-        self.add( Sphere::new( 1e5,   Vec3::new(-1e5 + 99.0,    40.8, 81.6), Vec3::zero(),                 Vec3::new(0.25, 0.25, 0.75), Refl::Diff ));
-        self.add( Sphere::new( 1e5,   Vec3::new(50.0,           40.8, 1e5 ), Vec3::zero(),                 Vec3::new(0.75, 0.75, 0.75), Refl::Diff ));
-        self.add( Sphere::new( 1e5,   Vec3::new(50.0,           40.8,-1e5 + 170.0), Vec3::zero(),          Vec3::zero(), Refl::Diff ));
-        self.add( Sphere::new( 1e5,   Vec3::new(50.0,            1e5, 81.6), Vec3::zero(),                 Vec3::new(0.75, 0.75, 0.75), Refl::Diff ));
-        self.add( Sphere::new( 1e5,   Vec3::new(50.0,-1e5 + 81.6+4.0, 81.6), Vec3::zero(),                 Vec3::new(0.75, 0.75, 0.75), Refl::Diff ));
+        self.add( Plane::new( Vec3::new( 1.0,  40.8,  81.6), Vec3::new( 1.0, 0.0, 0.0), Vec3::zero(), Vec3::new(0.75, 0.25, 0.25), Refl::Diff ));
+        self.add( Plane::new( Vec3::new(99.0,  40.8,  81.6), Vec3::new(-1.0, 0.0, 0.0), Vec3::zero(), Vec3::new(0.25, 0.25, 0.75), Refl::Diff ));
+        self.add( Plane::new( Vec3::new(50.0,  40.8,   0.0), Vec3::new( 0.0, 0.0, 1.0), Vec3::zero(), Vec3::new(0.75, 0.75, 0.75), Refl::Diff ));
+        self.add( Plane::new( Vec3::new(50.0,  40.8, 170.0), Vec3::new( 0.0, 0.0,-1.0), Vec3::zero(), Vec3::zero(), Refl::Diff ));
+        self.add( Plane::new( Vec3::new(50.0,   0.0,  81.6), Vec3::new( 0.0, 1.0, 0.0), Vec3::zero(), Vec3::new(0.75, 0.75, 0.75), Refl::Diff ));
+        self.add( Plane::new( Vec3::new(50.0,  85.6,  81.6), Vec3::new( 0.0,-1.0, 0.0), Vec3::zero(), Vec3::new(0.75, 0.75, 0.75), Refl::Diff ));
         self.add( Sphere::new( 16.5,  Vec3::new(27.0,           16.5, 47.0), Vec3::zero(),                 Vec3::new(1.0, 1.0, 1.0) * 0.999, Refl::Spec ));
         self.add( Sphere::new( 16.5,  Vec3::new(73.0,           16.5, 78.0), Vec3::zero(),                 Vec3::new(1.0, 1.0, 1.0) * 0.999, Refl::Refr ));
-        self.add( Sphere::new( 1.5,   Vec3::new(50.0,      81.6-16.5, 81.6), Vec3::new(4.0,4.0,4.0)*100.0, Vec3::zero(), Refl::Diff ));
+        // 元の半径1.5の点光源と全光束がほぼ同じ 20x20 の天井灯
+        self.add( Rect::new( Vec3::new(40.0, 85.5, 71.6), Vec3::new(20.0, 0.0, 0.0), Vec3::new(0.0, 0.0, 20.0), Vec3::new(28.0, 28.0, 28.0), Vec3::zero(), Refl::Diff ));
 	true
     }
     
@@ -63,13 +70,13 @@ impl Scene {
         self.add(Sphere::new(5e0,  Vec3::new(-0.2,0.16,-1.0)*1e4,   Vec3::new(1.00, 0.843, 0.698)*1e2,   Vec3::zero(), Refl::Diff));  // star
         self.add(Sphere::new(5e0,  Vec3::new(0.0,0.18,-1.0)*1e4,    Vec3::new(1.00, 0.851, 0.710)*1e2,   Vec3::zero(), Refl::Diff));  // star
         self.add(Sphere::new(5e0,  Vec3::new(0.3,0.15,-1.0)*1e4,    Vec3::new(0.671, 0.780, 1.00)*1e2,   Vec3::zero(), Refl::Diff));  // star
-        self.add(Sphere::new(3.5e4,Vec3::new(600.0,-3.5e4+1.0,300.0), Vec3::zero(),                      Vec3::new(0.6,0.8,1.0)*0.01,  Refl::Refr));   //pool
+        self.add(Sphere{rad:3.5e4,p:Vec3::new(600.0,-3.5e4+1.0,300.0), m:Material::new(Vec3::zero(),     Vec3::new(0.6,0.8,1.0)*0.01,  Refl::Refr).with_ior(WATER_IOR)});   //pool
         self.add(Sphere::new(5e4,  Vec3::new(-500.0,-5e4  ,0.0),    Vec3::zero(),                        Vec3::new(1.0,1.0,1.0)*0.35,  Refl::Diff));    //hill
         self.add(Sphere::new(16.5, Vec3::new(27.0,0.0,47.0),        Vec3::zero(),                        Vec3::new(1.0,1.0,1.0)*0.33, Refl::Diff)); //hut
         self.add(Sphere::new(7.0,  Vec3::new(27.0+8.0*SQRT_2,0.0,47.0+8.0*SQRT_2),Vec3::zero(),          Vec3::new(1.0,1.0,1.0)*0.33,  Refl::Diff)); //door
-        self.add(Sphere::new(500.0,Vec3::new(-1e3,-300.0,-3e3),     Vec3::zero(),                        Vec3::new(1.0,1.0,1.0)*0.351,    Refl::Diff));  //mnt
-        self.add(Sphere::new(830.0,Vec3::new(0.0,-500.0,-3e3),     Vec3::zero(),                         Vec3::new(1.0,1.0,1.0)*0.354,    Refl::Diff));  //mnt
-        self.add(Sphere::new(490.0,Vec3::new(1e3,-300.0,-3e3),      Vec3::zero(),                        Vec3::new(1.0,1.0,1.0)*0.352,    Refl::Diff));  //mnt 
+        // 遠くの山並み. 手前の丘は半径5e4の球の上でほぼ平らなので球のまま
+        self.add(Heightfield::new(&HeightMap::noise(129, 33, 5, 3).fade_edges(), Vec3::new(-1700.0, -100.0, -3400.0), Vec3::new(3400.0, 430.0, 800.0),
+                                  Material::new(Vec3::zero(), Vec3::new(1.0,1.0,1.0)*0.352, Refl::Diff)));  //mnt
         true
     }
 
@@ -80,10 +87,15 @@ impl Scene {
         self.add( Sphere::new(160.0, cen+Vec3::new(0.0, 600.0, -500.0), Vec3::new(1.0,1.0,1.0)*2e2,          Vec3::zero(),  Refl::Diff)); // sun
         self.add( Sphere::new(800.0, cen+Vec3::new(0.0,-880.0,-9120.0), Vec3::new(1.0,1.0,1.0)*2e1,          Vec3::zero(),  Refl::Diff)); // horizon
         self.add( Sphere::new(10000.0,cen+Vec3::new(0.0,0.0,-200.00),   Vec3::new(0.0627, 0.188, 0.569)*1e0, Vec3::new(1.0,1.0,1.0)*0.4,  Refl::Diff)); // sky
-        self.add( Sphere::new(800.0, cen+Vec3::new(0.0,-720.0,-200.0),  Vec3::zero(),              Vec3::new(0.110, 0.898, 1.00)*0.996,  Refl::Refr)); // water
+        self.add( Sphere{rad:800.0, p:cen+Vec3::new(0.0,-720.0,-200.0), m:Material::new(Vec3::zero(), Vec3::new(0.110, 0.898, 1.00)*0.996,  Refl::Refr).with_ior(WATER_IOR)}); // water
         self.add( Sphere::new(790.0, cen+Vec3::new(0.0,-720.0,-200.0),  Vec3::zero(),               Vec3::new(0.4,0.3,0.04)*0.6, Refl::Diff)); // earth
-        self.add( Sphere::new(325.0, cen+Vec3::new(0.0,-255.0,-50.0),   Vec3::zero(),               Vec3::new(0.4,0.3,0.04)*0.8, Refl::Diff)); // island
-        self.add( Sphere::new(275.0, cen+Vec3::new(0.0,-205.0,-33.0),   Vec3::zero(),               Vec3::new(0.02,0.3,0.02)*0.75,Refl::Diff)); // grass
+        // 島は同じ高さの格子を2枚重ねる. 低いところは砂の方が上に出て浜になり, 高いところは草が覆う
+        // 海の底の earth は水越しにしか見えないので球のまま
+        let island = HeightMap::noise(65, 65, 4, 1).fade_edges();
+        self.add( Heightfield::new(&island, cen+Vec3::new(-100.0, -10.0, -60.0), Vec3::new(200.0, 85.0, 220.0),
+                                   Material::new(Vec3::zero(), Vec3::new(0.4,0.3,0.04)*0.8, Refl::Diff))); // island
+        self.add( Heightfield::new(&island, cen+Vec3::new(-100.0, -25.0, -60.0), Vec3::new(200.0, 110.0, 220.0),
+                                   Material::new(Vec3::zero(), Vec3::new(0.02,0.3,0.02)*0.75, Refl::Diff))); // grass
         true
     }
 
@@ -94,20 +106,21 @@ impl Scene {
         self.add( Sphere::new(8000.0, cen+Vec3::new(0.0,-8000.0,-900.0),Vec3::new(1.0,0.4,0.1)*5e-1,        Vec3::zero(),  Refl::Diff)); // sun
         self.add( Sphere::new(1e4,    cen+Vec3::zero(),                 Vec3::new(0.631, 0.753, 1.00)*3e-1, Vec3::new(1.0,1.0,1.0)*0.5,  Refl::Diff)); // sky
 
-        self.add( Sphere::new(150.0,  cen+Vec3::new(-350.0, 0.0,-100.0), Vec3::zero(),           Vec3::new(1.0,1.0,1.0)*0.3,  Refl::Diff)); // mnt
-        self.add( Sphere::new(200.0,  cen+Vec3::new(-210.0, 0.0,-100.0), Vec3::zero(),           Vec3::new(1.0,1.0,1.0)*0.3,  Refl::Diff)); // mnt
-        self.add( Sphere::new(145.0,  cen+Vec3::new(-210.0,85.0,-100.0), Vec3::zero(),           Vec3::new(1.0,1.0,1.0)*0.8,  Refl::Diff)); // snow
-        self.add( Sphere::new(150.0,  cen+Vec3::new(-50.0,  0.0,-100.0), Vec3::zero(),           Vec3::new(1.0,1.0,1.0)*0.3,  Refl::Diff)); // mnt
-        self.add( Sphere::new(150.0,  cen+Vec3::new(100.0,  0.0,-100.0), Vec3::zero(),           Vec3::new(1.0,1.0,1.0)*0.3,  Refl::Diff)); // mnt
-        self.add( Sphere::new(125.0,  cen+Vec3::new(250.0,  0.0,-100.0), Vec3::zero(),           Vec3::new(1.0,1.0,1.0)*0.3,  Refl::Diff)); // mnt
-        self.add( Sphere::new(150.0,  cen+Vec3::new(375.0,  0.0,-100.0), Vec3::zero(),           Vec3::new(1.0,1.0,1.0)*0.3,  Refl::Diff)); // mnt
+        // 山並みと雪は同じ高さの格子を2枚重ね, 高いところだけ雪が岩の上に出る
+        let range = HeightMap::noise(129, 49, 5, 11).fade_edges();
+        self.add( Heightfield::new(&range, cen+Vec3::new(-500.0,-20.0,-300.0), Vec3::new(1000.0, 220.0, 400.0),
+                                   Material::new(Vec3::zero(), Vec3::new(1.0,1.0,1.0)*0.3, Refl::Diff))); // mnt
+        self.add( Heightfield::new(&range, cen+Vec3::new(-500.0,-60.0,-300.0), Vec3::new(1000.0, 270.0, 400.0),
+                                   Material::new(Vec3::zero(), Vec3::new(1.0,1.0,1.0)*0.8, Refl::Diff))); // snow
 
+        // 麓の台地, 海, 草地は半径2500や8000の球の上でほぼ平らなので球のまま
         self.add( Sphere::new(2500.0, cen+Vec3::new(0.0,-2400.0,-500.0), Vec3::zero(),           Vec3::new(1.0,1.0,1.0)*0.1,  Refl::Diff)); // mnt base
 
-        self.add( Sphere::new(8000.0, cen+Vec3::new(0.0,-8000.0, 200.0), Vec3::zero(),           Vec3::new(0.2,0.2,1.0),    Refl::Refr)); // water
+        self.add( Sphere{rad:8000.0, p:cen+Vec3::new(0.0,-8000.0, 200.0), m:Material::new(Vec3::zero(), Vec3::new(0.2,0.2,1.0),    Refl::Refr).with_ior(WATER_IOR)}); // water
         self.add( Sphere::new(8000.0, cen+Vec3::new(0.0,-8000.0,1100.0), Vec3::zero(),           Vec3::new(0.0,0.3,0.0),   Refl::Diff)); // grass
         self.add( Sphere::new(8.0   , cen+Vec3::new(-75.0, -5.0, 850.0), Vec3::zero(),           Vec3::new(0.0,0.3,0.0),     Refl::Diff)); // bush
-        self.add( Sphere::new(30.0,   cen+Vec3::new(0.0,   23.0, 825.0), Vec3::zero(),           Vec3::new(1.0,1.0,1.0)*0.996, Refl::Refr)); // ball
+        // 半分水に沈んだガラス玉. 重なったところはガラスの中とみなす
+        self.add( Sphere{rad:30.0,   p:cen+Vec3::new(0.0,   23.0, 825.0), m:Material::new(Vec3::zero(), Vec3::new(1.0,1.0,1.0)*0.996, Refl::Refr).with_priority(1)}); // ball
 
         self.add( Sphere::new(30.0,   cen+Vec3::new(200.0,280.0,-400.0), Vec3::zero(),           Vec3::new(1.0,1.0,1.0)*0.8,  Refl::Diff));   // clouds
         self.add( Sphere::new(37.0,   cen+Vec3::new(237.0,280.0,-400.0), Vec3::zero(),           Vec3::new(1.0,1.0,1.0)*0.8,  Refl::Diff));   // clouds
@@ -216,6 +229,239 @@ impl Scene {
         self.add( Sphere::new(6.5, Vec3::new(50.0,1.8+6.0*2.0+16.0*0.6*2.0+11.0*0.6*2.0+7.0*0.6,47.0),   Vec3::zero(), scc,  Refl::Diff));//"tree"
 	true
     }
+}
+
+impl Scene {
+    pub fn model_init10(&mut self)->bool{
+        //---------------mesh sc10-----------
+	self.add( Plane::new( Vec3::new( 1.0,  40.8,  81.6), Vec3::new( 1.0, 0.0, 0.0), Vec3::zero(), Vec3::new(0.75, 0.25, 0.25), Refl::Diff ));//left
+	self.add( Plane::new( Vec3::new(99.0,  40.8,  81.6), Vec3::new(-1.0, 0.0, 0.0), Vec3::zero(), Vec3::new(0.25, 0.25, 0.75), Refl::Diff ));//right
+	self.add( Plane::new( Vec3::new(50.0,  40.8,   0.0), Vec3::new( 0.0, 0.0, 1.0), Vec3::zero(), Vec3::new(0.75, 0.75, 0.75), Refl::Diff ));//front
+	self.add( Plane::new( Vec3::new(50.0,  40.8, 170.0), Vec3::new( 0.0, 0.0,-1.0), Vec3::zero(), Vec3::zero(), Refl::Diff ));//back
+	self.add( Plane::new( Vec3::new(50.0,   0.0,  81.6), Vec3::new( 0.0, 1.0, 0.0), Vec3::zero(), Vec3::new(0.75, 0.75, 0.75), Refl::Diff ));//bottom
+	self.add( Plane::new( Vec3::new(50.0,  85.6,  81.6), Vec3::new( 0.0,-1.0, 0.0), Vec3::zero(), Vec3::new(0.75, 0.75, 0.75), Refl::Diff ));//top
+	self.add( Rect::new( Vec3::new(34.0, 85.5, 65.6), Vec3::new(32.0, 0.0, 0.0), Vec3::new(0.0, 0.0, 32.0), Vec3::new(12.0, 12.0, 12.0), Vec3::zero(), Refl::Diff));//light
+
+        // 頂点法線で滑らかにした鏡の球
+        self.add_mesh(Mesh::uv_sphere(Vec3::new(27.0, 16.5, 47.0), 16.5, 32, 16,
+                                      Material::new(Vec3::zero(), Vec3::new(1.0, 1.0, 1.0) * 0.999, Refl::Spec)));
+        // 法線を持たない角張ったピラミッド
+        let (c, w, h) = (Vec3::new(73.0, 0.0, 78.0), 18.0, 36.0);
+        self.add_mesh(Mesh::new(
+            vec![c + Vec3::new(-w, 0.0, -w), c + Vec3::new(w, 0.0, -w),
+                 c + Vec3::new(w, 0.0, w),   c + Vec3::new(-w, 0.0, w),
+                 c + Vec3::new(0.0, h, 0.0)],
+            vec![[0, 1, 2], [0, 2, 3], [0, 4, 1], [1, 4, 2], [2, 4, 3], [3, 4, 0]],
+            Material::new(Vec3::zero(), Vec3::new(0.75, 0.65, 0.25), Refl::Diff)));
+	true
+    }
+
+
+    pub fn model_init11(&mut self)->bool{
+        //---------------solids sc11-----------
+	self.add( Plane::new( Vec3::new( 1.0,  40.8,  81.6), Vec3::new( 1.0, 0.0, 0.0), Vec3::zero(), Vec3::new(0.75, 0.25, 0.25), Refl::Diff ));//left
+	self.add( Plane::new( Vec3::new(99.0,  40.8,  81.6), Vec3::new(-1.0, 0.0, 0.0), Vec3::zero(), Vec3::new(0.25, 0.25, 0.75), Refl::Diff ));//right
+	self.add( Plane::new( Vec3::new(50.0,  40.8,   0.0), Vec3::new( 0.0, 0.0, 1.0), Vec3::zero(), Vec3::new(0.75, 0.75, 0.75), Refl::Diff ));//front
+	self.add( Plane::new( Vec3::new(50.0,  40.8, 170.0), Vec3::new( 0.0, 0.0,-1.0), Vec3::zero(), Vec3::zero(), Refl::Diff ));//back
+	self.add( Plane::new( Vec3::new(50.0,   0.0,  81.6), Vec3::new( 0.0, 1.0, 0.0), Vec3::zero(), Vec3::new(0.75, 0.75, 0.75), Refl::Diff ));//bottom
+	self.add( Plane::new( Vec3::new(50.0,  85.6,  81.6), Vec3::new( 0.0,-1.0, 0.0), Vec3::zero(), Vec3::new(0.75, 0.75, 0.75), Refl::Diff ));//top
+	self.add( Rect::new( Vec3::new(34.0, 85.5, 65.6), Vec3::new(32.0, 0.0, 0.0), Vec3::new(0.0, 0.0, 32.0), Vec3::new(12.0, 12.0, 12.0), Vec3::zero(), Refl::Diff));//light
+
+        let white = Material::new(Vec3::zero(), Vec3::new(0.75, 0.75, 0.75), Refl::Diff);
+        // y軸まわりに20度回した背の高い箱
+        let a = 20.0 * PI / 180.0;
+        self.add( Cuboid::oriented(Vec3::new(28.0, 22.0, 45.0), Vec3::new(a.cos(), 0.0, -a.sin()), Vec3::new(0.0, 1.0, 0.0),
+                                   Vec3::new(10.0, 22.0, 10.0), white));
+        // 軸に沿った台と, その上の円錐
+        self.add( Cuboid::new(Vec3::new(55.0, 0.0, 95.0), Vec3::new(75.0, 10.0, 115.0), white));
+        self.add( Cone::new(Vec3::new(65.0, 10.0, 105.0), Vec3::new(0.0, 1.0, 0.0), 8.0, 20.0, true,
+                            Material::new(Vec3::zero(), Vec3::new(0.75, 0.65, 0.25), Refl::Diff)));
+        // 鏡の円柱
+        self.add( Cylinder::new(Vec3::new(80.0, 0.0, 40.0), Vec3::new(0.0, 1.0, 0.0), 9.0, 25.0, true,
+                                Material::new(Vec3::zero(), Vec3::new(1.0, 1.0, 1.0) * 0.999, Refl::Spec)));
+        // 傾けたガラスのトーラス
+        self.add( Torus::new(Vec3::new(35.0, 12.0, 100.0), Vec3::new(0.3, 1.0, 0.5), 10.0, 3.5,
+                             Material::new(Vec3::zero(), Vec3::new(1.0, 1.0, 1.0) * 0.999, Refl::Refr)));
+	true
+    }
+
+
+    pub fn model_init12(&mut self)->bool{
+        //---------------instances sc12-----------
+	self.add( Plane::new( Vec3::new( 1.0,  40.8,  81.6), Vec3::new( 1.0, 0.0, 0.0), Vec3::zero(), Vec3::new(0.75, 0.25, 0.25), Refl::Diff ));//left
+	self.add( Plane::new( Vec3::new(99.0,  40.8,  81.6), Vec3::new(-1.0, 0.0, 0.0), Vec3::zero(), Vec3::new(0.25, 0.25, 0.75), Refl::Diff ));//right
+	self.add( Plane::new( Vec3::new(50.0,  40.8,   0.0), Vec3::new( 0.0, 0.0, 1.0), Vec3::zero(), Vec3::new(0.75, 0.75, 0.75), Refl::Diff ));//front
+	self.add( Plane::new( Vec3::new(50.0,  40.8, 170.0), Vec3::new( 0.0, 0.0,-1.0), Vec3::zero(), Vec3::zero(), Refl::Diff ));//back
+	self.add( Plane::new( Vec3::new(50.0,   0.0,  81.6), Vec3::new( 0.0, 1.0, 0.0), Vec3::zero(), Vec3::new(0.75, 0.75, 0.75), Refl::Diff ));//bottom
+	self.add( Plane::new( Vec3::new(50.0,  85.6,  81.6), Vec3::new( 0.0,-1.0, 0.0), Vec3::zero(), Vec3::new(0.75, 0.75, 0.75), Refl::Diff ));//top
+	self.add( Rect::new( Vec3::new(34.0, 85.5, 65.6), Vec3::new(32.0, 0.0, 0.0), Vec3::new(0.0, 0.0, 32.0), Vec3::new(12.0, 12.0, 12.0), Vec3::zero(), Refl::Diff));//light
+
+        // 正八面体を2色用意して, らせん状に300個並べる
+        let octahedron = |c: Vec3| {
+            let x = Vec3::new(1.0, 0.0, 0.0);
+            let y = Vec3::new(0.0, 1.0, 0.0);
+            let z = Vec3::new(0.0, 0.0, 1.0);
+            Mesh::new(
+                vec![x, x * -1.0, y, y * -1.0, z, z * -1.0],
+                vec![[0, 2, 4], [2, 1, 4], [1, 3, 4], [3, 0, 4], [2, 0, 5], [1, 2, 5], [3, 1, 5], [0, 3, 5]],
+                Material::new(Vec3::zero(), c, Refl::Diff))
+        };
+        let gems = [Arc::new(Group::from_mesh(octahedron(Vec3::new(0.75, 0.65, 0.25)))),
+                    Arc::new(Group::from_mesh(octahedron(Vec3::new(0.25, 0.65, 0.45))))];
+        for i in 0..300 {
+            let a = i as f64 * 0.15;
+            let p = Vec3::new(50.0 + 24.0 * a.cos(), 4.0 + i as f64 * 0.22, 75.0 + 24.0 * a.sin());
+            let xf = Transform::translate(p) * Transform::rotate_y(-a) * Transform::rotate_x(2.0 * a)
+                * Transform::scale(Vec3::new(2.5, 1.2, 1.2));
+            self.add( Instance::new(gems[i % 2].clone(), xf));
+        }
+        // 単位球を傾けて潰した楕円体の鏡. 法線は逆行列の転置で戻す
+        let ball = Arc::new(Group::new(vec![Sphere::new(1.0, Vec3::zero(), Vec3::zero(), Vec3::new(1.0, 1.0, 1.0) * 0.999, Refl::Spec).into()]));
+        self.add( Instance::new(ball, Transform::translate(Vec3::new(50.0, 14.0, 75.0)) * Transform::rotate_z(0.4)
+                                * Transform::scale(Vec3::new(18.0, 10.0, 10.0))));
+	true
+    }
+
+
+    pub fn model_init13(&mut self)->bool{
+        //---------------csg sc13-----------
+	self.add( Plane::new( Vec3::new( 1.0,  40.8,  81.6), Vec3::new( 1.0, 0.0, 0.0), Vec3::zero(), Vec3::new(0.75, 0.25, 0.25), Refl::Diff ));//left
+	self.add( Plane::new( Vec3::new(99.0,  40.8,  81.6), Vec3::new(-1.0, 0.0, 0.0), Vec3::zero(), Vec3::new(0.25, 0.25, 0.75), Refl::Diff ));//right
+	self.add( Plane::new( Vec3::new(50.0,  40.8,   0.0), Vec3::new( 0.0, 0.0, 1.0), Vec3::zero(), Vec3::new(0.75, 0.75, 0.75), Refl::Diff ));//front
+	self.add( Plane::new( Vec3::new(50.0,  40.8, 170.0), Vec3::new( 0.0, 0.0,-1.0), Vec3::zero(), Vec3::zero(), Refl::Diff ));//back
+	self.add( Plane::new( Vec3::new(50.0,   0.0,  81.6), Vec3::new( 0.0, 1.0, 0.0), Vec3::zero(), Vec3::new(0.75, 0.75, 0.75), Refl::Diff ));//bottom
+	self.add( Plane::new( Vec3::new(50.0,  85.6,  81.6), Vec3::new( 0.0,-1.0, 0.0), Vec3::zero(), Vec3::new(0.75, 0.75, 0.75), Refl::Diff ));//top
+	self.add( Rect::new( Vec3::new(34.0, 85.5, 65.6), Vec3::new(32.0, 0.0, 0.0), Vec3::new(0.0, 0.0, 32.0), Vec3::new(12.0, 12.0, 12.0), Vec3::zero(), Refl::Diff));//light
+
+        let glass = Vec3::new(1.0, 1.0, 1.0) * 0.999;
+        let white = Vec3::new(0.75, 0.75, 0.75);
+        // 半径30の球2つの共通部分で, 厚さ8, 半径15の両凸レンズ
+        let c = Vec3::new(30.0, 30.0, 80.0);
+        self.add( Csg::intersection(Sphere::new(30.0, c + Vec3::new(0.0, 0.0, 26.0), Vec3::zero(), glass, Refl::Refr),
+                                    Sphere::new(30.0, c - Vec3::new(0.0, 0.0, 26.0), Vec3::zero(), glass, Refl::Refr)));
+        // 球殻の上半分を切り取った器
+        let c = Vec3::new(70.0, 14.0, 70.0);
+        let shell = Csg::difference(Sphere::new(14.0, c, Vec3::zero(), Vec3::new(0.75, 0.65, 0.25), Refl::Diff),
+                                    Sphere::new(12.5, c, Vec3::zero(), Vec3::new(0.75, 0.65, 0.25), Refl::Diff));
+        self.add( Csg::difference(shell, Cuboid::new(c - Vec3::new(15.0, 0.0, 15.0), c + Vec3::new(15.0, 15.0, 15.0),
+                                                     Material::new(Vec3::zero(), white, Refl::Diff))));
+        // 上を平らに切ったガラス球. 切り口は球の材質になるので箱の材質は使われない
+        let c = Vec3::new(48.0, 10.0, 110.0);
+        self.add( Csg::difference(Sphere::new(10.0, c, Vec3::zero(), glass, Refl::Refr),
+                                  Cuboid::new(c + Vec3::new(-11.0, 5.0, -11.0), c + Vec3::new(11.0, 11.0, 11.0),
+                                              Material::new(Vec3::zero(), white, Refl::Diff))));
+	true
+    }
 
+
+    pub fn model_init14(&mut self)->bool{
+        //---------------sdf sc14-----------
+	self.add( Plane::new( Vec3::new( 1.0,  40.8,  81.6), Vec3::new( 1.0, 0.0, 0.0), Vec3::zero(), Vec3::new(0.75, 0.25, 0.25), Refl::Diff ));//left
+	self.add( Plane::new( Vec3::new(99.0,  40.8,  81.6), Vec3::new(-1.0, 0.0, 0.0), Vec3::zero(), Vec3::new(0.25, 0.25, 0.75), Refl::Diff ));//right
+	self.add( Plane::new( Vec3::new(50.0,  40.8,   0.0), Vec3::new( 0.0, 0.0, 1.0), Vec3::zero(), Vec3::new(0.75, 0.75, 0.75), Refl::Diff ));//front
+	self.add( Plane::new( Vec3::new(50.0,  40.8, 170.0), Vec3::new( 0.0, 0.0,-1.0), Vec3::zero(), Vec3::zero(), Refl::Diff ));//back
+	self.add( Plane::new( Vec3::new(50.0,   0.0,  81.6), Vec3::new( 0.0, 1.0, 0.0), Vec3::zero(), Vec3::new(0.75, 0.75, 0.75), Refl::Diff ));//bottom
+	self.add( Plane::new( Vec3::new(50.0,  85.6,  81.6), Vec3::new( 0.0,-1.0, 0.0), Vec3::zero(), Vec3::new(0.75, 0.75, 0.75), Refl::Diff ));//top
+	self.add( Rect::new( Vec3::new(34.0, 85.5, 65.6), Vec3::new(32.0, 0.0, 0.0), Vec3::new(0.0, 0.0, 32.0), Vec3::new(12.0, 12.0, 12.0), Vec3::zero(), Refl::Diff));//light
+
+        // ねじった角の丸い柱
+        let c = Vec3::new(25.0, 25.0, 60.0);
+        let column = Sdf::RoundBox(Vec3::new(6.0, 25.0, 6.0), 1.5).twist(0.08).translate(c);
+        self.add( SdfShape::new(column, AABB::new(c - Vec3::new(9.0, 25.0, 9.0), c + Vec3::new(9.0, 25.0, 9.0)),
+                                Material::new(Vec3::zero(), Vec3::new(0.75, 0.65, 0.25), Refl::Diff)).with_step_scale(0.5));
+        // 球を滑らかにつないだ塊とトーラス
+        let c = Vec3::new(72.0, 14.0, 60.0);
+        let blob = Sdf::Sphere(9.0).smooth_union(Sdf::Sphere(6.0).translate(Vec3::new(8.0, 7.0, 2.0)), 4.0)
+            .smooth_union(Sdf::Sphere(5.0).translate(Vec3::new(-7.0, 9.0, -3.0)), 4.0)
+            .smooth_union(Sdf::Torus(10.0, 2.0).translate(Vec3::new(0.0, -8.0, 0.0)), 3.0)
+            .translate(c);
+        self.add( SdfShape::new(blob, AABB::new(c - Vec3::new(13.0, 14.0, 13.0), c + Vec3::new(17.0, 17.0, 13.0)),
+                                Material::new(Vec3::zero(), Vec3::new(1.0, 1.0, 1.0) * 0.999, Refl::Spec)));
+        // 小さな球を格子状に繰り返し, 箱で切り取ったガラスの塊
+        let c = Vec3::new(50.0, 8.0, 105.0);
+        let lattice = Sdf::Sphere(1.8).repeat(Vec3::new(4.0, 4.0, 4.0))
+            .intersection(Sdf::Box(Vec3::new(8.0, 8.0, 8.0)))
+            .translate(c);
+        // 球の間で屈折を繰り返して抜け出しにくいので, 色を付けてロシアンルーレットで打ち切られやすくする
+        self.add( SdfShape::new(lattice, AABB::new(c - Vec3::new(8.0, 8.0, 8.0), c + Vec3::new(8.0, 8.0, 8.0)),
+                                Material::new(Vec3::zero(), Vec3::new(0.8, 0.9, 0.95), Refl::Refr)));
+	true
+    }
+
+
+    pub fn model_init15(&mut self, map: &HeightMap)->bool{
+        //---------------terrain sc15-----------
+        // 空と太陽は island sc4 と同じ. 地面は高さの格子, 海は平面
+        let  cen:Vec3=Vec3{x:50.0,y:-20.0,z:-860.0};
+        self.add( Sphere::new(160.0, cen+Vec3::new(0.0, 600.0, -500.0), Vec3::new(1.0,1.0,1.0)*2e2,          Vec3::zero(),  Refl::Diff)); // sun
+        self.add( Sphere::new(10000.0,cen+Vec3::new(0.0,0.0,-200.00),   Vec3::new(0.0627, 0.188, 0.569)*1e0, Vec3::new(1.0,1.0,1.0)*0.4,  Refl::Diff)); // sky
+        self.add( Plane{p:Vec3::new(0.0, 0.0, 0.0), n:Vec3::new(0.0, 1.0, 0.0), m:Material::new(Vec3::zero(), Vec3::new(0.110, 0.898, 1.00)*0.996, Refl::Refr).with_ior(WATER_IOR)}); // water
+        self.add( Heightfield::new(map, Vec3::new(-300.0, -40.0, -900.0), Vec3::new(700.0, 90.0, 1000.0),
+                                   Material::new(Vec3::zero(), Vec3::new(0.4,0.35,0.1)*0.8, Refl::Diff))); // land
+	true
+    }
+
+
+    pub fn model_init16(&mut self)->bool{
+        //---------------motion blur sc16-----------
+	self.add( Plane::new( Vec3::new( 1.0,  40.8,  81.6), Vec3::new( 1.0, 0.0, 0.0), Vec3::zero(), Vec3::new(0.75, 0.25, 0.25), Refl::Diff ));//left
+	self.add( Plane::new( Vec3::new(99.0,  40.8,  81.6), Vec3::new(-1.0, 0.0, 0.0), Vec3::zero(), Vec3::new(0.25, 0.25, 0.75), Refl::Diff ));//right
+	self.add( Plane::new( Vec3::new(50.0,  40.8,   0.0), Vec3::new( 0.0, 0.0, 1.0), Vec3::zero(), Vec3::new(0.75, 0.75, 0.75), Refl::Diff ));//front
+	self.add( Plane::new( Vec3::new(50.0,  40.8, 170.0), Vec3::new( 0.0, 0.0,-1.0), Vec3::zero(), Vec3::zero(), Refl::Diff ));//back
+	self.add( Plane::new( Vec3::new(50.0,   0.0,  81.6), Vec3::new( 0.0, 1.0, 0.0), Vec3::zero(), Vec3::new(0.75, 0.75, 0.75), Refl::Diff ));//bottom
+	self.add( Plane::new( Vec3::new(50.0,  85.6,  81.6), Vec3::new( 0.0,-1.0, 0.0), Vec3::zero(), Vec3::new(0.75, 0.75, 0.75), Refl::Diff ));//top
+	self.add( Rect::new( Vec3::new(34.0, 85.5, 65.6), Vec3::new(32.0, 0.0, 0.0), Vec3::new(0.0, 0.0, 32.0), Vec3::new(12.0, 12.0, 12.0), Vec3::zero(), Refl::Diff));//light
+
+        // シャッターは時刻 0 から 1 まで開く
+        self.camera.shutter = (0.0, 1.0);
+        let ball = |c: Vec3, refl: Refl| Arc::new(Group::new(vec![Sphere::new(8.0, Vec3::zero(), Vec3::zero(), c, refl).into()]));
+        // 横に転がる白い球と, 上に跳ねる鏡の球
+        self.add( Moving::new(ball(Vec3::new(0.75, 0.75, 0.75), Refl::Diff),
+                              Motion::linear(0.0, Vec3::new(18.0, 8.0, 60.0), 1.0, Vec3::new(34.0, 8.0, 60.0))));
+        self.add( Moving::new(ball(Vec3::new(1.0, 1.0, 1.0) * 0.999, Refl::Spec),
+                              Motion::new(vec![
+                                  Keyframe::new(0.0).translate(Vec3::new(78.0, 8.0, 70.0)),
+                                  Keyframe::new(0.5).translate(Vec3::new(78.0, 30.0, 70.0)),
+                                  Keyframe::new(1.0).translate(Vec3::new(78.0, 20.0, 70.0)),
+                              ])));
+        // 回りながら縮む箱
+        let cube = Arc::new(Group::new(vec![Cuboid::new(Vec3::new(-1.0, -1.0, -1.0), Vec3::new(1.0, 1.0, 1.0),
+                                                         Material::new(Vec3::zero(), Vec3::new(0.75, 0.65, 0.25), Refl::Diff)).into()]));
+        self.add( Moving::new(cube, Motion::new(vec![
+            Keyframe::new(0.0).translate(Vec3::new(50.0, 30.0, 90.0)).scale(Vec3::new(9.0, 9.0, 9.0)),
+            Keyframe::new(1.0).translate(Vec3::new(50.0, 30.0, 90.0)).rotate(Vec3::new(0.2, 1.0, 0.3), 1.2).scale(Vec3::new(6.0, 6.0, 6.0)),
+        ])));
+        // 止まっているガラス球
+        self.add( Sphere::new(10.0, Vec3::new(50.0, 10.0, 110.0), Vec3::zero(), Vec3::new(1.0, 1.0, 1.0) * 0.999, Refl::Refr));
+	true
+    }
 }
 	
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // scenes/ のファイルから作ったシーンが元の組み立てと同じ物体とカメラになる
+    #[test]
+    fn builtin_scene_files_match_the_models() {
+        let models: [fn(&mut Scene) -> bool; 10] = [
+            Scene::model_init0,
+            Scene::model_init1,
+            Scene::model_init2,
+            Scene::model_init3,
+            Scene::model_init4,
+            Scene::model_init5,
+            Scene::model_init6,
+            Scene::model_init7,
+            Scene::model_init8,
+            Scene::model_init9,
+        ];
+        for (n, model) in models.iter().enumerate() {
+            let mut scene = Scene::init();
+            model(&mut scene);
+            let (file, settings) = load_builtin_scene(n).unwrap().unwrap();
+            assert_eq!(dump_scene(&file, &settings), dump_scene(&scene, &settings), "model {}", n);
+        }
+    }
+}
@@ -0,0 +1,1153 @@
+use crate::raymod::*;
+
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+use toml::Spanned;
+
+// TOML のシーンファイル. 例は scenes/ にある
+//
+// [render]                 samples, output, leaf_size
+// [camera]                 position, look_at, up, fov, width, height, near,
+//                          aperture, focus_distance, blades, projection, shutter,
+//                          stereo, ipd, convergence (省略したものは既定のカメラのまま)
+// [materials.名前]         type = "diffuse" | "specular" | "refractive", color, emission,
+//                          ior, priority (refractive の屈折率と, 重なったときに中とみなす優先度),
+//                          roughness (specular と refractive の粗さ. 0.3 か, 接線と従法線の向きで [0.5, 0.1])
+// [[objects]], [[lights]]  type と形ごとの値, material (名前か表)
+// [[groups.名前]]          objects と同じ書き方. instance と moving から名前で使う
+//
+// lights は objects と同じ書き方で, 発光しないマテリアルだとエラーにする
+// 形の type は sphere, plane, rect, disk, cuboid, cylinder, cone, torus, mesh,
+// instance, moving, csg, sdf, heightfield. 値の名前は ObjectDesc を見る
+// dump_scene はどんな Scene でもこの形で書き出す (--dump-scene)
+
+// シーンと一緒にファイルに書かれた描画の設定. 無いものは None
+#[derive(Clone, Debug, Default)]
+pub struct RenderSettings {
+    pub samples: Option<usize>,
+    pub output: Option<String>,
+    pub leaf_size: Option<usize>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SceneFile {
+    #[serde(default)]
+    render: RenderDesc,
+    camera: Option<Spanned<CameraDesc>>,
+    #[serde(default)]
+    materials: HashMap<String, MaterialDesc>,
+    #[serde(default)]
+    groups: HashMap<String, Vec<Spanned<ObjectDesc>>>,
+    #[serde(default)]
+    objects: Vec<Spanned<ObjectDesc>>,
+    #[serde(default)]
+    lights: Vec<Spanned<ObjectDesc>>,
+}
+
+// 書き出すときの SceneFile. 位置は要らないので Spanned を外し, 名前順に並べる
+#[derive(Serialize)]
+struct SceneOut {
+    render: RenderDesc,
+    camera: CameraDesc,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    materials: BTreeMap<String, MaterialDesc>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    groups: BTreeMap<String, Vec<ObjectDesc>>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    objects: Vec<ObjectDesc>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    lights: Vec<ObjectDesc>,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+struct RenderDesc {
+    samples: Option<usize>,
+    output: Option<String>,
+    leaf_size: Option<usize>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct CameraDesc {
+    position: Option<[f64; 3]>,
+    look_at: Option<[f64; 3]>,
+    up: Option<[f64; 3]>,
+    fov: Option<f64>,
+    width: Option<usize>,
+    height: Option<usize>,
+    near: Option<f64>,
+    aperture: Option<f64>,
+    focus_distance: Option<f64>,
+    blades: Option<usize>,
+    projection: Option<String>,
+    shutter: Option<[f64; 2]>,
+    stereo: Option<String>,
+    ipd: Option<f64>,
+    convergence: Option<f64>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+enum ReflDesc {
+    #[default]
+    Diffuse,
+    Specular,
+    Refractive,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+struct MaterialDesc {
+    #[serde(rename = "type", default)]
+    refl: ReflDesc,
+    #[serde(default)]
+    color: [f64; 3],
+    #[serde(default, skip_serializing_if = "is_black")]
+    emission: [f64; 3],
+    // refractive の屈折率 (既定は 1.5) と, 重なったときの優先度 (既定は 0)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    ior: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    priority: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    roughness: Option<RoughnessDesc>,
+}
+
+// 粗さは1つの数か, 接線と従法線の向きの組
+#[derive(Serialize, Deserialize, Clone, Copy)]
+#[serde(untagged)]
+enum RoughnessDesc {
+    Isotropic(f64),
+    Anisotropic([f64; 2]),
+}
+
+// マテリアルは [materials] の名前か, その場に書いた表
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum MaterialRef {
+    Name(String),
+    Inline(MaterialDesc),
+}
+
+fn default_capped() -> bool {
+    true
+}
+
+fn default_scale() -> [f64; 3] {
+    [1.0, 1.0, 1.0]
+}
+
+fn default_step() -> f64 {
+    1.0
+}
+
+fn default_steps() -> usize {
+    SDF_DEFAULT_STEPS
+}
+
+fn is_black(a: &[f64; 3]) -> bool {
+    *a == [0.0; 3]
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase", deny_unknown_fields)]
+enum ObjectDesc {
+    Sphere {
+        center: [f64; 3],
+        radius: f64,
+        material: MaterialRef,
+    },
+    Plane {
+        point: [f64; 3],
+        normal: [f64; 3],
+        material: MaterialRef,
+    },
+    Rect {
+        corner: [f64; 3],
+        u: [f64; 3],
+        v: [f64; 3],
+        material: MaterialRef,
+    },
+    Disk {
+        center: [f64; 3],
+        normal: [f64; 3],
+        radius: f64,
+        material: MaterialRef,
+    },
+    // min, max で軸に沿った箱. center, half (と x_axis, y_axis) で向きのある箱
+    Cuboid {
+        min: Option<[f64; 3]>,
+        max: Option<[f64; 3]>,
+        center: Option<[f64; 3]>,
+        half: Option<[f64; 3]>,
+        x_axis: Option<[f64; 3]>,
+        y_axis: Option<[f64; 3]>,
+        material: MaterialRef,
+    },
+    Cylinder {
+        base: [f64; 3],
+        axis: [f64; 3],
+        radius: f64,
+        height: f64,
+        #[serde(default = "default_capped")]
+        capped: bool,
+        material: MaterialRef,
+    },
+    Cone {
+        base: [f64; 3],
+        axis: [f64; 3],
+        radius: f64,
+        height: f64,
+        #[serde(default = "default_capped")]
+        capped: bool,
+        material: MaterialRef,
+    },
+    Torus {
+        center: [f64; 3],
+        axis: [f64; 3],
+        major_radius: f64,
+        minor_radius: f64,
+        material: MaterialRef,
+    },
+    // メッシュファイルか, 頂点と三角形をその場に書いたもの. ファイルのパスはシーンファイルからの相対
+    // ファイルなら material はファイルのものを置き換える. その場に書くなら material は必ず要る
+    Mesh {
+        file: Option<String>,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        positions: Vec<[f64; 3]>,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        normals: Vec<[f64; 3]>,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        uvs: Vec<[f64; 2]>,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        colors: Vec<[f64; 3]>,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        indices: Vec<[usize; 3]>,
+        material: Option<MaterialRef>,
+    },
+    // group を4x4行列 (行ごと, 点は列ベクトル) で置く
+    Instance {
+        group: String,
+        matrix: [[f64; 4]; 4],
+    },
+    // group をキーフレームで動かす
+    Moving {
+        group: String,
+        keyframes: Vec<KeyframeDesc>,
+    },
+    Csg {
+        op: CsgOpDesc,
+        a: Box<ObjectDesc>,
+        b: Box<ObjectDesc>,
+    },
+    // 距離関数の木と, 調べる範囲の箱 min, max
+    Sdf {
+        sdf: SdfDesc,
+        min: [f64; 3],
+        max: [f64; 3],
+        #[serde(default = "default_steps")]
+        max_steps: usize,
+        #[serde(default = "default_step")]
+        step: f64,
+        material: MaterialRef,
+    },
+    // 高さは画像 heightmap か, z ごとの行を並べた heights か, 値ノイズの noise (0..1 で size.y 倍)
+    Heightfield {
+        origin: [f64; 3],
+        size: [f64; 3],
+        heightmap: Option<String>,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        heights: Vec<Vec<f64>>,
+        noise: Option<NoiseDesc>,
+        material: MaterialRef,
+    },
+}
+
+// HeightMap::noise の x, z の格子の数と重ねる回数と種. fade_edges なら縁を0に下げる
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct NoiseDesc {
+    samples: [usize; 2],
+    octaves: usize,
+    #[serde(default)]
+    seed: u32,
+    #[serde(default)]
+    fade_edges: bool,
+}
+
+// 回転は axis まわりに angle 度
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct KeyframeDesc {
+    time: f64,
+    #[serde(default)]
+    translate: [f64; 3],
+    axis: Option<[f64; 3]>,
+    #[serde(default)]
+    angle: f64,
+    #[serde(default = "default_scale")]
+    scale: [f64; 3],
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+enum CsgOpDesc {
+    Union,
+    Intersection,
+    Difference,
+}
+
+// Sdf と同じ形の木
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+enum SdfDesc {
+    Sphere { radius: f64 },
+    Box { half: [f64; 3] },
+    RoundBox { half: [f64; 3], radius: f64 },
+    Torus { major_radius: f64, minor_radius: f64 },
+    Translate { offset: [f64; 3], sdf: Box<SdfDesc> },
+    Scale { factor: f64, sdf: Box<SdfDesc> },
+    Union { a: Box<SdfDesc>, b: Box<SdfDesc> },
+    Intersection { a: Box<SdfDesc>, b: Box<SdfDesc> },
+    Difference { a: Box<SdfDesc>, b: Box<SdfDesc> },
+    SmoothUnion { a: Box<SdfDesc>, b: Box<SdfDesc>, k: f64 },
+    Repeat { period: [f64; 3], sdf: Box<SdfDesc> },
+    Twist { rate: f64, sdf: Box<SdfDesc> },
+}
+
+fn v3(a: [f64; 3]) -> Vec3 {
+    Vec3::new(a[0], a[1], a[2])
+}
+
+fn a3(v: Vec3) -> [f64; 3] {
+    [v.x, v.y, v.z]
+}
+
+impl MaterialDesc {
+    fn material(&self) -> Material {
+        let refl = match self.refl {
+            ReflDesc::Diffuse => Refl::Diff,
+            ReflDesc::Specular => Refl::Spec,
+            ReflDesc::Refractive => Refl::Refr,
+        };
+        let mut m = Material::new(v3(self.emission), v3(self.color), refl);
+        if let Some(ior) = self.ior {
+            m.ior = ior;
+        }
+        if let Some(p) = self.priority {
+            m.priority = p;
+        }
+        match self.roughness {
+            Some(RoughnessDesc::Isotropic(r)) => m.roughness = (r, r),
+            Some(RoughnessDesc::Anisotropic([ru, rv])) => m.roughness = (ru, rv),
+            None => {}
+        }
+        m
+    }
+
+    fn from_material(m: &Material) -> MaterialDesc {
+        let refl = match m.refl {
+            Refl::Diff => ReflDesc::Diffuse,
+            Refl::Spec => ReflDesc::Specular,
+            Refl::Refr => ReflDesc::Refractive,
+        };
+        MaterialDesc {
+            refl,
+            color: a3(m.c),
+            emission: a3(m.e),
+            ior: (m.ior != GLASS_IOR).then_some(m.ior),
+            priority: (m.priority != 0).then_some(m.priority),
+            roughness: match m.roughness {
+                (ru, rv) if ru == rv => (ru != 0.0).then_some(RoughnessDesc::Isotropic(ru)),
+                (ru, rv) => Some(RoughnessDesc::Anisotropic([ru, rv])),
+            },
+        }
+    }
+}
+
+impl SdfDesc {
+    fn sdf(&self) -> Sdf {
+        match self {
+            SdfDesc::Sphere { radius } => Sdf::Sphere(*radius),
+            SdfDesc::Box { half } => Sdf::Box(v3(*half)),
+            SdfDesc::RoundBox { half, radius } => Sdf::RoundBox(v3(*half), *radius),
+            SdfDesc::Torus { major_radius, minor_radius } => Sdf::Torus(*major_radius, *minor_radius),
+            SdfDesc::Translate { offset, sdf } => sdf.sdf().translate(v3(*offset)),
+            SdfDesc::Scale { factor, sdf } => sdf.sdf().scale(*factor),
+            SdfDesc::Union { a, b } => a.sdf().union(b.sdf()),
+            SdfDesc::Intersection { a, b } => a.sdf().intersection(b.sdf()),
+            SdfDesc::Difference { a, b } => a.sdf().difference(b.sdf()),
+            SdfDesc::SmoothUnion { a, b, k } => a.sdf().smooth_union(b.sdf(), *k),
+            SdfDesc::Repeat { period, sdf } => sdf.sdf().repeat(v3(*period)),
+            SdfDesc::Twist { rate, sdf } => sdf.sdf().twist(*rate),
+        }
+    }
+
+    fn from_sdf(s: &Sdf) -> SdfDesc {
+        let b = |s: &Sdf| Box::new(SdfDesc::from_sdf(s));
+        match s {
+            Sdf::Sphere(r) => SdfDesc::Sphere { radius: *r },
+            Sdf::Box(h) => SdfDesc::Box { half: a3(*h) },
+            Sdf::RoundBox(h, r) => SdfDesc::RoundBox { half: a3(*h), radius: *r },
+            Sdf::Torus(big_r, small_r) => SdfDesc::Torus {
+                major_radius: *big_r,
+                minor_radius: *small_r,
+            },
+            Sdf::Translate(v, s) => SdfDesc::Translate { offset: a3(*v), sdf: b(s) },
+            Sdf::Scale(k, s) => SdfDesc::Scale { factor: *k, sdf: b(s) },
+            Sdf::Union(x, y) => SdfDesc::Union { a: b(x), b: b(y) },
+            Sdf::Intersection(x, y) => SdfDesc::Intersection { a: b(x), b: b(y) },
+            Sdf::Difference(x, y) => SdfDesc::Difference { a: b(x), b: b(y) },
+            Sdf::SmoothUnion(x, y, k) => SdfDesc::SmoothUnion { a: b(x), b: b(y), k: *k },
+            Sdf::Repeat(c, s) => SdfDesc::Repeat { period: a3(*c), sdf: b(s) },
+            Sdf::Twist(k, s) => SdfDesc::Twist { rate: *k, sdf: b(s) },
+        }
+    }
+}
+
+// 発光するマテリアルを持つか. Group や CSG は中の形を見る
+fn emits(p: &Primitive) -> bool {
+    let lit = |m: &Material| m.e.x.max(m.e.y).max(m.e.z) > 0.0;
+    match p {
+        Primitive::Sphere(s) => lit(&s.m),
+        Primitive::Triangle(s) => lit(&s.mesh.m),
+        Primitive::Plane(s) => lit(&s.m),
+        Primitive::Rect(s) => lit(&s.m),
+        Primitive::Disk(s) => lit(&s.m),
+        Primitive::Cuboid(s) => lit(&s.m),
+        Primitive::Cylinder(s) => lit(&s.m),
+        Primitive::Cone(s) => lit(&s.m),
+        Primitive::Torus(s) => lit(&s.m),
+        Primitive::Instance(s) => s.geom.objects.iter().any(emits),
+        Primitive::Csg(s) => emits(&s.a) || emits(&s.b),
+        Primitive::Sdf(s) => lit(&s.m),
+        Primitive::Heightfield(s) => lit(&s.m),
+        Primitive::Moving(s) => s.geom.objects.iter().any(emits),
+    }
+}
+
+// 読んでいるファイルと, 位置から行番号を出すための本文
+struct Source<'a> {
+    path: &'a Path,
+    text: &'a str,
+}
+
+impl Source<'_> {
+    fn line(&self, offset: usize) -> usize {
+        self.text[..offset.min(self.text.len())].matches('\n').count() + 1
+    }
+
+    fn error<T>(&self, span: std::ops::Range<usize>, msg: impl Into<String>) -> Result<T, LoadError> {
+        Err(LoadError::new(self.path, self.line(span.start), msg))
+    }
+}
+
+// -m 0〜9 のシーン. scenes/ のファイルをビルドのときに埋め込む
+const BUILTIN_SCENES: [&str; 10] = [
+    include_str!("../../scenes/model0.toml"),
+    include_str!("../../scenes/model1.toml"),
+    include_str!("../../scenes/model2.toml"),
+    include_str!("../../scenes/model3.toml"),
+    include_str!("../../scenes/model4.toml"),
+    include_str!("../../scenes/model5.toml"),
+    include_str!("../../scenes/model6.toml"),
+    include_str!("../../scenes/model7.toml"),
+    include_str!("../../scenes/model8.toml"),
+    include_str!("../../scenes/model9.toml"),
+];
+
+// -m n のシーンを scenes/model{n}.toml から作る. そのファイルが無い番号なら None
+pub fn load_builtin_scene(n: usize) -> Option<Result<(Scene, RenderSettings), LoadError>> {
+    let text = BUILTIN_SCENES.get(n)?;
+    let path = format!("scenes/model{}.toml", n);
+    Some(load_scene_str(Path::new(&path), text))
+}
+
+pub fn load_scene_file(path: &Path) -> Result<(Scene, RenderSettings), LoadError> {
+    let text = fs::read_to_string(path).map_err(|e| LoadError::new(path, 0, e.to_string()))?;
+    load_scene_str(path, &text)
+}
+
+// path はエラーの表示と, heightmap などの相対パスの基準に使う
+fn load_scene_str(path: &Path, text: &str) -> Result<(Scene, RenderSettings), LoadError> {
+    let src = Source { path, text };
+    let file: SceneFile = toml::from_str(text).map_err(|e| {
+        let line = e.span().map_or(0, |s| src.line(s.start));
+        LoadError::new(path, line, e.message().trim_end())
+    })?;
+
+    let mut scene = Scene::init();
+    if let Some(cam) = &file.camera {
+        apply_camera(&src, cam, &mut scene.camera)?;
+    }
+    let mut loader = Loader {
+        src,
+        file: &file,
+        groups: HashMap::new(),
+        building: vec![],
+    };
+    for (obj, light) in file.objects.iter().map(|o| (o, false)).chain(file.lights.iter().map(|o| (o, true))) {
+        let span = obj.span();
+        let prims = loader.build(obj.get_ref(), span.clone())?;
+        if light && !prims.iter().any(emits) {
+            return loader.src.error(span, "a light needs a material with emission");
+        }
+        for p in prims {
+            scene.add(p);
+        }
+    }
+
+    let settings = RenderSettings {
+        samples: file.render.samples,
+        output: file.render.output,
+        leaf_size: file.render.leaf_size,
+    };
+    Ok((scene, settings))
+}
+
+// 物体を作る. group は使われたときに一度だけ作って共有する
+struct Loader<'a> {
+    src: Source<'a>,
+    file: &'a SceneFile,
+    groups: HashMap<String, Arc<Group>>,
+    // 作りかけの group の名前. 自分自身を入れ子にしていたらエラーにする
+    building: Vec<String>,
+}
+
+impl Loader<'_> {
+    fn material(&self, r: &MaterialRef, span: std::ops::Range<usize>) -> Result<Material, LoadError> {
+        let m = match r {
+            MaterialRef::Inline(m) => m.material(),
+            MaterialRef::Name(n) => match self.file.materials.get(n) {
+                Some(m) => m.material(),
+                None => return self.src.error(span, format!("unknown material '{}'", n)),
+            },
+        };
+        if m.ior.is_nan() || m.ior <= 0.0 {
+            return self.src.error(span, format!("ior must be positive, got {}", m.ior));
+        }
+        let (ru, rv) = m.roughness;
+        if ru.is_nan() || rv.is_nan() || ru < 0.0 || rv < 0.0 {
+            return self.src.error(span, format!("roughness must not be negative, got ({}, {})", ru, rv));
+        }
+        if m.is_rough() && matches!(m.refl, Refl::Diff) {
+            return self.src.error(span, "roughness is only for specular and refractive materials");
+        }
+        Ok(m)
+    }
+
+    fn group(&mut self, name: &str, span: std::ops::Range<usize>) -> Result<Arc<Group>, LoadError> {
+        if let Some(g) = self.groups.get(name) {
+            return Ok(g.clone());
+        }
+        let file = self.file;
+        let objects = match file.groups.get(name) {
+            Some(o) => o,
+            None => return self.src.error(span, format!("unknown group '{}'", name)),
+        };
+        if self.building.iter().any(|b| b == name) {
+            return self.src.error(span, format!("group '{}' contains itself", name));
+        }
+        self.building.push(name.to_string());
+        let mut prims = vec![];
+        for obj in objects {
+            prims.extend(self.build(obj.get_ref(), obj.span())?);
+        }
+        self.building.pop();
+        if prims.is_empty() {
+            return self.src.error(span, format!("group '{}' is empty", name));
+        }
+        let g = Arc::new(Group::new(prims));
+        self.groups.insert(name.to_string(), g.clone());
+        Ok(g)
+    }
+
+    // CSG の子. 形1つにならなければエラー
+    fn single(&mut self, obj: &ObjectDesc, span: std::ops::Range<usize>) -> Result<Primitive, LoadError> {
+        let mut prims = self.build(obj, span.clone())?;
+        if prims.len() != 1 {
+            return self.src.error(span, "a csg operand must be a single shape");
+        }
+        Ok(prims.remove(0))
+    }
+
+    fn build(&mut self, obj: &ObjectDesc, span: std::ops::Range<usize>) -> Result<Vec<Primitive>, LoadError> {
+        let p: Primitive = match obj {
+            ObjectDesc::Sphere { center, radius, material } => {
+                let m = self.material(material, span.clone())?;
+                Sphere { rad: *radius, p: v3(*center), m }.into()
+            }
+            ObjectDesc::Plane { point, normal, material } => {
+                let m = self.material(material, span.clone())?;
+                let mut p = Plane::new(v3(*point), v3(*normal), m.e, m.c, m.refl);
+                p.m = m;
+                p.into()
+            }
+            ObjectDesc::Rect { corner, u, v, material } => {
+                let m = self.material(material, span.clone())?;
+                let mut r = Rect::new(v3(*corner), v3(*u), v3(*v), m.e, m.c, m.refl);
+                r.m = m;
+                r.into()
+            }
+            ObjectDesc::Disk { center, normal, radius, material } => {
+                let m = self.material(material, span.clone())?;
+                let mut d = Disk::new(v3(*center), v3(*normal), *radius, m.e, m.c, m.refl);
+                d.m = m;
+                d.into()
+            }
+            ObjectDesc::Cuboid { min, max, center, half, x_axis, y_axis, material } => {
+                let m = self.material(material, span.clone())?;
+                match (min, max, center, half) {
+                    (Some(lo), Some(hi), None, None) if x_axis.is_none() && y_axis.is_none() => {
+                        Cuboid::new(v3(*lo), v3(*hi), m).into()
+                    }
+                    (None, None, Some(c), Some(h)) => {
+                        let x = x_axis.unwrap_or([1.0, 0.0, 0.0]);
+                        let y = y_axis.unwrap_or([0.0, 1.0, 0.0]);
+                        Cuboid::oriented(v3(*c), v3(x), v3(y), v3(*h), m).into()
+                    }
+                    _ => return self.src.error(span, "a cuboid needs either min and max, or center and half"),
+                }
+            }
+            ObjectDesc::Cylinder { base, axis, radius, height, capped, material } => {
+                let m = self.material(material, span.clone())?;
+                Cylinder::new(v3(*base), v3(*axis), *radius, *height, *capped, m).into()
+            }
+            ObjectDesc::Cone { base, axis, radius, height, capped, material } => {
+                let m = self.material(material, span.clone())?;
+                Cone::new(v3(*base), v3(*axis), *radius, *height, *capped, m).into()
+            }
+            ObjectDesc::Torus { center, axis, major_radius, minor_radius, material } => {
+                let m = self.material(material, span.clone())?;
+                Torus::new(v3(*center), v3(*axis), *major_radius, *minor_radius, m).into()
+            }
+            ObjectDesc::Mesh { file, positions, normals, uvs, colors, indices, material } => {
+                let over = material.as_ref().map(|r| self.material(r, span.clone())).transpose()?;
+                return Ok(self
+                    .meshes(file, positions, normals, uvs, colors, indices, over, span)?
+                    .into_iter()
+                    .flat_map(|mesh| Mesh::triangles(Arc::new(mesh)).map(Primitive::from))
+                    .collect());
+            }
+            ObjectDesc::Instance { group, matrix } => {
+                let g = self.group(group, span.clone())?;
+                match Transform::from_matrix(Mat4::new(*matrix)) {
+                    Some(xf) => Instance::new(g, xf).into(),
+                    None => return self.src.error(span, "instance matrix is not invertible"),
+                }
+            }
+            ObjectDesc::Moving { group, keyframes } => {
+                let g = self.group(group, span.clone())?;
+                if keyframes.is_empty() {
+                    return self.src.error(span, "moving needs at least one keyframe");
+                }
+                let mut keys = vec![];
+                for k in keyframes {
+                    let mut key = Keyframe::new(k.time).translate(v3(k.translate)).scale(v3(k.scale));
+                    match k.axis {
+                        Some(a) => key = key.rotate(v3(a), k.angle.to_radians()),
+                        None if k.angle != 0.0 => return self.src.error(span, "a keyframe angle needs an axis"),
+                        None => {}
+                    }
+                    keys.push(key);
+                }
+                Moving::new(g, Motion::new(keys)).into()
+            }
+            ObjectDesc::Csg { op, a, b } => {
+                let op = match op {
+                    CsgOpDesc::Union => CsgOp::Union,
+                    CsgOpDesc::Intersection => CsgOp::Intersection,
+                    CsgOpDesc::Difference => CsgOp::Difference,
+                };
+                let a = self.single(a, span.clone())?;
+                let b = self.single(b, span.clone())?;
+                Csg::new(op, a, b).into()
+            }
+            ObjectDesc::Sdf { sdf, min, max, max_steps, step, material } => {
+                let m = self.material(material, span.clone())?;
+                SdfShape::new(sdf.sdf(), AABB::new(v3(*min), v3(*max)), m)
+                    .with_steps(*max_steps)
+                    .with_step_scale(*step)
+                    .into()
+            }
+            ObjectDesc::Heightfield { origin, size, heightmap, heights, noise, material } => {
+                let m = self.material(material, span.clone())?;
+                let map = match (heightmap, heights.is_empty(), noise) {
+                    (Some(f), true, None) => {
+                        let map_path = self.src.path.parent().unwrap_or(Path::new("")).join(f);
+                        HeightMap::load(&map_path).or_else(|e| self.src.error(span.clone(), e.to_string()))?
+                    }
+                    (None, false, None) => {
+                        let nx = heights[0].len();
+                        if heights.len() < 2 || nx < 2 || heights.iter().any(|r| r.len() != nx) {
+                            return self.src.error(span, "heights must be at least 2 rows of the same length (2 or more)");
+                        }
+                        HeightMap::new(nx, heights.len(), heights.concat())
+                    }
+                    (None, true, Some(n)) => {
+                        if n.samples[0] < 2 || n.samples[1] < 2 {
+                            return self.src.error(span, "noise samples must be 2 or more on each side");
+                        }
+                        let map = HeightMap::noise(n.samples[0], n.samples[1], n.octaves, n.seed);
+                        if n.fade_edges {
+                            map.fade_edges()
+                        } else {
+                            map
+                        }
+                    }
+                    _ => return self.src.error(span, "a heightfield needs one of heightmap, heights or noise"),
+                };
+                Heightfield::new(&map, v3(*origin), v3(*size), m).into()
+            }
+        };
+        Ok(vec![p])
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn meshes(
+        &self,
+        file: &Option<String>,
+        positions: &[[f64; 3]],
+        normals: &[[f64; 3]],
+        uvs: &[[f64; 2]],
+        colors: &[[f64; 3]],
+        indices: &[[usize; 3]],
+        over: Option<Material>,
+        span: std::ops::Range<usize>,
+    ) -> Result<Vec<Mesh>, LoadError> {
+        let inline = !(positions.is_empty() && normals.is_empty() && uvs.is_empty() && colors.is_empty() && indices.is_empty());
+        match (file, inline) {
+            (Some(f), false) => {
+                let mesh_path = self.src.path.parent().unwrap_or(Path::new("")).join(f);
+                let meshes = load_mesh_file(&mesh_path).or_else(|e| self.src.error(span, e.to_string()))?;
+                Ok(meshes
+                    .into_iter()
+                    .map(|mut nm| {
+                        if let Some(m) = over {
+                            nm.mesh.m = m;
+                        }
+                        nm.mesh
+                    })
+                    .collect())
+            }
+            (None, true) => {
+                let m = match over {
+                    Some(m) => m,
+                    None => return self.src.error(span, "an inline mesh needs a material"),
+                };
+                let n = positions.len();
+                if indices.is_empty() {
+                    return self.src.error(span, "an inline mesh needs indices");
+                }
+                if indices.iter().flatten().any(|&i| i >= n) {
+                    return self.src.error(span, format!("mesh index out of range ({} positions)", n));
+                }
+                for (name, len) in [("normals", normals.len()), ("uvs", uvs.len()), ("colors", colors.len())] {
+                    if len != 0 && len != n {
+                        return self.src.error(span, format!("mesh has {} {} for {} positions", len, name, n));
+                    }
+                }
+                let mut mesh = Mesh::new(positions.iter().map(|p| v3(*p)).collect(), indices.to_vec(), m);
+                mesh.normals = normals.iter().map(|p| v3(*p)).collect();
+                mesh.uvs = uvs.iter().map(|t| (t[0], t[1])).collect();
+                mesh.colors = colors.iter().map(|c| v3(*c)).collect();
+                Ok(vec![mesh])
+            }
+            _ => self.src.error(span, "a mesh needs either file, or positions and indices"),
+        }
+    }
+}
+
+fn apply_camera(src: &Source, desc: &Spanned<CameraDesc>, cam: &mut Camera) -> Result<(), LoadError> {
+    let span = desc.span();
+    let d = desc.get_ref();
+    if let Some(p) = d.position {
+        cam.pos = v3(p);
+    }
+    if let Some(p) = d.look_at {
+        cam.look_at = v3(p);
+    }
+    if let Some(u) = d.up {
+        cam.up = v3(u);
+    }
+    if let Some(f) = d.fov {
+        cam.vfov = f;
+    }
+    if let Some(w) = d.width {
+        cam.width = w;
+    }
+    if let Some(h) = d.height {
+        cam.height = h;
+    }
+    if let Some(n) = d.near {
+        cam.near = n;
+    }
+    if let Some(a) = d.aperture {
+        cam.aperture = a;
+    }
+    if let Some(f) = d.focus_distance {
+        cam.focus_dist = Some(f);
+    }
+    if let Some(n) = d.blades {
+        if n < 3 {
+            return src.error(span, "an aperture needs at least 3 blades");
+        }
+        cam.lens = Aperture::polygon(n);
+    }
+    if let Some(p) = &d.projection {
+        cam.projection = match p.parse() {
+            Ok(p) => p,
+            Err(e) => return src.error(span, e),
+        };
+    }
+    if let Some(s) = d.shutter {
+        cam.shutter = (s[0], s[1]);
+    }
+    if let Some(layout) = &d.stereo {
+        let layout = match layout.parse() {
+            Ok(l) => l,
+            Err(e) => return src.error(span, e),
+        };
+        cam.stereo = Some(Stereo {
+            ipd: d.ipd.unwrap_or(DEFAULT_IPD),
+            convergence: d.convergence,
+            layout,
+        });
+    }
+    Ok(())
+}
+
+// Scene を load_scene_file で読み戻せる TOML にする
+// 同じマテリアルは [materials] に名前をつけて1つにまとめ, 発光する物体は [[lights]] に書く
+// 同じ Mesh の三角形が続くところは mesh 1つに, Instance と Moving の Group は [[groups.名前]] にする
+// 画像で与えた絞りの形とメッシュのテクスチャは書けないので, 円の絞りとテクスチャのないメッシュになる
+pub fn dump_scene(scene: &Scene, settings: &RenderSettings) -> String {
+    let mut d = Dumper {
+        materials: vec![],
+        groups: vec![],
+        out: SceneOut {
+            render: RenderDesc {
+                samples: settings.samples,
+                output: settings.output.clone(),
+                leaf_size: settings.leaf_size,
+            },
+            camera: camera_desc(&scene.camera),
+            materials: BTreeMap::new(),
+            groups: BTreeMap::new(),
+            objects: vec![],
+            lights: vec![],
+        },
+    };
+    for (obj, light) in d.objects(&scene.objects) {
+        if light {
+            d.out.lights.push(obj);
+        } else {
+            d.out.objects.push(obj);
+        }
+    }
+    let text = toml::to_string(&d.out).expect("a scene is always representable in TOML");
+    format!("# --dump-scene で書き出したシーン\n\n{}", text)
+}
+
+fn camera_desc(c: &Camera) -> CameraDesc {
+    CameraDesc {
+        position: Some(a3(c.pos)),
+        look_at: Some(a3(c.look_at)),
+        up: Some(a3(c.up)),
+        fov: Some(c.vfov),
+        width: Some(c.width),
+        height: Some(c.height),
+        near: Some(c.near),
+        aperture: Some(c.aperture),
+        focus_distance: c.focus_dist,
+        blades: match c.lens {
+            Aperture::Polygon { blades, .. } => Some(blades),
+            _ => None,
+        },
+        projection: Some(c.projection.to_string()),
+        shutter: Some([c.shutter.0, c.shutter.1]),
+        stereo: c.stereo.as_ref().map(|s| s.layout.to_string()),
+        ipd: c.stereo.as_ref().map(|s| s.ipd),
+        convergence: c.stereo.as_ref().and_then(|s| s.convergence),
+    }
+}
+
+struct Dumper {
+    // 書き出したマテリアルと Group と, それにつけた名前
+    materials: Vec<(Material, String)>,
+    groups: Vec<(Arc<Group>, String)>,
+    out: SceneOut,
+}
+
+impl Dumper {
+    fn material(&mut self, m: &Material) -> MaterialRef {
+        let bits = |v: Vec3| [v.x.to_bits(), v.y.to_bits(), v.z.to_bits()];
+        let desc = MaterialDesc::from_material(m);
+        let same = |k: &Material| {
+            bits(k.e) == bits(m.e)
+                && bits(k.c) == bits(m.c)
+                && MaterialDesc::from_material(k).refl == desc.refl
+                && k.ior.to_bits() == m.ior.to_bits()
+                && k.priority == m.priority
+                && k.roughness.0.to_bits() == m.roughness.0.to_bits()
+                && k.roughness.1.to_bits() == m.roughness.1.to_bits()
+        };
+        if let Some((_, n)) = self.materials.iter().find(|(k, _)| same(k)) {
+            return MaterialRef::Name(n.clone());
+        }
+        let kind = if !is_black(&desc.emission) {
+            "light"
+        } else {
+            match desc.refl {
+                ReflDesc::Diffuse => "diffuse",
+                ReflDesc::Specular => "specular",
+                ReflDesc::Refractive => "refractive",
+            }
+        };
+        let name = format!("{}{}", kind, self.materials.iter().filter(|(_, n)| n.starts_with(kind)).count() + 1);
+        self.materials.push((*m, name.clone()));
+        self.out.materials.insert(name.clone(), desc);
+        MaterialRef::Name(name)
+    }
+
+    fn group(&mut self, g: &Arc<Group>) -> String {
+        if let Some((_, n)) = self.groups.iter().find(|(k, _)| Arc::ptr_eq(k, g)) {
+            return n.clone();
+        }
+        let name = format!("group{}", self.groups.len() + 1);
+        self.groups.push((g.clone(), name.clone()));
+        let objects = self.objects(&g.objects).into_iter().map(|(o, _)| o).collect();
+        self.out.groups.insert(name.clone(), objects);
+        name
+    }
+
+    // 発光するかと一緒に返す. 同じ Mesh の三角形が続くところは1つにまとめる
+    fn objects(&mut self, prims: &[Primitive]) -> Vec<(ObjectDesc, bool)> {
+        let mut out = vec![];
+        let mut i = 0;
+        while i < prims.len() {
+            if let Primitive::Triangle(t) = &prims[i] {
+                let mut tris = vec![t.mesh.indices[t.i]];
+                i += 1;
+                while let Some(Primitive::Triangle(u)) = prims.get(i) {
+                    if !Arc::ptr_eq(&u.mesh, &t.mesh) {
+                        break;
+                    }
+                    tris.push(u.mesh.indices[u.i]);
+                    i += 1;
+                }
+                out.push((self.mesh(&t.mesh, tris), emits(&prims[i - 1])));
+            } else {
+                out.push((self.object(&prims[i]), emits(&prims[i])));
+                i += 1;
+            }
+        }
+        out
+    }
+
+    fn mesh(&mut self, mesh: &Mesh, indices: Vec<[usize; 3]>) -> ObjectDesc {
+        ObjectDesc::Mesh {
+            file: None,
+            positions: mesh.positions.iter().map(|p| a3(*p)).collect(),
+            normals: mesh.normals.iter().map(|n| a3(*n)).collect(),
+            uvs: mesh.uvs.iter().map(|t| [t.0, t.1]).collect(),
+            colors: mesh.colors.iter().map(|c| a3(*c)).collect(),
+            indices,
+            material: Some(self.material(&mesh.m)),
+        }
+    }
+
+    fn object(&mut self, p: &Primitive) -> ObjectDesc {
+        match p {
+            Primitive::Sphere(s) => ObjectDesc::Sphere {
+                center: a3(s.p),
+                radius: s.rad,
+                material: self.material(&s.m),
+            },
+            Primitive::Triangle(t) => self.mesh(&t.mesh, vec![t.mesh.indices[t.i]]),
+            Primitive::Plane(s) => ObjectDesc::Plane {
+                point: a3(s.p),
+                normal: a3(s.n),
+                material: self.material(&s.m),
+            },
+            Primitive::Rect(s) => ObjectDesc::Rect {
+                corner: a3(s.q),
+                u: a3(s.u),
+                v: a3(s.v),
+                material: self.material(&s.m),
+            },
+            Primitive::Disk(s) => ObjectDesc::Disk {
+                center: a3(s.p),
+                normal: a3(s.n),
+                radius: s.rad,
+                material: self.material(&s.m),
+            },
+            Primitive::Cuboid(s) => {
+                let (c, (x, y)) = (s.center(), s.axes());
+                let material = self.material(&s.m);
+                if a3(x) == [1.0, 0.0, 0.0] && a3(y) == [0.0, 1.0, 0.0] {
+                    ObjectDesc::Cuboid {
+                        min: Some(a3(c - s.half)),
+                        max: Some(a3(c + s.half)),
+                        center: None,
+                        half: None,
+                        x_axis: None,
+                        y_axis: None,
+                        material,
+                    }
+                } else {
+                    ObjectDesc::Cuboid {
+                        min: None,
+                        max: None,
+                        center: Some(a3(c)),
+                        half: Some(a3(s.half)),
+                        x_axis: Some(a3(x)),
+                        y_axis: Some(a3(y)),
+                        material,
+                    }
+                }
+            }
+            Primitive::Cylinder(s) => ObjectDesc::Cylinder {
+                base: a3(s.base()),
+                axis: a3(s.axis()),
+                radius: s.rad,
+                height: s.h,
+                capped: s.capped,
+                material: self.material(&s.m),
+            },
+            Primitive::Cone(s) => ObjectDesc::Cone {
+                base: a3(s.base()),
+                axis: a3(s.axis()),
+                radius: s.rad,
+                height: s.h,
+                capped: s.capped,
+                material: self.material(&s.m),
+            },
+            Primitive::Torus(s) => ObjectDesc::Torus {
+                center: a3(s.center()),
+                axis: a3(s.axis()),
+                major_radius: s.big_r,
+                minor_radius: s.small_r,
+                material: self.material(&s.m),
+            },
+            Primitive::Instance(s) => ObjectDesc::Instance {
+                group: self.group(&s.geom),
+                matrix: s.transform().m.m,
+            },
+            Primitive::Csg(s) => ObjectDesc::Csg {
+                op: match s.op {
+                    CsgOp::Union => CsgOpDesc::Union,
+                    CsgOp::Intersection => CsgOpDesc::Intersection,
+                    CsgOp::Difference => CsgOpDesc::Difference,
+                },
+                a: Box::new(self.object(&s.a)),
+                b: Box::new(self.object(&s.b)),
+            },
+            Primitive::Sdf(s) => ObjectDesc::Sdf {
+                sdf: SdfDesc::from_sdf(&s.sdf),
+                min: a3(s.bbox.min),
+                max: a3(s.bbox.max),
+                max_steps: s.max_steps,
+                step: s.step,
+                material: self.material(&s.m),
+            },
+            Primitive::Heightfield(s) => {
+                let (map, origin, size) = s.grid();
+                ObjectDesc::Heightfield {
+                    origin: a3(origin),
+                    size: a3(size),
+                    heightmap: None,
+                    heights: map.h.chunks(map.nx).map(|r| r.to_vec()).collect(),
+                    noise: None,
+                    material: self.material(&s.m),
+                }
+            }
+            Primitive::Moving(s) => ObjectDesc::Moving {
+                group: self.group(&s.geom),
+                keyframes: s
+                    .motion
+                    .keys()
+                    .iter()
+                    .map(|k| {
+                        let (axis, angle) = match k.rotate.axis_angle() {
+                            Some((a, theta)) => (Some(a3(a)), theta.to_degrees()),
+                            None => (None, 0.0),
+                        };
+                        KeyframeDesc {
+                            time: k.time,
+                            translate: a3(k.translate),
+                            axis,
+                            angle,
+                            scale: a3(k.scale),
+                        }
+                    })
+                    .collect(),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MAT: &str = r#"material = { type = "diffuse", color = [0.5, 0.5, 0.5] }"#;
+
+    fn scene_err(text: &str) -> LoadError {
+        load_scene_str(Path::new("test.toml"), text).err().unwrap()
+    }
+
+    #[test]
+    fn minimal_scene_loads() {
+        let text = format!("[render]\nsamples = 4\n\n[[objects]]\ntype = \"sphere\"\ncenter = [0.0, 0.0, 0.0]\nradius = 1.0\n{}\n", MAT);
+        let (scene, settings) = load_scene_str(Path::new("test.toml"), &text).unwrap();
+        assert_eq!(scene.objects.len(), 1);
+        assert_eq!(settings.samples, Some(4));
+    }
+
+    #[test]
+    fn syntax_errors_point_at_the_line() {
+        // 型を選ぶ表の中のエラーは表の見出しの行になる
+        let e = scene_err("[render]\nsamples = 1\n\n[[objects]]\ntype = \"sphere\"\ncenter = [0.0, 0.0, 0.0]\nradius = 1.0\ncolour = 1\n");
+        assert_eq!(e.line, 4);
+        assert!(e.msg.contains("colour"), "{}", e);
+        let e = scene_err("[[objects]]\ntype = \"teapot\"\n");
+        assert!(e.line >= 1 && e.msg.contains("teapot"), "{}", e);
+        let e = scene_err("[render]\nsamples = \"many\"\n");
+        assert_eq!(e.line, 2);
+        let e = scene_err("[render\n");
+        assert_eq!(e.line, 1);
+    }
+
+    #[test]
+    fn semantic_errors() {
+        let sphere = "type = \"sphere\"\ncenter = [0.0, 0.0, 0.0]\nradius = 1.0\n";
+        let e = scene_err(&format!("[[objects]]\n{}{}\n\n[[lights]]\n{}{}\n", sphere, MAT, sphere, MAT));
+        assert_eq!(e.line, 7);
+        assert!(e.msg.contains("needs a material with emission"), "{}", e);
+        let e = scene_err(&format!("[[objects]]\n{}material = \"nothing\"\n", sphere));
+        assert!(e.msg.contains("unknown material 'nothing'"), "{}", e);
+        let e = scene_err("[[objects]]\ntype = \"instance\"\ngroup = \"g\"\nmatrix = [[1.0, 0.0, 0.0, 0.0], [0.0, 1.0, 0.0, 0.0], [0.0, 0.0, 1.0, 0.0], [0.0, 0.0, 0.0, 1.0]]\n");
+        assert!(e.msg.contains("unknown group 'g'"), "{}", e);
+        let e = scene_err("[[groups.g]]\ntype = \"moving\"\ngroup = \"g\"\nkeyframes = [{ time = 0.0 }]\n\n[[objects]]\ntype = \"moving\"\ngroup = \"g\"\nkeyframes = [{ time = 0.0 }]\n");
+        assert!(e.msg.contains("contains itself"), "{}", e);
+    }
+
+    #[test]
+    fn heightfield_errors() {
+        let hf = format!("[[objects]]\ntype = \"heightfield\"\norigin = [0.0, 0.0, 0.0]\nsize = [1.0, 1.0, 1.0]\n{}\n", MAT);
+        let e = scene_err(&hf);
+        assert!(e.msg.contains("one of heightmap, heights or noise"), "{}", e);
+        let e = scene_err(&format!("{}heights = [[0.0, 1.0], [0.0]]\n", hf));
+        assert!(e.msg.contains("at least 2 rows of the same length"), "{}", e);
+        let e = scene_err(&format!("{}noise = {{ samples = [1, 8], octaves = 2 }}\n", hf));
+        assert!(e.msg.contains("noise samples must be 2 or more"), "{}", e);
+        let e = scene_err(&format!("{}heights = [[0.0, 1.0], [0.0, 1.0]]\nnoise = {{ samples = [4, 4], octaves = 2 }}\n", hf));
+        assert!(e.msg.contains("one of heightmap, heights or noise"), "{}", e);
+    }
+}
@@ -0,0 +1,271 @@
+use crate::raymod::*;
+
+// 表面とみなす距離
+const SDF_HIT_EPS: f64 = 1e-3;
+// 始点がちょうど表面上にあるとき(反射, 屈折の直後)にその面を拾わないための距離
+const SDF_MIN_T: f64 = 10.0 * SDF_HIT_EPS;
+// 法線を中心差分で求めるときの幅
+const SDF_GRAD_H: f64 = 1e-4;
+pub const SDF_DEFAULT_STEPS: usize = 256;
+
+// 符号付き距離関数の部品. 組み合わせて木にする
+// ねじりや拡大をすると距離が正確でなくなるので SdfShape の step で歩幅を縮める
+#[derive(Clone, Debug)]
+pub enum Sdf {
+    Sphere(f64),
+    // 各軸方向の半分の長さ
+    Box(Vec3),
+    // 半分の長さと角の丸みの半径. 外形は Box と同じ大きさ
+    RoundBox(Vec3, f64),
+    // y軸まわり. 中心円の半径と管の半径
+    Torus(f64, f64),
+    Translate(Vec3, Box<Sdf>),
+    Scale(f64, Box<Sdf>),
+    Union(Box<Sdf>, Box<Sdf>),
+    Intersection(Box<Sdf>, Box<Sdf>),
+    Difference(Box<Sdf>, Box<Sdf>),
+    // k は混ぜる幅
+    SmoothUnion(Box<Sdf>, Box<Sdf>, f64),
+    // 各軸の周期で無限に繰り返す. 0 の軸は繰り返さない
+    Repeat(Vec3, Box<Sdf>),
+    // y に比例して xz 面で回す. 1単位あたりのラジアン
+    Twist(f64, Box<Sdf>),
+}
+
+impl Sdf {
+    pub fn translate(self, v: Vec3) -> Sdf {
+        Sdf::Translate(v, Box::new(self))
+    }
+
+    pub fn scale(self, s: f64) -> Sdf {
+        Sdf::Scale(s, Box::new(self))
+    }
+
+    pub fn union(self, b: Sdf) -> Sdf {
+        Sdf::Union(Box::new(self), Box::new(b))
+    }
+
+    pub fn intersection(self, b: Sdf) -> Sdf {
+        Sdf::Intersection(Box::new(self), Box::new(b))
+    }
+
+    pub fn difference(self, b: Sdf) -> Sdf {
+        Sdf::Difference(Box::new(self), Box::new(b))
+    }
+
+    pub fn smooth_union(self, b: Sdf, k: f64) -> Sdf {
+        Sdf::SmoothUnion(Box::new(self), Box::new(b), k)
+    }
+
+    pub fn repeat(self, period: Vec3) -> Sdf {
+        Sdf::Repeat(period, Box::new(self))
+    }
+
+    pub fn twist(self, k: f64) -> Sdf {
+        Sdf::Twist(k, Box::new(self))
+    }
+
+    pub fn dist(&self, p: Vec3) -> f64 {
+        match self {
+            Sdf::Sphere(r) => p.dot(&p).sqrt() - r,
+            Sdf::Box(b) => box_dist(p, *b),
+            Sdf::RoundBox(b, r) => box_dist(p, *b - Vec3::new(*r, *r, *r)) - r,
+            Sdf::Torus(big_r, small_r) => {
+                let qx = (p.x * p.x + p.z * p.z).sqrt() - big_r;
+                (qx * qx + p.y * p.y).sqrt() - small_r
+            }
+            Sdf::Translate(v, s) => s.dist(p - *v),
+            Sdf::Scale(k, s) => s.dist(p * (1.0 / k)) * k,
+            Sdf::Union(a, b) => a.dist(p).min(b.dist(p)),
+            Sdf::Intersection(a, b) => a.dist(p).max(b.dist(p)),
+            Sdf::Difference(a, b) => a.dist(p).max(-b.dist(p)),
+            Sdf::SmoothUnion(a, b, k) => {
+                let (da, db) = (a.dist(p), b.dist(p));
+                let h = (0.5 + 0.5 * (db - da) / k).clamp(0.0, 1.0);
+                db + (da - db) * h - k * h * (1.0 - h)
+            }
+            Sdf::Repeat(c, s) => {
+                let rep = |x: f64, c: f64| if c > 0.0 { x - c * (x / c).round() } else { x };
+                s.dist(Vec3::new(rep(p.x, c.x), rep(p.y, c.y), rep(p.z, c.z)))
+            }
+            Sdf::Twist(k, s) => {
+                let (sn, cs) = (k * p.y).sin_cos();
+                s.dist(Vec3::new(cs * p.x - sn * p.z, p.y, sn * p.x + cs * p.z))
+            }
+        }
+    }
+}
+
+fn box_dist(p: Vec3, b: Vec3) -> f64 {
+    let q = Vec3::new(p.x.abs() - b.x, p.y.abs() - b.y, p.z.abs() - b.z);
+    let out = Vec3::new(q.x.max(0.0), q.y.max(0.0), q.z.max(0.0));
+    out.dot(&out).sqrt() + q.x.max(q.y).max(q.z).min(0.0)
+}
+
+// Sdf をスフィアトレーシングで描く形状
+// 無限に続くものもあるので, 調べる範囲の箱 bbox は自分で与える
+#[derive(Clone, Debug)]
+pub struct SdfShape {
+    pub sdf: Sdf,
+    pub bbox: AABB,
+    pub m: Material,
+    // 1回の交差判定で歩く最大の回数
+    pub max_steps: usize,
+    // 歩幅に掛ける係数. 距離が正確な Sdf なら 1
+    pub step: f64,
+}
+
+impl SdfShape {
+    pub fn new(sdf: Sdf, bbox: AABB, m: Material) -> SdfShape {
+        SdfShape {
+            sdf,
+            bbox,
+            m,
+            max_steps: SDF_DEFAULT_STEPS,
+            step: 1.0,
+        }
+    }
+
+    pub fn with_steps(mut self, max_steps: usize) -> SdfShape {
+        self.max_steps = max_steps;
+        self
+    }
+
+    pub fn with_step_scale(mut self, step: f64) -> SdfShape {
+        self.step = step;
+        self
+    }
+
+    // 中心差分による勾配. 距離関数なので外向きの法線になる
+    fn normal(&self, p: Vec3) -> Vec3 {
+        let h = SDF_GRAD_H;
+        let d = |v: Vec3| self.sdf.dist(p + v) - self.sdf.dist(p - v);
+        Vec3::new(
+            d(Vec3::new(h, 0.0, 0.0)),
+            d(Vec3::new(0.0, h, 0.0)),
+            d(Vec3::new(0.0, 0.0, h)),
+        )
+        .norm()
+    }
+
+    // レイが箱の中にある区間
+    fn clip(&self, ray: &Ray, t_max: f64) -> Option<(f64, f64)> {
+        let mut t0: f64 = 0.0;
+        let mut t1 = t_max;
+        for a in 0..3 {
+            // 軸に平行なら 0 * inf が NaN になるので, 始点が板の中かだけを見る
+            if ray.d[a].abs() < 1e-12 {
+                if ray.o[a] < self.bbox.min[a] || ray.o[a] > self.bbox.max[a] {
+                    return None;
+                }
+                continue;
+            }
+            let inv = 1.0 / ray.d[a];
+            let n = (self.bbox.min[a] - ray.o[a]) * inv;
+            let f = (self.bbox.max[a] - ray.o[a]) * inv;
+            let (n, f) = if n < f { (n, f) } else { (f, n) };
+            t0 = t0.max(n);
+            t1 = t1.min(f);
+            if t1 < t0 {
+                return None;
+            }
+        }
+        Some((t0, t1))
+    }
+}
+
+impl Shape for SdfShape {
+    fn intersect(&self, ray: &Ray, t_max: f64) -> Option<HitInfo<'_>> {
+        let (t0, t1) = self.clip(ray, t_max)?;
+        let mut t = t0;
+        // 始点が表面上にあるときは, 一度表面から離れるまで当たりにしない
+        // (斜めに出ていくレイが同じ面を拾い続けるのを防ぐ)
+        let mut left = false;
+        for _ in 0..self.max_steps {
+            let p = ray.o + ray.d * t;
+            // 中から外へ出るレイ(屈折)もあるので距離の絶対値で歩く
+            let d = self.sdf.dist(p).abs();
+            if d < SDF_HIT_EPS {
+                if left && t > SDF_MIN_T {
+                    return Some(HitInfo::new(t, p, self.normal(p), &self.m));
+                }
+            } else if d > 2.0 * SDF_HIT_EPS {
+                left = true;
+            }
+            t += (d * self.step).max(SDF_HIT_EPS);
+            if t > t1 {
+                return None;
+            }
+        }
+        None
+    }
+
+    fn bounding_box(&self) -> Option<AABB> {
+        Some(self.bbox)
+    }
+
+    // 距離関数を何十回も評価する
+    fn cost(&self) -> f64 {
+        32.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn diffuse() -> Material {
+        Material::new(Vec3::zero(), Vec3::new(0.5, 0.5, 0.5), Refl::Diff)
+    }
+
+    #[test]
+    fn traced_sphere_matches_analytic_sphere() {
+        let c = Vec3::new(1.0, -2.0, 3.0);
+        let r = 2.0;
+        let bbox = AABB::new(c - Vec3::new(3.0, 3.0, 3.0), c + Vec3::new(3.0, 3.0, 3.0));
+        let traced = SdfShape::new(Sdf::Sphere(r).translate(c), bbox, diffuse());
+        let exact = Sphere::new(r, c, Vec3::zero(), Vec3::new(0.5, 0.5, 0.5), Refl::Diff);
+        let mut hits = 0;
+        for i in 0..20 {
+            for j in 0..20 {
+                let o = Vec3::new(-6.0 + i as f64 * 0.6, 8.0, -4.0 + j as f64 * 0.7);
+                let ray = Ray::new(o, (c - o + Vec3::new(0.3 * (i as f64 - 10.0), 0.0, 0.3 * (j as f64 - 10.0))).norm());
+                // 縁をかすめるレイはどちらに転んでもよいので外す
+                let to_c = c - ray.o;
+                let along = to_c.dot(&ray.d);
+                let miss = (to_c.length() - along * along).sqrt();
+                if (miss - r).abs() < 0.05 {
+                    continue;
+                }
+                match (traced.intersect(&ray, INF), exact.intersect(&ray, INF)) {
+                    (Some(a), Some(b)) => {
+                        // 表面から SDF_HIT_EPS 以内の手前で止まる. 斜めに当たると t の差はそれより開く
+                        assert!(((a.p - c).length().sqrt() - r).abs() < SDF_HIT_EPS);
+                        assert!(a.t <= b.t + 1e-9 && b.t - a.t < 0.01, "{} vs {}", a.t, b.t);
+                        assert!((a.n - b.n).length() < 1e-4);
+                        hits += 1;
+                    }
+                    (None, None) => {}
+                    (a, b) => panic!("{:?} vs {:?}", a.map(|h| h.t), b.map(|h| h.t)),
+                }
+            }
+        }
+        assert!(hits > 50);
+    }
+
+    #[test]
+    fn axis_parallel_ray_on_box_face() {
+        // 箱の面 x=0 の上を z に沿って進むレイ. 0 * inf で区間が NaN にならないこと
+        let bbox = AABB::new(Vec3::new(0.0, -2.0, -2.0), Vec3::new(2.0, 2.0, 2.0));
+        let shape = SdfShape::new(Sdf::Sphere(1.0), bbox, diffuse());
+        let h = shape.intersect(&Ray::new(Vec3::new(0.0, 0.0, 5.0), Vec3::new(0.0, 0.0, -1.0)), INF).unwrap();
+        assert!((h.t - 4.0).abs() < 2.0 * SDF_HIT_EPS);
+        assert!((h.n - Vec3::new(0.0, 0.0, 1.0)).length() < 1e-4);
+        // 平行で板の外なら当たらない
+        assert!(shape.intersect(&Ray::new(Vec3::new(-0.5, 0.0, 5.0), Vec3::new(0.0, 0.0, -1.0)), INF).is_none());
+        assert!(shape.intersect(&Ray::new(Vec3::new(0.5, 3.0, 5.0), Vec3::new(0.0, 0.0, -1.0)), INF).is_none());
+        // 2軸に平行でも同じ
+        let h = shape.intersect(&Ray::new(Vec3::new(0.5, 5.0, 0.0), Vec3::new(0.0, -1.0, 0.0)), INF).unwrap();
+        assert!((h.t - (5.0 - 0.75f64.sqrt())).abs() < 2.0 * SDF_HIT_EPS);
+    }
+}
@@ -0,0 +1,53 @@
+use crate::raymod::*;
+
+// 交差点の情報
+pub struct HitInfo<'a> {
+    pub t: f64,
+    pub p: Vec3,
+    // 外向きの法線
+    pub n: Vec3,
+    pub m: &'a Material,
+    // テクスチャ座標. 持たない形状では(0,0)
+    pub uv: (f64, f64),
+    // 頂点色など, m.c の代わりに使う拡散色
+    pub color: Option<Color>,
+    // 発光テクスチャなど, m.e の代わりに使う発光
+    pub emission: Option<Color>,
+    // u が増える向きの接線. 粗さが異方性のときの向きに使う. 持たない形状では None
+    pub tangent: Option<Vec3>,
+}
+
+impl<'a> HitInfo<'a> {
+    pub fn new(t: f64, p: Vec3, n: Vec3, m: &'a Material) -> HitInfo<'a> {
+        HitInfo {
+            t,
+            p,
+            n,
+            m,
+            uv: (0.0, 0.0),
+            color: None,
+            emission: None,
+            tangent: None,
+        }
+    }
+
+    pub fn with_uv(mut self, u: f64, v: f64) -> HitInfo<'a> {
+        self.uv = (u, v);
+        self
+    }
+
+    pub fn with_tangent(mut self, tangent: Option<Vec3>) -> HitInfo<'a> {
+        self.tangent = tangent;
+        self
+    }
+}
+
+pub trait Shape: Send + Sync {
+    // t_max より遠い交差は無視してよい
+    fn intersect(&self, ray: &Ray, t_max: f64) -> Option<HitInfo<'_>>;
+    fn bounding_box(&self) -> Option<AABB>;
+    // 交差判定1回のおおよその重さ. 小さな球を1とする. BVHのSAHで使う
+    fn cost(&self) -> f64 {
+        1.0
+    }
+}
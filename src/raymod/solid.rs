@@ -0,0 +1,679 @@
+use crate::raymod::*;
+
+use std::f64::consts::*;
+
+// 形状ごとの局所座標. 原点 o, 直交する単位ベクトル u, v, w
+// 円柱, 円錐, トーラスは w を軸にとる
+#[derive(Clone, Copy, Debug)]
+struct Frame {
+    o: Vec3,
+    u: Vec3,
+    v: Vec3,
+    w: Vec3,
+}
+
+impl Frame {
+    fn new(o: Vec3, axis: Vec3) -> Frame {
+        let w = axis.norm();
+        let (u, v) = tangent_basis(&w);
+        Frame { o, u, v, w }
+    }
+
+    fn point(&self, p: Vec3) -> Vec3 {
+        let d = p - self.o;
+        Vec3::new(d.dot(&self.u), d.dot(&self.v), d.dot(&self.w))
+    }
+
+    fn dir(&self, d: Vec3) -> Vec3 {
+        Vec3::new(d.dot(&self.u), d.dot(&self.v), d.dot(&self.w))
+    }
+
+    fn world(&self, d: Vec3) -> Vec3 {
+        self.u * d.x + self.v * d.y + self.w * d.z
+    }
+
+    // 局所座標で [-e, e] の箱を囲むワールドの箱
+    fn bounds(&self, c: Vec3, e: Vec3) -> AABB {
+        let c = self.o + self.world(c);
+        let r = Vec3::new(
+            self.u.x.abs() * e.x + self.v.x.abs() * e.y + self.w.x.abs() * e.z,
+            self.u.y.abs() * e.x + self.v.y.abs() * e.y + self.w.y.abs() * e.z,
+            self.u.z.abs() * e.x + self.v.z.abs() * e.y + self.w.z.abs() * e.z,
+        );
+        AABB::new(c - r, c + r)
+    }
+}
+
+// 軸まわりの角度を 0..1 にしたもの. UVの u に使う
+fn azimuth(x: f64, y: f64) -> f64 {
+    let phi = y.atan2(x);
+    (if phi < 0.0 { phi + 2.0 * PI } else { phi }) / (2.0 * PI)
+}
+
+// 軸まわりに回る向き (azimuth が増える向き). 軸の上では None
+fn around_axis(f: &Frame, p: Vec3) -> Option<Vec3> {
+    let r = (p.x * p.x + p.y * p.y).sqrt();
+    if r > 1e-12 {
+        Some(f.world(Vec3::new(-p.y / r, p.x / r, 0.0)))
+    } else {
+        None
+    }
+}
+
+// 直方体. 中心 c, 直交する3軸 axes と各軸方向の半分の長さ half
+#[derive(Clone, Debug)]
+pub struct Cuboid {
+    f: Frame,
+    pub half: Vec3,
+    pub m: Material,
+}
+
+impl Cuboid {
+    // 軸に沿った箱
+    pub fn new(min: Vec3, max: Vec3, m: Material) -> Cuboid {
+        Cuboid {
+            f: Frame {
+                o: (min + max) * 0.5,
+                u: Vec3::new(1.0, 0.0, 0.0),
+                v: Vec3::new(0.0, 1.0, 0.0),
+                w: Vec3::new(0.0, 0.0, 1.0),
+            },
+            half: (max - min) * 0.5,
+            m,
+        }
+    }
+
+    // 向きを持つ箱. x_axis と y_axis から直交する3軸を作る (y_axis は x_axis に直交化する)
+    pub fn oriented(c: Vec3, x_axis: Vec3, y_axis: Vec3, half: Vec3, m: Material) -> Cuboid {
+        let u = x_axis.norm();
+        let v = (y_axis - u * y_axis.dot(&u)).norm();
+        let w = u % v;
+        Cuboid {
+            f: Frame { o: c, u, v, w },
+            half,
+            m,
+        }
+    }
+
+    pub fn center(&self) -> Vec3 {
+        self.f.o
+    }
+
+    // oriented に渡す x_axis と y_axis
+    pub fn axes(&self) -> (Vec3, Vec3) {
+        (self.f.u, self.f.v)
+    }
+}
+
+impl Shape for Cuboid {
+    #[inline]
+    fn intersect(&self, ray: &Ray, t_max: f64) -> Option<HitInfo<'_>> {
+        let o = self.f.point(ray.o);
+        let d = self.f.dir(ray.d);
+        // スラブ法. 入る面と出る面の軸と符号を覚えておく
+        let mut t0 = -INF;
+        let mut t1 = INF;
+        let mut a0 = (0, 0.0);
+        let mut a1 = (0, 0.0);
+        for a in 0..3 {
+            let h = self.half[a];
+            if d[a].abs() < 1e-12 {
+                if o[a] < -h || o[a] > h {
+                    return None;
+                }
+                continue;
+            }
+            let inv = 1.0 / d[a];
+            let (n, f) = ((-h - o[a]) * inv, (h - o[a]) * inv);
+            // 近い方の面の符号は, レイが正の向きなら -1
+            let s = if inv < 0.0 { 1.0 } else { -1.0 };
+            let (n, f) = if n < f { (n, f) } else { (f, n) };
+            if n > t0 {
+                t0 = n;
+                a0 = (a, s);
+            }
+            if f < t1 {
+                t1 = f;
+                a1 = (a, -s);
+            }
+            if t0 > t1 {
+                return None;
+            }
+        }
+        let (t, (axis, sign)) = if t0 > EPS { (t0, a0) } else { (t1, a1) };
+        if t < EPS || t >= t_max {
+            return None;
+        }
+        let p = o + d * t;
+        let mut ln = Vec3::zero();
+        match axis {
+            0 => ln.x = sign,
+            1 => ln.y = sign,
+            _ => ln.z = sign,
+        }
+        // 面内の残り2軸を 0..1 にしたもの
+        let (i, j) = ((axis + 1) % 3, (axis + 2) % 3);
+        let u = (p[i] / self.half[i] + 1.0) * 0.5;
+        let v = (p[j] / self.half[j] + 1.0) * 0.5;
+        let tu = match i {
+            0 => Vec3::new(1.0, 0.0, 0.0),
+            1 => Vec3::new(0.0, 1.0, 0.0),
+            _ => Vec3::new(0.0, 0.0, 1.0),
+        };
+        Some(HitInfo::new(t, ray.o + ray.d * t, self.f.world(ln), &self.m).with_uv(u, v).with_tangent(Some(self.f.world(tu))))
+    }
+
+    fn bounding_box(&self) -> Option<AABB> {
+        Some(self.f.bounds(Vec3::zero(), self.half))
+    }
+
+    fn cost(&self) -> f64 {
+        4.0
+    }
+}
+
+// 円柱. 底面の中心 p から axis 方向に高さ h, 半径 rad
+// capped が false なら両端の開いた筒
+#[derive(Clone, Debug)]
+pub struct Cylinder {
+    f: Frame,
+    pub rad: f64,
+    pub h: f64,
+    pub capped: bool,
+    pub m: Material,
+}
+
+impl Cylinder {
+    pub fn new(p: Vec3, axis: Vec3, rad: f64, h: f64, capped: bool, m: Material) -> Cylinder {
+        Cylinder {
+            f: Frame::new(p, axis),
+            rad,
+            h,
+            capped,
+            m,
+        }
+    }
+
+    // 底面の中心
+    pub fn base(&self) -> Vec3 {
+        self.f.o
+    }
+
+    pub fn axis(&self) -> Vec3 {
+        self.f.w
+    }
+}
+
+impl Shape for Cylinder {
+    #[inline]
+    fn intersect(&self, ray: &Ray, t_max: f64) -> Option<HitInfo<'_>> {
+        let o = self.f.point(ray.o);
+        let d = self.f.dir(ray.d);
+        let mut best: Option<(f64, Vec3, (f64, f64))> = None;
+        let mut take = |t: f64, n: Vec3, uv: (f64, f64)| {
+            if t > EPS && t < best.map_or(t_max, |b| b.0) {
+                best = Some((t, n, uv));
+            }
+        };
+
+        // 側面 x^2 + y^2 = rad^2
+        let a = d.x * d.x + d.y * d.y;
+        if a > 1e-12 {
+            let b = o.x * d.x + o.y * d.y;
+            let c = o.x * o.x + o.y * o.y - self.rad * self.rad;
+            let disc = b * b - a * c;
+            if disc >= 0.0 {
+                let sq = disc.sqrt();
+                for t in [(-b - sq) / a, (-b + sq) / a] {
+                    let p = o + d * t;
+                    if p.z >= 0.0 && p.z <= self.h {
+                        let n = Vec3::new(p.x, p.y, 0.0) * (1.0 / self.rad);
+                        take(t, n, (azimuth(p.x, p.y), p.z / self.h));
+                    }
+                }
+            }
+        }
+        // 蓋 z = 0, z = h. UVは角度と中心からの距離
+        if self.capped && d.z.abs() > 1e-12 {
+            for (z, nz) in [(0.0, -1.0), (self.h, 1.0)] {
+                let t = (z - o.z) / d.z;
+                let p = o + d * t;
+                let r2 = p.x * p.x + p.y * p.y;
+                if r2 <= self.rad * self.rad {
+                    take(t, Vec3::new(0.0, 0.0, nz), (azimuth(p.x, p.y), r2.sqrt() / self.rad));
+                }
+            }
+        }
+
+        let (t, n, uv) = best?;
+        let tangent = around_axis(&self.f, o + d * t);
+        Some(HitInfo::new(t, ray.o + ray.d * t, self.f.world(n), &self.m).with_uv(uv.0, uv.1).with_tangent(tangent))
+    }
+
+    fn bounding_box(&self) -> Option<AABB> {
+        Some(self.f.bounds(
+            Vec3::new(0.0, 0.0, self.h * 0.5),
+            Vec3::new(self.rad, self.rad, self.h * 0.5),
+        ))
+    }
+
+    fn cost(&self) -> f64 {
+        4.0
+    }
+}
+
+// 円錐. 底面の中心 p, 底面の半径 rad, axis 方向に高さ h の位置が頂点
+// capped なら底面を閉じる
+#[derive(Clone, Debug)]
+pub struct Cone {
+    f: Frame,
+    pub rad: f64,
+    pub h: f64,
+    pub capped: bool,
+    pub m: Material,
+}
+
+impl Cone {
+    pub fn new(p: Vec3, axis: Vec3, rad: f64, h: f64, capped: bool, m: Material) -> Cone {
+        Cone {
+            f: Frame::new(p, axis),
+            rad,
+            h,
+            capped,
+            m,
+        }
+    }
+
+    // 底面の中心
+    pub fn base(&self) -> Vec3 {
+        self.f.o
+    }
+
+    pub fn axis(&self) -> Vec3 {
+        self.f.w
+    }
+}
+
+impl Shape for Cone {
+    #[inline]
+    fn intersect(&self, ray: &Ray, t_max: f64) -> Option<HitInfo<'_>> {
+        let o = self.f.point(ray.o);
+        let d = self.f.dir(ray.d);
+        let mut best: Option<(f64, Vec3, (f64, f64))> = None;
+        let mut take = |t: f64, n: Vec3, uv: (f64, f64)| {
+            if t > EPS && t < best.map_or(t_max, |b| b.0) {
+                best = Some((t, n, uv));
+            }
+        };
+
+        // 側面 x^2 + y^2 = k^2 (h - z)^2, k = rad / h
+        let k2 = (self.rad / self.h) * (self.rad / self.h);
+        let w = self.h - o.z;
+        let a = d.x * d.x + d.y * d.y - k2 * d.z * d.z;
+        let b = o.x * d.x + o.y * d.y + k2 * w * d.z;
+        let c = o.x * o.x + o.y * o.y - k2 * w * w;
+        let mut side = |t: f64| {
+            let p = o + d * t;
+            // 頂点の向こう側の影の円錐は除く
+            if p.z >= 0.0 && p.z <= self.h {
+                let n = Vec3::new(p.x, p.y, k2 * (self.h - p.z)).norm();
+                take(t, n, (azimuth(p.x, p.y), p.z / self.h));
+            }
+        };
+        if a.abs() > 1e-12 {
+            let disc = b * b - a * c;
+            if disc >= 0.0 {
+                let sq = disc.sqrt();
+                side((-b - sq) / a);
+                side((-b + sq) / a);
+            }
+        } else if b.abs() > 1e-12 {
+            // 母線に平行なレイは1点でしか交わらない
+            side(-c / (2.0 * b));
+        }
+        if self.capped && d.z.abs() > 1e-12 {
+            let t = -o.z / d.z;
+            let p = o + d * t;
+            let r2 = p.x * p.x + p.y * p.y;
+            if r2 <= self.rad * self.rad {
+                take(t, Vec3::new(0.0, 0.0, -1.0), (azimuth(p.x, p.y), r2.sqrt() / self.rad));
+            }
+        }
+
+        let (t, n, uv) = best?;
+        let tangent = around_axis(&self.f, o + d * t);
+        Some(HitInfo::new(t, ray.o + ray.d * t, self.f.world(n), &self.m).with_uv(uv.0, uv.1).with_tangent(tangent))
+    }
+
+    fn bounding_box(&self) -> Option<AABB> {
+        Some(self.f.bounds(
+            Vec3::new(0.0, 0.0, self.h * 0.5),
+            Vec3::new(self.rad, self.rad, self.h * 0.5),
+        ))
+    }
+
+    fn cost(&self) -> f64 {
+        4.0
+    }
+}
+
+// トーラス. 中心 p, 軸 axis, 中心円の半径 big_r, 管の半径 small_r
+#[derive(Clone, Debug)]
+pub struct Torus {
+    f: Frame,
+    pub big_r: f64,
+    pub small_r: f64,
+    pub m: Material,
+}
+
+impl Torus {
+    pub fn new(p: Vec3, axis: Vec3, big_r: f64, small_r: f64, m: Material) -> Torus {
+        Torus {
+            f: Frame::new(p, axis),
+            big_r,
+            small_r,
+            m,
+        }
+    }
+
+    pub fn center(&self) -> Vec3 {
+        self.f.o
+    }
+
+    pub fn axis(&self) -> Vec3 {
+        self.f.w
+    }
+}
+
+impl Shape for Torus {
+    fn intersect(&self, ray: &Ray, t_max: f64) -> Option<HitInfo<'_>> {
+        let o = self.f.point(ray.o);
+        let d = self.f.dir(ray.d);
+
+        // 外接球で先に弾き, 4次式は球に入る点を原点にして解く (遠くからのレイの桁落ち対策)
+        let br = self.big_r + self.small_r;
+        let b = o.dot(&d);
+        let disc = b * b - o.dot(&o) + br * br;
+        if disc < 0.0 {
+            return None;
+        }
+        let sq = disc.sqrt();
+        if -b + sq < EPS {
+            return None;
+        }
+        let t0 = (-b - sq).max(0.0);
+        if t0 >= t_max {
+            return None;
+        }
+
+        // big_r = 1 になるよう縮めた座標で
+        // (|p|^2 + R^2 - r^2)^2 = 4 R^2 (x^2 + y^2)
+        let s = 1.0 / self.big_r;
+        let q = (o + d * t0) * s;
+        let r2 = self.small_r * self.small_r * s * s;
+        let e = q.dot(&q) + 1.0 - r2;
+        let f = q.dot(&d);
+        let dxy = d.x * d.x + d.y * d.y;
+        let roots = solve_quartic(
+            4.0 * f,
+            4.0 * f * f + 2.0 * e - 4.0 * dxy,
+            4.0 * f * e - 8.0 * (q.x * d.x + q.y * d.y),
+            e * e - 4.0 * (q.x * q.x + q.y * q.y),
+        );
+        let t = roots
+            .into_iter()
+            .map(|u| t0 + u * self.big_r)
+            .filter(|&t| t > EPS && t < t_max)
+            .fold(INF, f64::min);
+        if t >= INF {
+            return None;
+        }
+
+        let p = o + d * t;
+        let rho = (p.x * p.x + p.y * p.y).sqrt();
+        // 管の中心円上で最も近い点から外向き
+        let ring = if rho > 0.0 {
+            Vec3::new(p.x, p.y, 0.0) * (self.big_r / rho)
+        } else {
+            Vec3::new(self.big_r, 0.0, 0.0)
+        };
+        let n = (p - ring).norm();
+        let v = azimuth(rho - self.big_r, p.z);
+        let tangent = around_axis(&self.f, p);
+        Some(HitInfo::new(t, ray.o + ray.d * t, self.f.world(n), &self.m).with_uv(azimuth(p.x, p.y), v).with_tangent(tangent))
+    }
+
+    fn bounding_box(&self) -> Option<AABB> {
+        let br = self.big_r + self.small_r;
+        Some(self.f.bounds(Vec3::zero(), Vec3::new(br, br, self.small_r)))
+    }
+
+    // 4次方程式を解くので他の立体より重い
+    fn cost(&self) -> f64 {
+        8.0
+    }
+}
+
+// 0 に近いとみなす係数
+const ROOT_EPS: f64 = 1e-12;
+
+// x^2 + a x + b = 0 の実数解
+fn solve_quadratic(a: f64, b: f64) -> Vec<f64> {
+    let p = a * 0.5;
+    let disc = p * p - b;
+    if disc.abs() < ROOT_EPS {
+        vec![-p]
+    } else if disc < 0.0 {
+        vec![]
+    } else {
+        let sq = disc.sqrt();
+        vec![-p - sq, -p + sq]
+    }
+}
+
+// x^3 + a x^2 + b x + c = 0 の実数解 (カルダノ, 3実根はビエト)
+fn solve_cubic(a: f64, b: f64, c: f64) -> Vec<f64> {
+    let sub = a / 3.0;
+    let p = (-a * a / 3.0 + b) / 3.0;
+    let q = (2.0 / 27.0 * a * a * a - a * b / 3.0 + c) * 0.5;
+    let cb_p = p * p * p;
+    let disc = q * q + cb_p;
+    let mut roots = if disc.abs() < ROOT_EPS {
+        if q.abs() < ROOT_EPS {
+            vec![0.0]
+        } else {
+            let u = (-q).cbrt();
+            vec![2.0 * u, -u]
+        }
+    } else if disc < 0.0 {
+        let phi = (-q / (-cb_p).sqrt()).clamp(-1.0, 1.0).acos() / 3.0;
+        let t = 2.0 * (-p).sqrt();
+        vec![
+            t * phi.cos(),
+            -t * (phi + FRAC_PI_3).cos(),
+            -t * (phi - FRAC_PI_3).cos(),
+        ]
+    } else {
+        let sq = disc.sqrt();
+        vec![(sq - q).cbrt() - (sq + q).cbrt()]
+    };
+    for r in roots.iter_mut() {
+        *r -= sub;
+    }
+    roots
+}
+
+// x^4 + a x^3 + b x^2 + c x + d = 0 の実数解 (フェラーリ)
+// 解析解は桁落ちしやすいのでニュートン法で2回磨く
+pub fn solve_quartic(a: f64, b: f64, c: f64, d: f64) -> Vec<f64> {
+    let sub = a / 4.0;
+    let a2 = a * a;
+    // x = y - a/4 で3次の項を消す: y^4 + p y^2 + q y + r = 0
+    let p = -3.0 / 8.0 * a2 + b;
+    let q = a2 * a / 8.0 - a * b / 2.0 + c;
+    let r = -3.0 / 256.0 * a2 * a2 + a2 * b / 16.0 - a * c / 4.0 + d;
+
+    let mut roots = if r.abs() < ROOT_EPS {
+        let mut v = solve_cubic(0.0, p, q);
+        v.push(0.0);
+        v
+    } else {
+        // 分解方程式の解 z から2つの2次式に分ける. 最大の解なら下の平方根の中が負になりにくい
+        let z = solve_cubic(-p / 2.0, -r, r * p / 2.0 - q * q / 8.0)
+            .into_iter()
+            .fold(-INF, f64::max);
+        let u = z * z - r;
+        let v = 2.0 * z - p;
+        let u = if u.abs() < ROOT_EPS { 0.0 } else if u > 0.0 { u.sqrt() } else { return vec![] };
+        let v = if v.abs() < ROOT_EPS { 0.0 } else if v > 0.0 { v.sqrt() } else { return vec![] };
+        let v = if q < 0.0 { -v } else { v };
+        let mut s = solve_quadratic(v, z - u);
+        s.extend(solve_quadratic(-v, z + u));
+        s
+    };
+
+    for x in roots.iter_mut() {
+        *x -= sub;
+        let poly = |x: f64| (((x + a) * x + b) * x + c) * x + d;
+        for _ in 0..2 {
+            let f = poly(*x);
+            let df = ((4.0 * *x + 3.0 * a) * *x + 2.0 * b) * *x + c;
+            // 重解の近くでは df が小さく遠くへ飛ぶので, 残差が減るときだけ進める
+            if df.abs() > ROOT_EPS {
+                let nx = *x - f / df;
+                if poly(nx).abs() < f.abs() {
+                    *x = nx;
+                }
+            }
+        }
+    }
+    roots
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn gray() -> Material {
+        Material::new(Vec3::zero(), Vec3::new(0.5, 0.5, 0.5), Refl::Diff)
+    }
+
+    fn near(a: Vec3, b: Vec3) -> bool {
+        (a - b).length() < 1e-12
+    }
+
+    // 求めた解がどれも expect のどれかに近く, expect のどれにも解が近い
+    fn same_roots(mut got: Vec<f64>, expect: &[f64], tol: f64) {
+        got.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        for x in got.iter() {
+            assert!(expect.iter().any(|e| (x - e).abs() < tol), "root {} not in {:?}", x, expect);
+        }
+        for e in expect {
+            assert!(got.iter().any(|x| (x - e).abs() < tol), "missing root {} in {:?}", e, got);
+        }
+    }
+
+    #[test]
+    fn cubic_roots() {
+        // (x-1)(x-2)(x-3)
+        same_roots(solve_cubic(-6.0, 11.0, -6.0), &[1.0, 2.0, 3.0], 1e-9);
+        // x^3 - 1 は実数解が1つ
+        same_roots(solve_cubic(0.0, 0.0, -1.0), &[1.0], 1e-9);
+        // (x-1)^2 (x+2) の重解
+        same_roots(solve_cubic(0.0, -3.0, 2.0), &[1.0, -2.0], 1e-6);
+        // (x-2)^3 の三重解
+        same_roots(solve_cubic(-6.0, 12.0, -8.0), &[2.0], 1e-6);
+    }
+
+    #[test]
+    fn quartic_roots() {
+        // (x-1)(x-2)(x-3)(x-4)
+        same_roots(solve_quartic(-10.0, 35.0, -50.0, 24.0), &[1.0, 2.0, 3.0, 4.0], 1e-9);
+        // x(x-1)(x+1)(x-2) は定数項が 0
+        same_roots(solve_quartic(-2.0, -1.0, 2.0, 0.0), &[-1.0, 0.0, 1.0, 2.0], 1e-9);
+        // x^4 + 1 は実数解が無い
+        assert!(solve_quartic(0.0, 0.0, 0.0, 1.0).is_empty());
+        // (x-1)^2 (x-3)^2 の重解
+        same_roots(solve_quartic(-8.0, 22.0, -24.0, 9.0), &[1.0, 3.0], 1e-6);
+        // (x-1)(x-1-1e-6)(x+2)(x+5) の近い2解
+        let (a, b) = (1.0, 1.0 + 1e-6);
+        let roots = solve_quartic(
+            7.0 - a - b,
+            10.0 - 7.0 * (a + b) + a * b,
+            -10.0 * (a + b) + 7.0 * a * b,
+            10.0 * a * b,
+        );
+        same_roots(roots, &[a, b, -2.0, -5.0], 1e-5);
+    }
+
+    #[test]
+    fn cuboid_hits_from_outside_and_inside() {
+        let c = Cuboid::new(Vec3::new(-1.0, -1.0, -1.0), Vec3::new(1.0, 1.0, 1.0), gray());
+        let h = c.intersect(&Ray::new(Vec3::new(0.0, 0.0, 5.0), Vec3::new(0.0, 0.0, -1.0)), INF).unwrap();
+        assert!((h.t - 4.0).abs() < 1e-12);
+        assert!(near(h.n, Vec3::new(0.0, 0.0, 1.0)));
+        // 中からは出る面に当たり, 法線は外向きのまま
+        let h = c.intersect(&Ray::new(Vec3::zero(), Vec3::new(1.0, 0.0, 0.0)), INF).unwrap();
+        assert!((h.t - 1.0).abs() < 1e-12);
+        assert!(near(h.n, Vec3::new(1.0, 0.0, 0.0)));
+        // 軸に平行で板の外を通るレイ
+        assert!(c.intersect(&Ray::new(Vec3::new(0.0, 3.0, 5.0), Vec3::new(0.0, 0.0, -1.0)), INF).is_none());
+        assert!(c.intersect(&Ray::new(Vec3::new(0.0, 0.0, 5.0), Vec3::new(0.0, 0.0, -1.0)), 3.0).is_none());
+    }
+
+    #[test]
+    fn oriented_cuboid_face_normal() {
+        // y 軸まわりに 45 度回した箱. x 方向から来ると (1, 0, 1) の向きの面に当たる
+        let c = Cuboid::oriented(Vec3::zero(), Vec3::new(1.0, 0.0, 1.0), Vec3::new(0.0, 1.0, 0.0), Vec3::new(1.0, 1.0, 1.0), gray());
+        let h = c.intersect(&Ray::new(Vec3::new(5.0, 0.0, 0.3), Vec3::new(-1.0, 0.0, 0.0)), INF).unwrap();
+        let n = Vec3::new(1.0, 0.0, 1.0).norm();
+        assert!((h.n - n).length() < 1e-12);
+        assert!(((h.p.dot(&n)) - 1.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn cylinder_side_caps_and_open_tube() {
+        let c = Cylinder::new(Vec3::zero(), Vec3::new(0.0, 1.0, 0.0), 1.0, 2.0, true, gray());
+        let h = c.intersect(&Ray::new(Vec3::new(5.0, 1.0, 0.0), Vec3::new(-1.0, 0.0, 0.0)), INF).unwrap();
+        assert!((h.t - 4.0).abs() < 1e-9);
+        assert!((h.n - Vec3::new(1.0, 0.0, 0.0)).length() < 1e-9);
+        let h = c.intersect(&Ray::new(Vec3::new(0.2, 5.0, 0.0), Vec3::new(0.0, -1.0, 0.0)), INF).unwrap();
+        assert!((h.t - 3.0).abs() < 1e-9);
+        assert!((h.n - Vec3::new(0.0, 1.0, 0.0)).length() < 1e-9);
+        // 蓋の無い筒は軸に沿って素通りする
+        let open = Cylinder::new(Vec3::zero(), Vec3::new(0.0, 1.0, 0.0), 1.0, 2.0, false, gray());
+        assert!(open.intersect(&Ray::new(Vec3::new(0.2, 5.0, 0.0), Vec3::new(0.0, -1.0, 0.0)), INF).is_none());
+        // 上を越えるレイ
+        assert!(c.intersect(&Ray::new(Vec3::new(5.0, 2.5, 0.0), Vec3::new(-1.0, 0.0, 0.0)), INF).is_none());
+    }
+
+    #[test]
+    fn cone_side_base_and_apex() {
+        let c = Cone::new(Vec3::zero(), Vec3::new(0.0, 1.0, 0.0), 1.0, 2.0, true, gray());
+        // 高さ 1 では半径 0.5. 法線は軸の方へ傾く
+        let h = c.intersect(&Ray::new(Vec3::new(5.0, 1.0, 0.0), Vec3::new(-1.0, 0.0, 0.0)), INF).unwrap();
+        assert!((h.t - 4.5).abs() < 1e-9);
+        assert!((h.n - Vec3::new(2.0, 1.0, 0.0).norm()).length() < 1e-9);
+        let h = c.intersect(&Ray::new(Vec3::new(0.3, -5.0, 0.0), Vec3::new(0.0, 1.0, 0.0)), INF).unwrap();
+        assert!((h.t - 5.0).abs() < 1e-9);
+        assert!((h.n - Vec3::new(0.0, -1.0, 0.0)).length() < 1e-9);
+        // 頂点より上の影の円錐には当たらない
+        assert!(c.intersect(&Ray::new(Vec3::new(5.0, 3.0, 0.0), Vec3::new(-1.0, 0.0, 0.0)), INF).is_none());
+    }
+
+    #[test]
+    fn torus_outer_top_and_hole() {
+        let t = Torus::new(Vec3::zero(), Vec3::new(0.0, 1.0, 0.0), 2.0, 0.5, gray());
+        let h = t.intersect(&Ray::new(Vec3::new(5.0, 0.0, 0.0), Vec3::new(-1.0, 0.0, 0.0)), INF).unwrap();
+        assert!((h.t - 2.5).abs() < 1e-9);
+        assert!((h.n - Vec3::new(1.0, 0.0, 0.0)).length() < 1e-9);
+        let h = t.intersect(&Ray::new(Vec3::new(2.0, 5.0, 0.0), Vec3::new(0.0, -1.0, 0.0)), INF).unwrap();
+        assert!((h.t - 4.5).abs() < 1e-9);
+        assert!((h.n - Vec3::new(0.0, 1.0, 0.0)).length() < 1e-9);
+        // 穴を抜けるレイ
+        assert!(t.intersect(&Ray::new(Vec3::new(0.0, 5.0, 0.0), Vec3::new(0.0, -1.0, 0.0)), INF).is_none());
+        // 遠くからでも桁落ちしない
+        let h = t.intersect(&Ray::new(Vec3::new(1e4, 0.0, 0.0), Vec3::new(-1.0, 0.0, 0.0)), INF).unwrap();
+        assert!((h.t - (1e4 - 2.5)).abs() < 1e-6);
+    }
+}
@@ -0,0 +1,150 @@
+use crate::raymod::*;
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+// 同じ座標の頂点をまとめて共有頂点のメッシュにする
+struct Welder {
+    map: HashMap<[u64; 3], usize>,
+    positions: Vec<Vec3>,
+    indices: Vec<[usize; 3]>,
+}
+
+impl Welder {
+    fn vertex(&mut self, p: Vec3) -> usize {
+        let key = [p.x.to_bits(), p.y.to_bits(), p.z.to_bits()];
+        let positions = &mut self.positions;
+        *self.map.entry(key).or_insert_with(|| {
+            positions.push(p);
+            positions.len() - 1
+        })
+    }
+
+    // 法線が書かれていて頂点の回り順と逆なら法線を信じて裏返す
+    fn facet(&mut self, n: Vec3, v: &[Vec3]) {
+        for k in 1..v.len() - 1 {
+            let (a, mut b, mut c) = (v[0], v[k], v[k + 1]);
+            if n.dot(&((b - a) % (c - a))) < 0.0 {
+                std::mem::swap(&mut b, &mut c);
+            }
+            let f = [self.vertex(a), self.vertex(b), self.vertex(c)];
+            self.indices.push(f);
+        }
+    }
+}
+
+fn f32_at(b: &[u8], p: usize) -> f64 {
+    f32::from_le_bytes([b[p], b[p + 1], b[p + 2], b[p + 3]]) as f64
+}
+
+// ASCII / バイナリのSTL. 面ごとの法線しか無いので平らな面として扱う
+pub fn load_stl(path: &Path) -> Result<Mesh, LoadError> {
+    let bytes = fs::read(path).map_err(|e| LoadError::new(path, 0, e.to_string()))?;
+    let mut w = Welder {
+        map: HashMap::new(),
+        positions: vec![],
+        indices: vec![],
+    };
+
+    let binary_count = if bytes.len() >= 84 {
+        let n = u32::from_le_bytes([bytes[80], bytes[81], bytes[82], bytes[83]]) as usize;
+        if bytes.len() == 84 + 50 * n { Some(n) } else { None }
+    } else {
+        None
+    };
+
+    if let Some(n) = binary_count {
+        for i in 0..n {
+            let p = 84 + 50 * i;
+            let v = |k: usize| {
+                let q = p + 12 * k;
+                Vec3::new(f32_at(&bytes, q), f32_at(&bytes, q + 4), f32_at(&bytes, q + 8))
+            };
+            w.facet(v(0), &[v(1), v(2), v(3)]);
+        }
+    } else {
+        if !bytes.starts_with(b"solid") {
+            return Err(LoadError::new(path, 0, "neither ASCII (solid ...) nor binary STL"));
+        }
+        let text = String::from_utf8_lossy(&bytes);
+        let mut n = Vec3::zero();
+        let mut verts: Vec<Vec3> = vec![];
+        for (i, l) in text.lines().enumerate() {
+            let line = i + 1;
+            let toks: Vec<&str> = l.split_whitespace().collect();
+            let num = |k: usize| -> Result<f64, LoadError> {
+                let t = toks.get(k).ok_or_else(|| LoadError::new(path, line, "missing number"))?;
+                t.parse()
+                    .map_err(|_| LoadError::new(path, line, format!("bad number '{}'", t)))
+            };
+            match toks.first().copied() {
+                Some("facet") => {
+                    if toks.get(1) != Some(&"normal") {
+                        return Err(LoadError::new(path, line, "expected 'facet normal'"));
+                    }
+                    n = Vec3::new(num(2)?, num(3)?, num(4)?);
+                    verts.clear();
+                }
+                Some("vertex") => verts.push(Vec3::new(num(1)?, num(2)?, num(3)?)),
+                Some("endloop") => {
+                    if verts.len() < 3 {
+                        return Err(LoadError::new(path, line, "loop with fewer than 3 vertices"));
+                    }
+                    w.facet(n, &verts);
+                    verts.clear();
+                }
+                Some("solid") | Some("outer") | Some("endfacet") | Some("endsolid") | None => {}
+                Some(k) => return Err(LoadError::new(path, line, format!("unexpected '{}'", k))),
+            }
+        }
+    }
+
+    if w.indices.is_empty() {
+        return Err(LoadError::new(path, 0, "no facets"));
+    }
+    Ok(Mesh::new(
+        w.positions,
+        w.indices,
+        Material::new(Vec3::zero(), Vec3::new(0.75, 0.75, 0.75), Refl::Diff),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FACET: &str = "facet normal 0 0 1\nouter loop\nvertex 0 0 0\nvertex 1 0 0\nvertex 0 1 0\nendloop\nendfacet\n";
+
+    fn stl_err(name: &str, data: &[u8]) -> LoadError {
+        load_stl(&test_file(name, data)).err().unwrap()
+    }
+
+    #[test]
+    fn ascii_and_binary_load() {
+        let mesh = load_stl(&test_file("ok.stl", format!("solid t\n{}endsolid t\n", FACET).as_bytes())).unwrap();
+        assert_eq!(mesh.indices.len(), 1);
+        // 80 バイトのヘッダ, 個数 1, 法線と3頂点と属性
+        let mut data = vec![0u8; 80];
+        data.extend_from_slice(&1u32.to_le_bytes());
+        for v in [0.0f32, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0] {
+            data.extend_from_slice(&v.to_le_bytes());
+        }
+        data.extend_from_slice(&[0, 0]);
+        assert_eq!(load_stl(&test_file("ok_bin.stl", &data)).unwrap().indices.len(), 1);
+    }
+
+    #[test]
+    fn malformed_stl() {
+        let e = stl_err("not_stl.stl", b"hello\n");
+        assert!(e.msg.contains("neither ASCII"), "{}", e);
+        let text = FACET.replace("vertex 0 1 0\n", "");
+        let e = stl_err("two_vertices.stl", format!("solid t\n{}endsolid t\n", text).as_bytes());
+        assert_eq!(e.line, 6);
+        assert!(e.msg.contains("fewer than 3"), "{}", e);
+        let e = stl_err("bad_number.stl", format!("solid t\n{}endsolid t\n", FACET.replace("vertex 1 0 0", "vertex 1 a 0")).as_bytes());
+        assert_eq!(e.line, 5);
+        let e = stl_err("empty.stl", b"solid t\nendsolid t\n");
+        assert!(e.msg.contains("no facets"), "{}", e);
+    }
+}
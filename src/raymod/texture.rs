@@ -0,0 +1,87 @@
+use crate::raymod::*;
+
+// 範囲外の UV の扱い
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Wrap {
+    Repeat,
+    Mirror,
+    Clamp,
+}
+
+// 画像のテクスチャ. 画素は線形の RGB で持ち, 双線形で補間して引く
+// UV の原点は画像の左上で, v は下へ向かう (glTF と同じ)
+#[derive(Debug)]
+pub struct Texture {
+    w: usize,
+    h: usize,
+    texels: Vec<Color>,
+    pub wrap: (Wrap, Wrap),
+}
+
+// 8bit の sRGB の値を線形にする
+pub fn srgb_to_linear(c: f64) -> f64 {
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+impl Texture {
+    pub fn new(w: usize, h: usize, texels: Vec<Color>) -> Texture {
+        assert_eq!(texels.len(), w * h, "one texel per pixel");
+        assert!(w > 0 && h > 0, "texture must not be empty");
+        Texture {
+            w,
+            h,
+            texels,
+            wrap: (Wrap::Repeat, Wrap::Repeat),
+        }
+    }
+
+    pub fn with_wrap(mut self, s: Wrap, t: Wrap) -> Texture {
+        self.wrap = (s, t);
+        self
+    }
+
+    pub fn size(&self) -> (usize, usize) {
+        (self.w, self.h)
+    }
+
+    // 全画素の平均
+    pub fn average(&self) -> Color {
+        let sum = self.texels.iter().fold(Color::zero(), |a, &c| a + c);
+        sum * (1.0 / self.texels.len() as f64)
+    }
+
+    fn texel(&self, x: i64, y: i64) -> Color {
+        let x = wrap_index(x, self.w, self.wrap.0);
+        let y = wrap_index(y, self.h, self.wrap.1);
+        self.texels[y * self.w + x]
+    }
+
+    pub fn sample(&self, uv: (f64, f64)) -> Color {
+        // 画素の中心が整数になるようにずらす
+        let x = uv.0 * self.w as f64 - 0.5;
+        let y = uv.1 * self.h as f64 - 0.5;
+        let (x0, y0) = (x.floor(), y.floor());
+        let (fx, fy) = (x - x0, y - y0);
+        let (x0, y0) = (x0 as i64, y0 as i64);
+        let top = self.texel(x0, y0) * (1.0 - fx) + self.texel(x0 + 1, y0) * fx;
+        let bottom = self.texel(x0, y0 + 1) * (1.0 - fx) + self.texel(x0 + 1, y0 + 1) * fx;
+        top * (1.0 - fy) + bottom * fy
+    }
+}
+
+fn wrap_index(i: i64, n: usize, wrap: Wrap) -> usize {
+    let n = n as i64;
+    let i = match wrap {
+        Wrap::Repeat => i.rem_euclid(n),
+        Wrap::Mirror => {
+            let k = i.rem_euclid(2 * n);
+            if k < n { k } else { 2 * n - 1 - k }
+        }
+        Wrap::Clamp => i.clamp(0, n - 1),
+    };
+    i as usize
+}
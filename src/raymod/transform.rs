@@ -0,0 +1,312 @@
+use crate::raymod::*;
+
+use std::ops::Mul;
+
+// 4x4行列. 点は列ベクトル (x, y, z, 1) として左から掛ける
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Mat4 {
+    pub m: [[f64; 4]; 4],
+}
+
+impl Mat4 {
+    pub fn new(m: [[f64; 4]; 4]) -> Mat4 {
+        Mat4 { m }
+    }
+
+    pub fn identity() -> Mat4 {
+        let mut m = [[0.0; 4]; 4];
+        for (i, row) in m.iter_mut().enumerate() {
+            row[i] = 1.0;
+        }
+        Mat4 { m }
+    }
+
+    pub fn transpose(&self) -> Mat4 {
+        let mut m = [[0.0; 4]; 4];
+        for (i, row) in m.iter_mut().enumerate() {
+            for (j, x) in row.iter_mut().enumerate() {
+                *x = self.m[j][i];
+            }
+        }
+        Mat4 { m }
+    }
+
+    // ガウス・ジョルダン法. 特異なら None
+    pub fn inverse(&self) -> Option<Mat4> {
+        let mut a = self.m;
+        let mut inv = Mat4::identity().m;
+        for c in 0..4 {
+            let p = (c..4).max_by(|&i, &j| a[i][c].abs().total_cmp(&a[j][c].abs()))?;
+            if a[p][c].abs() < 1e-15 {
+                return None;
+            }
+            a.swap(c, p);
+            inv.swap(c, p);
+            let s = 1.0 / a[c][c];
+            for j in 0..4 {
+                a[c][j] *= s;
+                inv[c][j] *= s;
+            }
+            for r in 0..4 {
+                if r == c {
+                    continue;
+                }
+                let f = a[r][c];
+                for j in 0..4 {
+                    a[r][j] -= f * a[c][j];
+                    inv[r][j] -= f * inv[c][j];
+                }
+            }
+        }
+        Some(Mat4 { m: inv })
+    }
+
+    // 平行移動を含めて点を変換する
+    #[inline]
+    pub fn point(&self, p: Vec3) -> Vec3 {
+        let m = &self.m;
+        Vec3::new(
+            m[0][0] * p.x + m[0][1] * p.y + m[0][2] * p.z + m[0][3],
+            m[1][0] * p.x + m[1][1] * p.y + m[1][2] * p.z + m[1][3],
+            m[2][0] * p.x + m[2][1] * p.y + m[2][2] * p.z + m[2][3],
+        )
+    }
+
+    // 方向ベクトル. 平行移動は効かない
+    #[inline]
+    pub fn dir(&self, d: Vec3) -> Vec3 {
+        let m = &self.m;
+        Vec3::new(
+            m[0][0] * d.x + m[0][1] * d.y + m[0][2] * d.z,
+            m[1][0] * d.x + m[1][1] * d.y + m[1][2] * d.z,
+            m[2][0] * d.x + m[2][1] * d.y + m[2][2] * d.z,
+        )
+    }
+
+    // 転置した行列で方向を変換する. 逆行列に対して使えば法線の変換になる
+    #[inline]
+    pub fn dir_transposed(&self, d: Vec3) -> Vec3 {
+        let m = &self.m;
+        Vec3::new(
+            m[0][0] * d.x + m[1][0] * d.y + m[2][0] * d.z,
+            m[0][1] * d.x + m[1][1] * d.y + m[2][1] * d.z,
+            m[0][2] * d.x + m[1][2] * d.y + m[2][2] * d.z,
+        )
+    }
+}
+
+impl Mul for Mat4 {
+    type Output = Mat4;
+    fn mul(self, rhs: Mat4) -> Mat4 {
+        let mut m = [[0.0; 4]; 4];
+        for (i, row) in m.iter_mut().enumerate() {
+            for (j, x) in row.iter_mut().enumerate() {
+                *x = (0..4).map(|k| self.m[i][k] * rhs.m[k][j]).sum();
+            }
+        }
+        Mat4 { m }
+    }
+}
+
+// 行列とその逆行列の組. 基本の変換は逆行列を直接作るので逆行列の計算はしない
+// a * b は「b をしてから a」
+#[derive(Clone, Copy, Debug)]
+pub struct Transform {
+    pub m: Mat4,
+    pub inv: Mat4,
+}
+
+impl Transform {
+    pub fn identity() -> Transform {
+        Transform {
+            m: Mat4::identity(),
+            inv: Mat4::identity(),
+        }
+    }
+
+    // 任意の行列から. 逆行列が無ければ None
+    pub fn from_matrix(m: Mat4) -> Option<Transform> {
+        Some(Transform { m, inv: m.inverse()? })
+    }
+
+    pub fn translate(v: Vec3) -> Transform {
+        let m = Mat4::new([
+            [1.0, 0.0, 0.0, v.x],
+            [0.0, 1.0, 0.0, v.y],
+            [0.0, 0.0, 1.0, v.z],
+            [0.0, 0.0, 0.0, 1.0],
+        ]);
+        let inv = Mat4::new([
+            [1.0, 0.0, 0.0, -v.x],
+            [0.0, 1.0, 0.0, -v.y],
+            [0.0, 0.0, 1.0, -v.z],
+            [0.0, 0.0, 0.0, 1.0],
+        ]);
+        Transform { m, inv }
+    }
+
+    pub fn scale(v: Vec3) -> Transform {
+        let m = Mat4::new([
+            [v.x, 0.0, 0.0, 0.0],
+            [0.0, v.y, 0.0, 0.0],
+            [0.0, 0.0, v.z, 0.0],
+            [0.0, 0.0, 0.0, 1.0],
+        ]);
+        let inv = Mat4::new([
+            [1.0 / v.x, 0.0, 0.0, 0.0],
+            [0.0, 1.0 / v.y, 0.0, 0.0],
+            [0.0, 0.0, 1.0 / v.z, 0.0],
+            [0.0, 0.0, 0.0, 1.0],
+        ]);
+        Transform { m, inv }
+    }
+
+    pub fn uniform_scale(s: f64) -> Transform {
+        Transform::scale(Vec3::new(s, s, s))
+    }
+
+    // axis まわりに theta ラジアン (右手系). 回転の逆行列は転置
+    pub fn rotate(axis: Vec3, theta: f64) -> Transform {
+        let a = axis.norm();
+        let (s, c) = theta.sin_cos();
+        let t = 1.0 - c;
+        let m = Mat4::new([
+            [t * a.x * a.x + c, t * a.x * a.y - s * a.z, t * a.x * a.z + s * a.y, 0.0],
+            [t * a.x * a.y + s * a.z, t * a.y * a.y + c, t * a.y * a.z - s * a.x, 0.0],
+            [t * a.x * a.z - s * a.y, t * a.y * a.z + s * a.x, t * a.z * a.z + c, 0.0],
+            [0.0, 0.0, 0.0, 1.0],
+        ]);
+        Transform { m, inv: m.transpose() }
+    }
+
+    pub fn rotate_x(theta: f64) -> Transform {
+        Transform::rotate(Vec3::new(1.0, 0.0, 0.0), theta)
+    }
+
+    pub fn rotate_y(theta: f64) -> Transform {
+        Transform::rotate(Vec3::new(0.0, 1.0, 0.0), theta)
+    }
+
+    pub fn rotate_z(theta: f64) -> Transform {
+        Transform::rotate(Vec3::new(0.0, 0.0, 1.0), theta)
+    }
+
+    pub fn inverse(&self) -> Transform {
+        Transform {
+            m: self.inv,
+            inv: self.m,
+        }
+    }
+
+    pub fn point(&self, p: Vec3) -> Vec3 {
+        self.m.point(p)
+    }
+
+    pub fn dir(&self, d: Vec3) -> Vec3 {
+        self.m.dir(d)
+    }
+
+    // 法線は逆行列の転置で変換する (非一様な拡大でも面に垂直なまま)
+    pub fn normal(&self, n: Vec3) -> Vec3 {
+        self.inv.dir_transposed(n).norm()
+    }
+
+    // 箱の8つの角を変換して囲み直す
+    pub fn bounds(&self, b: &AABB) -> AABB {
+        let mut out = AABB::empty();
+        for i in 0..8 {
+            let c = Vec3::new(
+                if i & 1 == 0 { b.min.x } else { b.max.x },
+                if i & 2 == 0 { b.min.y } else { b.max.y },
+                if i & 4 == 0 { b.min.z } else { b.max.z },
+            );
+            let p = self.point(c);
+            out = surrounding_box(&out, &AABB::new(p, p));
+        }
+        out
+    }
+}
+
+impl Mul for Transform {
+    type Output = Transform;
+    fn mul(self, rhs: Transform) -> Transform {
+        Transform {
+            m: self.m * rhs.m,
+            inv: rhs.inv * self.inv,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_identity(a: &Mat4) {
+        let id = Mat4::identity();
+        for i in 0..4 {
+            for j in 0..4 {
+                assert!((a.m[i][j] - id.m[i][j]).abs() < 1e-12, "{:?}", a);
+            }
+        }
+    }
+
+    fn sample() -> Transform {
+        Transform::translate(Vec3::new(3.0, -2.0, 7.0))
+            * Transform::rotate(Vec3::new(1.0, 2.0, -0.5), 0.7)
+            * Transform::scale(Vec3::new(2.0, 0.5, 3.0))
+    }
+
+    #[test]
+    fn matrix_times_inverse_is_identity() {
+        let xf = sample() * Transform::rotate_x(-1.1) * Transform::uniform_scale(1.5);
+        assert_identity(&(xf.m * xf.inv));
+        assert_identity(&(xf.inv * xf.m));
+        // ガウス・ジョルダン法の逆行列も合成で作った逆行列と同じ
+        let general = Transform::from_matrix(xf.m).unwrap();
+        assert_identity(&(general.inv * xf.m));
+        let m = Mat4::new([
+            [2.0, 1.0, 0.0, 1.0],
+            [0.0, 3.0, 1.0, -2.0],
+            [1.0, 0.0, 4.0, 0.5],
+            [0.0, 0.0, 0.0, 1.0],
+        ]);
+        assert_identity(&(m * m.inverse().unwrap()));
+        // 特異な行列は逆を持たない
+        let mut singular = m;
+        singular.m[2] = singular.m[0];
+        assert!(singular.inverse().is_none());
+        assert!(Transform::from_matrix(singular).is_none());
+    }
+
+    #[test]
+    fn normals_stay_perpendicular_under_nonuniform_scale() {
+        let xf = sample();
+        // 面に沿った2本のベクトルと, その外積の法線
+        let u = Vec3::new(1.0, 1.0, 0.0);
+        let v = Vec3::new(0.0, 1.0, -2.0);
+        let n = (u % v).norm();
+        let (tu, tv, tn) = (xf.dir(u), xf.dir(v), xf.normal(n));
+        assert!(tu.dot(&tn).abs() < 1e-12);
+        assert!(tv.dot(&tn).abs() < 1e-12);
+        assert!((tn.length() - 1.0).abs() < 1e-12);
+        // 向きは保たれる
+        assert!(tn.dot(&(tu % tv)) > 0.0);
+        // 法線を行列そのもので移すと垂直でなくなる
+        assert!(tu.dot(&xf.dir(n)).abs() > 0.1);
+    }
+
+    #[test]
+    fn bounds_enclose_transformed_corners() {
+        let xf = sample();
+        let b = AABB::new(Vec3::new(-1.0, -2.0, -3.0), Vec3::new(1.0, 2.0, 3.0));
+        let tb = xf.bounds(&b);
+        for i in 0..27 {
+            let f = |k: usize| (k % 3) as f64 * 0.5;
+            let p = Vec3::new(-1.0 + 2.0 * f(i), -2.0 + 4.0 * f(i / 3), -3.0 + 6.0 * f(i / 9));
+            let q = xf.point(p);
+            for a in 0..3 {
+                assert!(q[a] >= tb.min[a] - 1e-9 && q[a] <= tb.max[a] + 1e-9);
+            }
+        }
+    }
+}
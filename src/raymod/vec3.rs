@@ -1,7 +1,7 @@
 
 use std::fs;
 use std::io::Write;
-use std::ops::{Add, Mul, Rem, Sub};
+use std::ops::{Add, Index, Mul, Rem, Sub};
 
 #[derive(Copy, Clone, Debug)]
 pub struct Vec3 {
@@ -13,7 +13,6 @@ pub struct Vec3 {
 pub type Color = Vec3;
 
 #[allow(dead_code)]
-
 impl Vec3 {
     pub fn new(x: f64, y: f64, z: f64) -> Vec3 {
         Vec3 { x, y, z }
@@ -26,16 +25,16 @@ impl Vec3 {
     }
     pub fn norm(mut self) -> Vec3 {
         let l = 1.0 / (self.x * self.x + self.y * self.y + self.z * self.z).sqrt();
-        self.x = self.x * l;
-        self.y = self.y * l;
-        self.z = self.z * l;
+        self.x *= l;
+        self.y *= l;
+        self.z *= l;
         self
     }
     pub fn dot(&self, b: &Vec3) -> f64 {
-        return self.x * b.x + self.y * b.y + self.z * b.z;
+        self.x * b.x + self.y * b.y + self.z * b.z
     }
     pub fn length(&self) -> f64 {
-        return self.x * self.x + self.y * self.y + self.z * self.z;
+        self.x * self.x + self.y * self.y + self.z * self.z
     }
 }
 
@@ -60,6 +59,18 @@ impl Mul<f64> for Vec3 {
     }
 }
 
+impl Index<usize> for Vec3 {
+    type Output = f64;
+    fn index(&self, i: usize) -> &f64 {
+        match i {
+            0 => &self.x,
+            1 => &self.y,
+            2 => &self.z,
+            _ => panic!("Vec3 index out of range: {}", i),
+        }
+    }
+}
+
 impl Rem for Vec3 {
     type Output = Vec3;
     fn rem(self, rhs: Self) -> Self {
@@ -72,16 +83,11 @@ impl Rem for Vec3 {
 }
 
 fn clamp(x: f64) -> f64 {
-    if x < 0.0 {
-        0.0
-    } else if x > 1.0 {
-        1.0
-    } else {
-        x
-    }
+    x.clamp(0.0, 1.0)
 }
 
-fn to_int(x: f64) -> u8 {
+// 0..1 の線形の値をガンマ 2.2 の 8bit にする
+pub fn to_int(x: f64) -> u8 {
     (clamp(x).powf(1.0 / 2.2) * 255.0 + 0.5) as u8
 }
 
@@ -90,15 +96,8 @@ fn save_ppm_file(filename: &str, image: Vec<Color>, width: usize, height: usize)
     let mut f = fs::File::create(filename).unwrap();
     
     writeln!(f, "P3\n{} {}\n{}", width, height, 255).unwrap();
-    for i in 0..(width * (height)) {
-        write!(
-            f,
-            "{} {} {} ",
-            to_int(image[i as usize].x),
-            to_int(image[i as usize].y),
-            to_int(image[i as usize].z)
-        )
-        .unwrap();
+    for c in image.iter().take(width * height) {
+        write!(f, "{} {} {} ", to_int(c.x), to_int(c.y), to_int(c.z)).unwrap();
     }
 }
 